@@ -0,0 +1,357 @@
+//! Authentication of clients.
+//!
+//! When an [`Authenticator`] is set on a server (see [`ServerBuilder::set_authenticator`]), the
+//! [authentication metadata](Authentication) of the clients is checked on SETUP, and on every
+//! request. The metadata is either the whole metadata of a frame, if the metadata MIME type of
+//! the connection is `message/x.rsocket.authentication.v0`, or an entry of the composite
+//! metadata, if it is `message/x.rsocket.composite-metadata.v0`.
+//!
+//! A client may authenticate once in its SETUP frame, which is rejected with `REJECTED_SETUP` if
+//! the authentication fails. Otherwise, every request of the client must carry authentication
+//! metadata, and is rejected with `REJECTED` if it does not, or if the authentication fails. The
+//! requests of a client authenticated on SETUP may still carry authentication metadata, which is
+//! checked as well.
+//!
+//! [`ServerBuilder::set_authenticator`]: crate::ServerBuilder::set_authenticator
+use crate::error::{Code, Error, Result};
+use crate::extension::{metadata_entry, Authentication};
+use crate::mimetype::WellKnownMimeType;
+use crate::payload::{Metadata, Payload};
+use crate::rsocket::with_first_payload;
+use crate::{Flux, Mono, RSocket, SetupFrame};
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// Checks the credentials of clients.
+///
+/// The authenticator is called synchronously for each authenticated frame, so any credentials
+/// that take time to check should be cached.
+///
+/// `Authenticator` is implemented for closures, so that simple authenticators can be written
+/// inline.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::Authentication;
+/// use binate::{Code, Error, Result, Server};
+///
+/// fn authenticate(auth: &Authentication) -> Result<()> {
+///     match auth {
+///         Authentication::Bearer(token) if token == "secret" => Ok(()),
+///         _ => Err(Error::with_code(Code::Rejected, "invalid token")),
+///     }
+/// }
+///
+/// let builder = Server::builder().set_authenticator(authenticate);
+/// ```
+pub trait Authenticator: Send + Sync {
+    /// Checks the given credentials, and returns an error if they are not valid.
+    fn authenticate(&self, auth: &Authentication) -> Result<()>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&Authentication) -> Result<()> + Send + Sync,
+{
+    fn authenticate(&self, auth: &Authentication) -> Result<()> {
+        (self)(auth)
+    }
+}
+
+/// Authenticates the given SETUP frame, and returns whether it carries authentication metadata.
+///
+/// The returned error has the code `REJECTED_SETUP`.
+pub(crate) fn authenticate_setup(
+    authenticator: &dyn Authenticator,
+    setup: &SetupFrame,
+) -> Result<bool> {
+    let mime_type = metadata_mime_type(setup);
+    match authentication(mime_type, setup.metadata()) {
+        Ok(Some(auth)) => authenticator
+            .authenticate(&auth)
+            .map(|_| true)
+            .map_err(|err| rejected(Code::RejectedSetup, err)),
+        Ok(None) => Ok(false),
+        Err(err) => Err(rejected(Code::RejectedSetup, err)),
+    }
+}
+
+/// Returns the metadata MIME type of the connection set up by the given SETUP frame.
+fn metadata_mime_type(setup: &SetupFrame) -> WellKnownMimeType {
+    setup
+        .metadata_mimetype()
+        .map(WellKnownMimeType::from)
+        .unwrap_or(WellKnownMimeType::UNPARSEABLE)
+}
+
+/// Returns the authentication in the given metadata, if any.
+fn authentication(
+    mime_type: WellKnownMimeType,
+    metadata: Option<&Bytes>,
+) -> Result<Option<Authentication>> {
+    let auth = metadata_entry(
+        mime_type,
+        metadata,
+        WellKnownMimeType::MESSAGE_X_RSOCKET_AUTHENTICATION_V0,
+    )?;
+    auth.as_ref().map(Authentication::decode).transpose()
+}
+
+/// Returns the given error with the given code, keeping its code if it already has it.
+fn rejected(code: Code, err: Error) -> Error {
+    if err.code() == Some(code) {
+        err
+    } else {
+        Error::with_code(code, err.to_string())
+    }
+}
+
+/// A responder that authenticates the requests before they reach the wrapped responder.
+pub(crate) struct Authenticated {
+    inner: Arc<dyn RSocket>,
+    authenticator: Arc<dyn Authenticator>,
+    mime_type: WellKnownMimeType,
+    /// Whether the connection was authenticated on SETUP.
+    setup_authenticated: bool,
+}
+
+impl Authenticated {
+    pub(crate) fn new(
+        inner: Box<dyn RSocket>,
+        authenticator: Arc<dyn Authenticator>,
+        setup: &SetupFrame,
+        setup_authenticated: bool,
+    ) -> Authenticated {
+        Authenticated {
+            inner: Arc::from(inner),
+            authenticator,
+            mime_type: metadata_mime_type(setup),
+            setup_authenticated,
+        }
+    }
+
+    fn authenticate(&self, metadata: Option<&Bytes>) -> Result<()> {
+        authenticate_request(
+            &*self.authenticator,
+            self.mime_type,
+            self.setup_authenticated,
+            metadata,
+        )
+    }
+}
+
+/// Authenticates a request with the given metadata, returning a `REJECTED` error on failure.
+fn authenticate_request(
+    authenticator: &dyn Authenticator,
+    mime_type: WellKnownMimeType,
+    setup_authenticated: bool,
+    metadata: Option<&Bytes>,
+) -> Result<()> {
+    match authentication(mime_type, metadata) {
+        Ok(Some(auth)) => authenticator
+            .authenticate(&auth)
+            .map_err(|err| rejected(Code::Rejected, err)),
+        Ok(None) if setup_authenticated => Ok(()),
+        Ok(None) => {
+            Err(Error::with_code(Code::Rejected, "authentication required"))
+        }
+        Err(err) => Err(rejected(Code::Rejected, err)),
+    }
+}
+
+impl RSocket for Authenticated {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        match self.authenticate(payload.metadata()) {
+            Ok(()) => self.inner.request_response(payload),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        match self.authenticate(payload.metadata()) {
+            Ok(()) => self.inner.request_stream(payload),
+            Err(err) => Box::pin(tokio_stream::once(Err(err))),
+        }
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        // The credentials are in the first payload, so the wrapped responder is only called
+        // once it has been authenticated.
+        let inner = self.inner.clone();
+        let authenticator = self.authenticator.clone();
+        let (mime_type, setup_authenticated) =
+            (self.mime_type, self.setup_authenticated);
+        with_first_payload(payloads, move |first, payloads| {
+            let result = authenticate_request(
+                &*authenticator,
+                mime_type,
+                setup_authenticated,
+                first.metadata(),
+            );
+            match result {
+                Ok(()) => inner.request_channel(payloads),
+                Err(err) => Box::pin(tokio_stream::once(Err(err))),
+            }
+        })
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        self.authenticate(payload.metadata())?;
+        self.inner.fire_and_forget(payload)
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        match self.authenticate(Some(&metadata)) {
+            Ok(()) => self.inner.metadata_push(metadata),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::CompositeMetadata;
+    use crate::rsocket::DummyRSocket;
+    use crate::test_helpers::*;
+
+    use bytes::{BufMut, BytesMut};
+    use tokio_stream::StreamExt;
+
+    fn check_password(auth: &Authentication) -> Result<()> {
+        match auth {
+            Authentication::Simple { password, .. } if password == "pass" => {
+                Ok(())
+            }
+            _ => Err(Error::with_code(Code::ApplicationError, "unauthorized")),
+        }
+    }
+
+    fn composite(password: &str) -> Bytes {
+        let mut metadata = CompositeMetadata::new();
+        metadata.push(
+            WellKnownMimeType::MESSAGE_X_RSOCKET_AUTHENTICATION_V0,
+            Authentication::simple("user", password).to_bytes(),
+        );
+        metadata.to_bytes()
+    }
+
+    /// Returns composite metadata whose entry names its MIME type rather than using its
+    /// identifier, as some implementations do.
+    fn composite_by_name(password: &str) -> Bytes {
+        let name = "message/x.rsocket.authentication.v0";
+        let auth = Authentication::simple("user", password).to_bytes();
+        let mut metadata = BytesMut::new();
+        metadata.put_u8(name.len() as u8 - 1);
+        metadata.put_slice(name.as_bytes());
+        metadata.put_uint(auth.len() as u64, 3);
+        metadata.put_slice(&auth);
+        metadata.freeze()
+    }
+
+    fn setup(metadata: Option<Bytes>) -> SetupFrame {
+        let mut setup = SetupFrame::builder()
+            .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0");
+        if let Some(metadata) = metadata {
+            setup = setup.set_metadata(metadata);
+        }
+        setup.build()
+    }
+
+    fn request(password: &str) -> Payload {
+        Payload::builder().set_metadata(composite(password)).build()
+    }
+
+    fn authenticated(setup_authenticated: bool) -> Authenticated {
+        Authenticated::new(
+            Box::new(EchoRSocket),
+            Arc::new(check_password),
+            &setup(None),
+            setup_authenticated,
+        )
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Arc<dyn Authenticator>>();
+        assert_sync::<Arc<dyn Authenticator>>();
+    }
+
+    #[test]
+    fn setup_authentication() {
+        let setup_with = |metadata| setup(Some(metadata));
+        assert!(!authenticate_setup(&check_password, &setup(None)).unwrap());
+        let authenticated = authenticate_setup(
+            &check_password,
+            &setup_with(composite("pass")),
+        );
+        assert!(authenticated.unwrap());
+        let authenticated = authenticate_setup(
+            &check_password,
+            &setup_with(composite_by_name("pass")),
+        );
+        assert!(authenticated.unwrap());
+
+        let err = authenticate_setup(
+            &check_password,
+            &setup_with(composite("wrong")),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedSetup));
+        let err = authenticate_setup(
+            &check_password,
+            &setup_with(Bytes::from_static(b"\x80")),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedSetup));
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let responder = authenticated(false);
+        assert!(responder.request_response(request("pass")).await.is_ok());
+        let err = responder.request_response(request("wrong")).await;
+        assert!(err.unwrap_err().is_rejected());
+        let err = responder.request_response(Payload::builder().build()).await;
+        assert!(err.unwrap_err().is_rejected());
+        assert!(responder.fire_and_forget(request("wrong")).is_err());
+        assert!(responder.metadata_push(composite("pass")).await.is_ok());
+
+        // Requests without credentials are accepted once authenticated on SETUP.
+        let responder = authenticated(true);
+        let payload = Payload::builder().build();
+        assert!(responder.request_response(payload).await.is_ok());
+        let err = responder.request_response(request("wrong")).await;
+        assert!(err.unwrap_err().is_rejected());
+    }
+
+    #[tokio::test]
+    async fn channel() {
+        let responder = authenticated(false);
+        let payloads =
+            vec![Ok(request("pass")), Ok(Payload::builder().build())];
+        let payloads =
+            responder.request_channel(Box::pin(tokio_stream::iter(payloads)));
+        assert_eq!(payloads.collect::<Vec<_>>().await.len(), 2);
+
+        let payloads = Box::pin(tokio_stream::once(Ok(request("wrong"))));
+        let mut payloads = responder.request_channel(payloads);
+        let err = payloads.next().await.unwrap().unwrap_err();
+        assert!(err.is_rejected());
+        assert!(payloads.next().await.is_none());
+
+        let responder = Authenticated::new(
+            Box::new(DummyRSocket),
+            Arc::new(check_password),
+            &setup(None),
+            true,
+        );
+        let payloads = Box::pin(tokio_stream::empty());
+        assert!(responder.request_channel(payloads).next().await.is_none());
+    }
+}
@@ -0,0 +1,922 @@
+#[cfg(any(feature = "json", feature = "cbor", feature = "msgpack"))]
+use crate::codec::{Codec, PayloadCodec};
+use crate::connection::{
+    ConnectionStatus, DuplexConnection, Prefetch, RSocketMachine,
+    ResumableConnection, Role,
+};
+use crate::consts::{
+    DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_KEEPALIVE_TIMEOUT,
+    DEFAULT_MAX_REASSEMBLED_SIZE, DEFAULT_RESUME_SESSION_DURATION, MIN_MTU,
+};
+use crate::error::{Code, Error, Kind, Result};
+use crate::frame::codec::SetupFrame;
+use crate::frame::{Frame, MAX_U31};
+use crate::layer::{self, RSocketLayer};
+use crate::lease::LeasePolicy;
+use crate::mimetype::{MimeType, DEFAULT_MIMETYPE};
+use crate::payload::Payload;
+use crate::resume::{InMemoryFramesStore, ResumableFramesStore};
+use crate::runtime;
+use crate::zipkin::{ZipkinLayer, ZipkinRequester};
+use crate::{Flux, Mono, RSocket};
+
+use bytes::Bytes;
+#[cfg(any(feature = "json", feature = "cbor", feature = "msgpack"))]
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+/// The time between attempts to resume a session.
+const RESUME_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connected RSocket client.
+///
+/// `Client` implements [`RSocket`], which sends requests to the server it connects to.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::prelude::*;
+/// use binate::transport::TcpConnection;
+/// use binate::{Client, RSocket};
+///
+/// # #[tokio::main]
+/// # async fn main() -> binate::Result<()> {
+/// let transport = TcpConnection::new("127.0.0.1:7878".parse().unwrap());
+/// let client = Client::builder()
+///     .set_data_mimetype("application/json")
+///     .connect(transport)
+///     .await?;
+/// let response = client
+///     .request_response(Payload::builder().set_data("ping").build())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    rsm: RSocketMachine,
+    requester: Arc<dyn RSocket>,
+    data_mime_type: MimeType,
+}
+
+impl Client {
+    /// Returns a [`ClientBuilder`] to configure and connect a client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Returns the data MIME type of the connection, as set up by the SETUP frame.
+    pub fn data_mime_type(&self) -> &MimeType {
+        &self.data_mime_type
+    }
+
+    /// Closes the underlying connection.
+    pub fn close(&self) {
+        self.rsm.close()
+    }
+}
+
+cfg_doc! {
+    #[any(feature = "json", feature = "cbor", feature = "msgpack")]
+
+    /// Typed requests, whose data is encoded and decoded by the [`Codec`] of the data MIME
+    /// type of the connection.
+    ///
+    /// The typed requests have no metadata. Requests with metadata are built from the data
+    /// encoded by [`Client::codec`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use binate::transport::TcpConnection;
+    /// use binate::Client;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> binate::Result<()> {
+    /// let transport = TcpConnection::new("127.0.0.1:7878".parse().unwrap());
+    /// let client = Client::builder()
+    ///     .set_data_mimetype("application/json")
+    ///     .connect(transport)
+    ///     .await?;
+    /// let sum: u64 = client.request_response_typed(&[1, 2, 3]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    impl Client {
+        /// Returns the codec of the data MIME type of the connection.
+        ///
+        /// An `INVALID` error is returned if no enabled codec handles the data MIME type.
+        pub fn codec(&self) -> Result<Codec> {
+            Codec::try_from_mime_type(&self.data_mime_type)
+        }
+
+        /// Sends a request-response request with the given value, and decodes the response.
+        ///
+        /// An `INVALID` error is returned if there is no codec for the data MIME type of the
+        /// connection, or if the response cannot be decoded.
+        pub fn request_response_typed<Req, Resp>(
+            &self,
+            request: &Req,
+        ) -> Mono<Result<Resp>>
+        where
+            Req: Serialize + ?Sized,
+            Resp: DeserializeOwned + Send + 'static,
+        {
+            let (codec, request) = match self.encode(request) {
+                Ok(encoded) => encoded,
+                Err(err) => return Box::pin(async move { Err(err) }),
+            };
+            let response = self.request_response(request);
+            Box::pin(async move { codec.decode_payload(&response.await?) })
+        }
+
+        /// Sends a request-stream request with the given value, and decodes the responses.
+        ///
+        /// An `INVALID` error is returned if there is no codec for the data MIME type of the
+        /// connection, or if a response cannot be decoded.
+        pub fn request_stream_typed<Req, Resp>(
+            &self,
+            request: &Req,
+        ) -> Flux<Result<Resp>>
+        where
+            Req: Serialize + ?Sized,
+            Resp: DeserializeOwned + Send + 'static,
+        {
+            let (codec, request) = match self.encode(request) {
+                Ok(encoded) => encoded,
+                Err(err) => return Box::pin(tokio_stream::once(Err(err))),
+            };
+            Box::pin(self.request_stream(request).map(move |response| {
+                codec.decode_payload(&response?)
+            }))
+        }
+
+        /// Sends a fire-and-forget request with the given value.
+        ///
+        /// An `INVALID` error is returned if there is no codec for the data MIME type of the
+        /// connection.
+        pub fn fire_and_forget_typed<Req>(&self, request: &Req) -> Result<()>
+        where
+            Req: Serialize + ?Sized,
+        {
+            let (_, request) = self.encode(request)?;
+            self.fire_and_forget(request)
+        }
+
+        fn encode<Req>(&self, request: &Req) -> Result<(Codec, Payload)>
+        where
+            Req: Serialize + ?Sized,
+        {
+            let codec = self.codec()?;
+            Ok((codec, codec.encode_payload(request)?))
+        }
+    }
+}
+
+impl RSocket for Client {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        self.requester.request_response(payload)
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        self.requester.request_stream(payload)
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        self.requester.request_channel(payloads)
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        self.requester.fire_and_forget(payload)
+    }
+
+    fn metadata_push(&self, metadata: Bytes) -> Mono<Result<()>> {
+        self.requester.metadata_push(metadata)
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client").finish()
+    }
+}
+
+/// A builder for configuring and connecting a [`Client`].
+pub struct ClientBuilder {
+    keepalive_interval: Duration,
+    keepalive_timeout: Duration,
+    metadata_mimetype: String,
+    data_mimetype: String,
+    setup_payload: Payload,
+    lease: bool,
+    lease_policy: Option<Arc<dyn LeasePolicy>>,
+    prefetch: Prefetch,
+    mtu: Option<usize>,
+    max_reassembled_size: usize,
+    resume_token: Option<Bytes>,
+    resume_transport: Option<Reconnect>,
+    resume_session_duration: Duration,
+    resume_store: Option<Arc<dyn ResumableFramesStore>>,
+    responder: Option<Box<dyn RSocket>>,
+    layers: Vec<Arc<dyn RSocketLayer>>,
+    zipkin: bool,
+}
+
+/// Creates the transports to resume the session over.
+type Reconnect = Arc<dyn Fn() -> Arc<dyn DuplexConnection> + Send + Sync>;
+
+impl ClientBuilder {
+    /// Creates a new `ClientBuilder` with the default configuration.
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            keepalive_timeout: DEFAULT_KEEPALIVE_TIMEOUT,
+            metadata_mimetype: DEFAULT_MIMETYPE.to_owned(),
+            data_mimetype: DEFAULT_MIMETYPE.to_owned(),
+            setup_payload: Payload::default(),
+            lease: false,
+            lease_policy: None,
+            prefetch: Prefetch::default(),
+            mtu: None,
+            max_reassembled_size: DEFAULT_MAX_REASSEMBLED_SIZE,
+            resume_token: None,
+            resume_transport: None,
+            resume_session_duration: DEFAULT_RESUME_SESSION_DURATION,
+            resume_store: None,
+            responder: None,
+            layers: Vec::new(),
+            zipkin: false,
+        }
+    }
+
+    /// Sets the time between KEEPALIVE frames that the client will send (defaults to 30
+    /// seconds).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given `interval` is less than 1 millisecond, or if it (in
+    /// milliseconds) is greater than `MAX_U31` (2,147,483,647).
+    pub fn set_keepalive_interval(mut self, interval: Duration) -> Self {
+        assert!(interval.as_millis() > 0);
+        assert!(interval.as_millis() <= MAX_U31 as u128);
+        self.keepalive_interval = interval;
+        self
+    }
+
+    /// Sets the time that the client will allow the server to not respond to a KEEPALIVE before
+    /// it is assumed to be dead (defaults to 60 seconds).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given `timeout` is less than 1 millisecond, or if it (in
+    /// milliseconds) is greater than `MAX_U31` (2,147,483,647).
+    pub fn set_keepalive_timeout(mut self, timeout: Duration) -> Self {
+        assert!(timeout.as_millis() > 0);
+        assert!(timeout.as_millis() <= MAX_U31 as u128);
+        self.keepalive_timeout = timeout;
+        self
+    }
+
+    /// Sets the MIME type for encoding the metadata.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the length of the given `mimetype` is greater than `255` bytes.
+    pub fn set_metadata_mimetype<T: Into<String>>(
+        mut self,
+        mimetype: T,
+    ) -> Self {
+        let mimetype = mimetype.into();
+        assert!(mimetype.len() <= 255);
+        self.metadata_mimetype = mimetype;
+        self
+    }
+
+    /// Sets the MIME type for encoding the data.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the length of the given `mimetype` is greater than `255` bytes.
+    pub fn set_data_mimetype<T: Into<String>>(mut self, mimetype: T) -> Self {
+        let mimetype = mimetype.into();
+        assert!(mimetype.len() <= 255);
+        self.data_mimetype = mimetype;
+        self
+    }
+
+    /// Sets the payload sent along with the SETUP frame.
+    pub fn set_setup_payload(mut self, payload: Payload) -> Self {
+        self.setup_payload = payload;
+        self
+    }
+
+    /// Requests the server to honor LEASE frames.
+    ///
+    /// Once leases are enabled, the client only sends requests while it holds a valid lease
+    /// from the server, and requests made without one fail with a `REJECTED` error. The server
+    /// can only send requests to the client if a lease policy is set as well.
+    pub fn set_lease(mut self) -> Self {
+        self.lease = true;
+        self
+    }
+
+    /// Enables leases, and sets the policy that issues leases to the server.
+    pub fn set_lease_policy<P>(mut self, policy: P) -> Self
+    where
+        P: LeasePolicy + 'static,
+    {
+        self.lease = true;
+        self.lease_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets the number of payloads to ask for when a stream starts (defaults to 256), and the
+    /// number of outstanding payloads under which more payloads are asked for as the payloads
+    /// are consumed (defaults to 64).
+    ///
+    /// This applies to the streams and channels that the client requests, as well as the
+    /// channels requested by the server, which are never sent more payloads than they ask for. A
+    /// `prefetch` of `2^31 - 1` asks for an unbounded number of payloads.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `prefetch` is 0 or greater than `2^31 - 1`, or if
+    /// `low_water_mark` is not less than `prefetch`.
+    pub fn set_prefetch(mut self, prefetch: u32, low_water_mark: u32) -> Self {
+        assert!(prefetch > 0 && prefetch <= MAX_U31);
+        assert!(low_water_mark < prefetch);
+        self.prefetch = Prefetch { prefetch, low_water_mark };
+        self
+    }
+
+    /// Sets the maximum length (in bytes) of the frames sent to the server, over which payloads
+    /// are split into fragments (by default payloads are never fragmented).
+    ///
+    /// The length counts the frame headers, but not the frame length prefix that some
+    /// transports put before each frame.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given `mtu` is less than 64.
+    pub fn set_mtu(mut self, mtu: usize) -> Self {
+        assert!(mtu >= MIN_MTU);
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Sets the maximum size (in bytes) of the payloads reassembled from the fragments received
+    /// from the server (defaults to 64 MiB).
+    ///
+    /// The connection is closed with a `CONNECTION_ERROR` if a fragmented payload exceeds this
+    /// size.
+    pub fn set_max_reassembled_size(mut self, max_size: usize) -> Self {
+        self.max_reassembled_size = max_size;
+        self
+    }
+
+    /// Sets the token that identifies this client when resuming the session.
+    ///
+    /// The token is only sent in the SETUP frame if a resume transport is set as well (see
+    /// [`set_resume_transport`](Self::set_resume_transport)), as the session cannot be resumed
+    /// otherwise.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the length of the given token is greater than 65,535 bytes.
+    pub fn set_resume_token(mut self, token: Bytes) -> Self {
+        assert!(token.len() <= 65_535);
+        self.resume_token = Some(token);
+        self
+    }
+
+    /// Sets the function that creates a new transport to resume the session over, once the
+    /// connection is lost.
+    ///
+    /// The session is resumable only if a resume token is set as well. Streams that are in
+    /// flight carry on after the session is resumed, with the frames lost in between sent again.
+    pub fn set_resume_transport<F, T>(mut self, transport: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: DuplexConnection + 'static,
+    {
+        self.resume_transport = Some(Arc::new(move || Arc::new(transport())));
+        self
+    }
+
+    /// Sets the time that the client keeps trying to resume the session after the connection is
+    /// lost (defaults to 120 seconds).
+    pub fn set_resume_session_duration(mut self, duration: Duration) -> Self {
+        self.resume_session_duration = duration;
+        self
+    }
+
+    /// Sets the store that keeps the frames sent on the session until the server receives them
+    /// (defaults to an [`InMemoryFramesStore`] that keeps 1 MiB of frames).
+    pub fn set_resume_store<S>(mut self, store: S) -> Self
+    where
+        S: ResumableFramesStore + 'static,
+    {
+        self.resume_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Sets the responder that handles requests sent from the server.
+    ///
+    /// Requests from the server are rejected if no responder is set.
+    pub fn set_responder(mut self, responder: Box<dyn RSocket>) -> Self {
+        self.responder = Some(responder);
+        self
+    }
+
+    /// Adds a layer that wraps the responder set by [`set_responder`](Self::set_responder).
+    ///
+    /// The layer added first is the outermost one, which sees the requests first.
+    pub fn add_layer<L>(mut self, layer: L) -> Self
+    where
+        L: RSocketLayer + 'static,
+    {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Propagates Zipkin traces across the connection.
+    ///
+    /// The requests of the client are sent as a [`ZipkinRequester`] sends them, and the
+    /// responder set by [`set_responder`](Self::set_responder) is wrapped in a [`ZipkinLayer`],
+    /// outside of the layers added by [`add_layer`](Self::add_layer). See the
+    /// [`zipkin`](crate::zipkin) module for details.
+    pub fn set_zipkin_tracing(mut self) -> Self {
+        self.zipkin = true;
+        self
+    }
+
+    /// Opens the given transport, sends the SETUP frame, and returns the connected client.
+    pub async fn connect<T>(self, transport: T) -> Result<Client>
+    where
+        T: DuplexConnection + 'static,
+    {
+        open(&transport).await?;
+
+        let setup = self.setup_frame();
+        let (rsm, frames) = match (&self.resume_token, self.resume_transport) {
+            (Some(token), Some(reconnect)) => {
+                let store = self.resume_store.unwrap_or_else(|| {
+                    Arc::new(InMemoryFramesStore::default())
+                });
+                let conn = ResumableConnection::new(token.clone(), store);
+                let frames = transport.receive();
+                conn.attach(Arc::new(transport), frames);
+                let duration = self.resume_session_duration;
+                runtime::spawn(resume(conn.clone(), reconnect, duration));
+
+                let frames = conn.receive();
+                let rsm = RSocketMachine::unstarted_resumable(
+                    Role::Client,
+                    conn,
+                    self.keepalive_interval,
+                    self.keepalive_timeout,
+                );
+                (rsm, frames)
+            }
+            _ => {
+                let frames = transport.receive();
+                let rsm = RSocketMachine::unstarted(
+                    Role::Client,
+                    transport,
+                    self.keepalive_interval,
+                    self.keepalive_timeout,
+                );
+                (rsm, frames)
+            }
+        };
+        rsm.set_prefetch(self.prefetch);
+        if let Some(mtu) = self.mtu {
+            rsm.set_mtu(mtu);
+        }
+        rsm.set_max_reassembled_size(self.max_reassembled_size);
+        if self.lease {
+            // Leases are issued once the SETUP frame is sent.
+            rsm.enable_lease(None);
+        }
+        rsm.start(frames);
+        if let Some(responder) = self.responder {
+            let mut responder = layer::layered(&self.layers, responder);
+            if self.zipkin {
+                responder = ZipkinLayer::new().layer(responder);
+            }
+            rsm.set_request_handler(responder).await;
+        }
+        rsm.send(Frame::Setup(setup)).await?;
+        if let Some(policy) = self.lease_policy {
+            rsm.issue_leases(policy);
+        }
+        let requester: Arc<dyn RSocket> = if self.zipkin {
+            Arc::new(ZipkinRequester::new(Box::new(rsm.clone())))
+        } else {
+            Arc::new(rsm.clone())
+        };
+        let data_mime_type = MimeType::from(self.data_mimetype);
+        Ok(Client { rsm, requester, data_mime_type })
+    }
+
+    fn setup_frame(&self) -> SetupFrame {
+        let mut setup = SetupFrame::builder()
+            .set_keepalive_interval(self.keepalive_interval.as_millis() as u32)
+            .set_keepalive_timeout(self.keepalive_timeout.as_millis() as u32)
+            .set_metadata_mimetype(self.metadata_mimetype.clone())
+            .set_data_mimetype(self.data_mimetype.clone());
+        if self.lease {
+            setup = setup.set_lease_flag();
+        }
+        // The session is only resumable if the client can reconnect to resume it.
+        if let (Some(token), Some(_)) =
+            (&self.resume_token, &self.resume_transport)
+        {
+            setup = setup.set_resume_token(token.clone());
+        }
+        if let Some(metadata) = &self.setup_payload.metadata {
+            setup = setup.set_metadata(metadata.clone());
+        }
+        if let Some(data) = &self.setup_payload.data {
+            setup = setup.set_data(data.clone());
+        }
+        setup.build()
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder::new()
+    }
+}
+
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("keepalive_interval", &self.keepalive_interval)
+            .field("keepalive_timeout", &self.keepalive_timeout)
+            .field("metadata_mimetype", &self.metadata_mimetype)
+            .field("data_mimetype", &self.data_mimetype)
+            .field("setup_payload", &self.setup_payload)
+            .field("lease", &self.lease)
+            .field("lease_policy", &self.lease_policy.is_some())
+            .field("prefetch", &self.prefetch)
+            .field("mtu", &self.mtu)
+            .field("max_reassembled_size", &self.max_reassembled_size)
+            .field("resume_token", &self.resume_token)
+            .field("resume_session_duration", &self.resume_session_duration)
+            .field("layers", &self.layers.len())
+            .field("zipkin", &self.zipkin)
+            .finish()
+    }
+}
+
+/// Opens the given transport, and waits until it is connected.
+async fn open<T>(transport: &T) -> Result<()>
+where
+    T: DuplexConnection + ?Sized,
+{
+    let mut status = transport.connection_status();
+    transport.connect();
+    while let Some(status) = status.next().await {
+        match status {
+            ConnectionStatus::Connected => return Ok(()),
+            ConnectionStatus::Closed => {
+                return Err(connect_error("connection was closed"))
+            }
+            ConnectionStatus::Error(e) => return Err(connect_error(e)),
+            _ => (),
+        }
+    }
+    Err(connect_error("connection was closed"))
+}
+
+/// Resumes the session every time the connection is lost, until either the session is closed,
+/// the server rejects the resumption, or the session cannot be resumed in `session_duration`.
+async fn resume(
+    conn: ResumableConnection,
+    reconnect: Reconnect,
+    session_duration: Duration,
+) {
+    while conn.disconnected().await {
+        let deadline = Instant::now() + session_duration;
+        loop {
+            let transport = reconnect();
+            let result = match open(&*transport).await {
+                Ok(()) => conn.resume(transport).await,
+                Err(err) => Err(err),
+            };
+            let err = match result {
+                Ok(()) => break,
+                Err(err) => err,
+            };
+            if conn.is_closed() {
+                return;
+            }
+            if err.code() == Some(Code::RejectedResume) {
+                conn.expire(err.to_string());
+                return;
+            }
+            debug!("failed to resume session: {}", err);
+            if Instant::now() + RESUME_RETRY_INTERVAL > deadline {
+                conn.expire("resume session expired");
+                return;
+            }
+            tokio::time::sleep(RESUME_RETRY_INTERVAL).await;
+        }
+    }
+}
+
+fn connect_error<E: Into<String>>(reason: E) -> Error {
+    Error::new(Kind::Io, Some(reason.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::lease::FixedLeasePolicy;
+    use crate::test_helpers::*;
+    use crate::transport::{LocalConnection, TcpConnection};
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Client>();
+        assert_sync::<Client>();
+        assert_send::<ClientBuilder>();
+    }
+
+    #[test]
+    fn max_mimetype_len() {
+        let mimetype = "a".repeat(255);
+        let builder = Client::builder()
+            .set_metadata_mimetype(mimetype.as_str())
+            .set_data_mimetype(mimetype.as_str());
+        assert_eq!(builder.metadata_mimetype, mimetype);
+        assert_eq!(builder.data_mimetype, mimetype);
+    }
+
+    #[test]
+    fn max_keepalive() {
+        let max = Duration::from_millis(MAX_U31 as u64);
+        let setup = Client::builder()
+            .set_keepalive_interval(max)
+            .set_keepalive_timeout(max)
+            .setup_frame();
+        assert_eq!(setup.keepalive_interval(), max);
+        assert_eq!(setup.keepalive_timeout(), max);
+    }
+
+    #[test]
+    #[should_panic]
+    fn keepalive_interval_too_long() {
+        Client::builder()
+            .set_keepalive_interval(Duration::from_millis(MAX_U31 as u64 + 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn keepalive_timeout_too_long() {
+        Client::builder()
+            .set_keepalive_timeout(Duration::from_millis(MAX_U31 as u64 + 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn metadata_mimetype_too_long() {
+        Client::builder().set_metadata_mimetype("a".repeat(256));
+    }
+
+    #[test]
+    #[should_panic]
+    fn data_mimetype_too_long() {
+        Client::builder().set_data_mimetype("a".repeat(256));
+    }
+
+    #[tokio::test]
+    async fn sends_setup_frame() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = server_conn.receive();
+        let _client = Client::builder()
+            .set_keepalive_interval(Duration::from_millis(500))
+            .set_keepalive_timeout(Duration::from_secs(5))
+            .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0")
+            .set_data_mimetype("application/json")
+            .set_setup_payload(
+                Payload::builder().set_metadata("m").set_data("d").build(),
+            )
+            .set_lease()
+            .set_resume_token(Bytes::from("token"))
+            .set_resume_transport(|| LocalConnection::pair().0)
+            .connect(client_conn)
+            .await
+            .unwrap();
+
+        let setup = match frames.next().await.unwrap() {
+            Frame::Setup(setup) => setup,
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        assert_eq!(setup.keepalive_interval(), Duration::from_millis(500));
+        assert_eq!(setup.keepalive_timeout(), Duration::from_secs(5));
+        assert_eq!(
+            setup.metadata_mimetype(),
+            Some("message/x.rsocket.composite-metadata.v0")
+        );
+        assert_eq!(setup.data_mimetype(), Some("application/json"));
+        assert!(setup.is_lease());
+        assert!(setup.is_resume());
+        assert_eq!(setup.resume_token().unwrap(), "token");
+        assert_eq!(setup.metadata().unwrap(), "m");
+        assert_eq!(setup.data().unwrap(), "d");
+    }
+
+    #[tokio::test]
+    async fn resume_token_without_transport() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = server_conn.receive();
+        let _client = Client::builder()
+            .set_resume_token(Bytes::from("token"))
+            .connect(client_conn)
+            .await
+            .unwrap();
+
+        let setup = match frames.next().await.unwrap() {
+            Frame::Setup(setup) => setup,
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        assert!(!setup.is_resume());
+        assert!(setup.resume_token().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sends_setup_frame_before_lease() {
+        // The frames are sent from different tasks, so the order is checked a number of times.
+        for _ in 0..50 {
+            let (client_conn, server_conn) = LocalConnection::pair();
+            let mut frames = server_conn.receive();
+            let policy = FixedLeasePolicy::new(Duration::from_secs(5), 1);
+            let _client = Client::builder()
+                .set_lease_policy(policy)
+                .set_responder(Box::new(EchoRSocket))
+                .connect(client_conn)
+                .await
+                .unwrap();
+
+            assert!(matches!(frames.next().await, Some(Frame::Setup(_))));
+            assert!(matches!(frames.next().await, Some(Frame::Lease(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_and_responder() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let client = Client::builder()
+            .set_responder(Box::new(EchoRSocket))
+            .connect(client_conn)
+            .await
+            .unwrap();
+
+        let server = RSocketMachine::new(
+            Role::Server,
+            server_conn,
+            DEFAULT_KEEPALIVE_INTERVAL,
+            DEFAULT_KEEPALIVE_TIMEOUT,
+        )
+        .await;
+        server.set_request_handler(Box::new(EchoRSocket)).await;
+
+        let ping = Payload::builder().set_data("ping").build();
+        assert_eq!(client.request_response(ping.clone()).await.unwrap(), ping);
+
+        // Server-initiated requests are served by the client's responder.
+        assert_eq!(server.request_response(ping.clone()).await.unwrap(), ping);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn typed_requests() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let client = Client::builder()
+            .set_data_mimetype("application/json")
+            .connect(client_conn)
+            .await
+            .unwrap();
+        let server = RSocketMachine::new(
+            Role::Server,
+            server_conn,
+            DEFAULT_KEEPALIVE_INTERVAL,
+            DEFAULT_KEEPALIVE_TIMEOUT,
+        )
+        .await;
+        server.set_request_handler(Box::new(EchoRSocket)).await;
+
+        assert_eq!(client.codec().unwrap(), Codec::Json);
+        let response: Vec<u32> =
+            client.request_response_typed(&[1, 2]).await.unwrap();
+        assert_eq!(response, [1, 2]);
+        let mut responses = client.request_stream_typed::<_, String>("ping");
+        assert_eq!(responses.next().await.unwrap().unwrap(), "ping");
+        assert!(client.fire_and_forget_typed("ping").is_ok());
+
+        // Responses of another type cannot be decoded.
+        let err = client.request_response_typed::<_, u32>("ping").await;
+        assert!(err.unwrap_err().is_invalid());
+    }
+
+    #[cfg(any(feature = "json", feature = "cbor", feature = "msgpack"))]
+    #[tokio::test]
+    async fn typed_requests_without_codec() {
+        let (client_conn, _server_conn) = LocalConnection::pair();
+        let client = Client::builder().connect(client_conn).await.unwrap();
+        assert_eq!(client.data_mime_type().as_str(), DEFAULT_MIMETYPE);
+        assert!(client.codec().unwrap_err().is_invalid());
+        let err = client.request_response_typed::<_, u32>(&1).await;
+        assert!(err.unwrap_err().is_invalid());
+        assert!(client.fire_and_forget_typed(&1).unwrap_err().is_invalid());
+    }
+
+    #[tokio::test]
+    async fn connect_error() {
+        let addr = crate::transport::TcpAcceptor::bind(
+            "127.0.0.1:0".parse().unwrap(),
+        )
+        .await
+        .unwrap()
+        .local_addr();
+        let result = Client::builder().connect(TcpConnection::new(addr)).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejected_setup() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = server_conn.receive();
+        let client = Client::builder().connect(client_conn).await.unwrap();
+        assert!(matches!(frames.next().await, Some(Frame::Setup(_))));
+
+        server_conn
+            .send_and_forget(Frame::Error(ErrorFrame::new(
+                0,
+                ErrorFrame::REJECTED_SETUP,
+                Some(Bytes::from("rejected")),
+            )))
+            .unwrap();
+        let payload = Payload::builder().set_data("ping").build();
+        assert!(client.request_response(payload).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resumes_session() {
+        let server = crate::Server::builder().set_resume().set_acceptor(
+            |_: &SetupFrame, _: Box<dyn RSocket>| {
+                Box::pin(async {
+                    Ok(Box::new(EchoRSocket) as Box<dyn RSocket>)
+                }) as Mono<Result<Box<dyn RSocket>>>
+            },
+        );
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let (reconnect_tx, mut reconnect_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            server.serve_connection(server_conn).await.unwrap();
+            while let Some(conn) = reconnect_rx.recv().await {
+                server.serve_connection(conn).await.unwrap();
+            }
+        });
+
+        let client = Client::builder()
+            .set_resume_token(Bytes::from("token"))
+            .set_resume_transport(move || {
+                let (client_conn, server_conn) = LocalConnection::pair();
+                reconnect_tx.send(server_conn).unwrap();
+                client_conn
+            })
+            .connect(client_conn)
+            .await
+            .unwrap();
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut responses = client.request_channel(Box::pin(
+            UnboundedReceiverStream::new(rx).map(Ok),
+        ));
+        let payload = |data| Payload::builder().set_data(data).build();
+        tx.send(payload("1")).unwrap();
+        assert_eq!(responses.next().await.unwrap().unwrap(), payload("1"));
+
+        // The payload sent while disconnected is replayed after resumption.
+        client.rsm.disconnect();
+        tx.send(payload("2")).unwrap();
+        assert_eq!(responses.next().await.unwrap().unwrap(), payload("2"));
+        tx.send(payload("3")).unwrap();
+        assert_eq!(responses.next().await.unwrap().unwrap(), payload("3"));
+    }
+}
@@ -0,0 +1,395 @@
+//! Typed payload codecs, which encode and decode the data of payloads with [serde].
+//!
+//! A [`PayloadCodec`] encodes values into the data of payloads, and decodes the data of payloads
+//! back into values. The codec of a connection is selected by its data MIME type with
+//! [`Codec::from_mime_type`], and each codec is enabled by a cargo feature:
+//!
+//! | Codec                | MIME type               | Feature   |
+//! |----------------------|-------------------------|-----------|
+//! | [`JsonCodec`]        | `application/json`      | `json`    |
+//! | [`CborCodec`]        | `application/cbor`      | `cbor`    |
+//! | [`MessagePackCodec`] | `application/x-msgpack` | `msgpack` |
+//!
+//! MessagePack is not a [well-known](crate::mimetype::WellKnownMimeType) MIME type, so it is
+//! written out in full wherever it is set.
+//!
+//! Requesters send typed requests through the helpers of [`Client`](crate::Client), such as
+//! [`request_response_typed`](crate::Client::request_response_typed), which use the data MIME
+//! type of the connection. Responders select the codec of each request from the
+//! [`ConnectionMimeTypes`](crate::extension::ConnectionMimeTypes) of the connection.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "json")]
+//! # fn main() -> binate::Result<()> {
+//! use binate::codec::{Codec, PayloadCodec};
+//! use binate::extension::ConnectionMimeTypes;
+//! use binate::SetupFrame;
+//!
+//! let setup = SetupFrame::builder().set_data_mimetype("application/json").build();
+//! let mime_types = ConnectionMimeTypes::from_setup(&setup);
+//!
+//! let request = Codec::Json.encode_payload(&vec![1, 2, 3])?;
+//! let codec = Codec::from_mime_type(mime_types.data_mime_type(&request)?).unwrap();
+//! let numbers: Vec<u32> = codec.decode_payload(&request)?;
+//! assert_eq!(numbers, [1, 2, 3]);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "json"))]
+//! # fn main() {}
+//! ```
+//!
+//! [serde]: https://serde.rs
+use crate::error::{Code, Error, Result};
+use crate::mimetype::MimeType;
+#[cfg(any(feature = "json", feature = "cbor"))]
+use crate::mimetype::WellKnownMimeType;
+use crate::payload::Payload;
+
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The MIME type of MessagePack data.
+pub const MSGPACK_MIME_TYPE: &str = "application/x-msgpack";
+
+/// A codec that encodes values into the data of payloads, and decodes them back.
+pub trait PayloadCodec {
+    /// Returns the data MIME type of the encoded values.
+    fn mime_type(&self) -> MimeType;
+
+    /// Encodes the given value.
+    ///
+    /// An `APPLICATION_ERROR` error is returned if the value cannot be encoded.
+    fn encode<T>(&self, value: &T) -> Result<Bytes>
+    where
+        T: Serialize + ?Sized;
+
+    /// Decodes a value from the given data.
+    ///
+    /// An `INVALID` error is returned if the data is malformed, or does not match the type of
+    /// the value.
+    fn decode<T>(&self, data: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned;
+
+    /// Encodes the given value into the data of a new payload.
+    ///
+    /// An `APPLICATION_ERROR` error is returned if the value cannot be encoded.
+    fn encode_payload<T>(&self, value: &T) -> Result<Payload>
+    where
+        T: Serialize + ?Sized,
+    {
+        let data = self.encode(value)?;
+        Ok(Payload::builder().set_data(data).build())
+    }
+
+    /// Decodes a value from the data of the given payload, which is empty if the payload has no
+    /// data.
+    ///
+    /// An `INVALID` error is returned if the data is malformed, or does not match the type of
+    /// the value.
+    fn decode_payload<T>(&self, payload: &Payload) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.decode(payload.data().map(|data| &data[..]).unwrap_or_default())
+    }
+}
+
+/// The codecs that are enabled by cargo features, which are selected by data MIME type.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// The [`JsonCodec`].
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    Json,
+    /// The [`CborCodec`].
+    #[cfg(feature = "cbor")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "cbor")))]
+    Cbor,
+    /// The [`MessagePackCodec`].
+    #[cfg(feature = "msgpack")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "msgpack")))]
+    MessagePack,
+}
+
+impl Codec {
+    /// Returns the codec of the given data MIME type, or `None` if no enabled codec handles
+    /// it.
+    pub fn from_mime_type<M>(mime_type: M) -> Option<Codec>
+    where
+        M: Into<MimeType>,
+    {
+        match mime_type.into() {
+            #[cfg(feature = "json")]
+            MimeType::WellKnown(WellKnownMimeType::APPLICATION_JSON) => {
+                Some(Codec::Json)
+            }
+            #[cfg(feature = "cbor")]
+            MimeType::WellKnown(WellKnownMimeType::APPLICATION_CBOR) => {
+                Some(Codec::Cbor)
+            }
+            #[cfg(feature = "msgpack")]
+            MimeType::Custom(mime_type) if mime_type == MSGPACK_MIME_TYPE => {
+                Some(Codec::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the codec of the given data MIME type.
+    ///
+    /// An `INVALID` error is returned if no enabled codec handles the MIME type.
+    pub(crate) fn try_from_mime_type(mime_type: &MimeType) -> Result<Codec> {
+        Codec::from_mime_type(mime_type.clone()).ok_or_else(|| {
+            Error::with_code(
+                Code::Invalid,
+                format!("no codec for data MIME type {}", mime_type),
+            )
+        })
+    }
+}
+
+impl PayloadCodec for Codec {
+    fn mime_type(&self) -> MimeType {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => JsonCodec.mime_type(),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.mime_type(),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.mime_type(),
+        }
+    }
+
+    fn encode<T>(&self, value: &T) -> Result<Bytes>
+    where
+        T: Serialize + ?Sized,
+    {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => JsonCodec.encode(value),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.encode(value),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.encode(value),
+        }
+    }
+
+    fn decode<T>(&self, data: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            #[cfg(feature = "json")]
+            Codec::Json => JsonCodec.decode(data),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => CborCodec.decode(data),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => MessagePackCodec.decode(data),
+        }
+    }
+}
+
+/// Returns the error for a value that cannot be encoded.
+fn encode_error<E: ToString>(err: E) -> Error {
+    Error::with_code(Code::ApplicationError, err.to_string())
+}
+
+/// Returns the error for data that cannot be decoded.
+fn decode_error<E: ToString>(err: E) -> Error {
+    Error::with_code(Code::Invalid, err.to_string())
+}
+
+cfg_doc! {
+    #[feature = "json"]
+
+    /// The JSON codec (`application/json`).
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct JsonCodec;
+
+    impl PayloadCodec for JsonCodec {
+        fn mime_type(&self) -> MimeType {
+            MimeType::WellKnown(WellKnownMimeType::APPLICATION_JSON)
+        }
+
+        fn encode<T>(&self, value: &T) -> Result<Bytes>
+        where
+            T: Serialize + ?Sized,
+        {
+            serde_json::to_vec(value).map(Bytes::from).map_err(encode_error)
+        }
+
+        fn decode<T>(&self, data: &[u8]) -> Result<T>
+        where
+            T: DeserializeOwned,
+        {
+            serde_json::from_slice(data).map_err(decode_error)
+        }
+    }
+}
+
+cfg_doc! {
+    #[feature = "cbor"]
+
+    /// The CBOR codec (`application/cbor`).
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct CborCodec;
+
+    impl PayloadCodec for CborCodec {
+        fn mime_type(&self) -> MimeType {
+            MimeType::WellKnown(WellKnownMimeType::APPLICATION_CBOR)
+        }
+
+        fn encode<T>(&self, value: &T) -> Result<Bytes>
+        where
+            T: Serialize + ?Sized,
+        {
+            let mut buf = Vec::new();
+            ciborium::ser::into_writer(value, &mut buf).map_err(encode_error)?;
+            Ok(Bytes::from(buf))
+        }
+
+        fn decode<T>(&self, data: &[u8]) -> Result<T>
+        where
+            T: DeserializeOwned,
+        {
+            ciborium::de::from_reader(data).map_err(decode_error)
+        }
+    }
+}
+
+cfg_doc! {
+    #[feature = "msgpack"]
+
+    /// The MessagePack codec (`application/x-msgpack`).
+    ///
+    /// Structs are encoded as maps with named fields, rather than as arrays, so that they can
+    /// be decoded by peers that do not share their definitions.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct MessagePackCodec;
+
+    impl PayloadCodec for MessagePackCodec {
+        fn mime_type(&self) -> MimeType {
+            MimeType::Custom(MSGPACK_MIME_TYPE.to_owned())
+        }
+
+        fn encode<T>(&self, value: &T) -> Result<Bytes>
+        where
+            T: Serialize + ?Sized,
+        {
+            rmp_serde::to_vec_named(value)
+                .map(Bytes::from)
+                .map_err(encode_error)
+        }
+
+        fn decode<T>(&self, data: &[u8]) -> Result<T>
+        where
+            T: DeserializeOwned,
+        {
+            rmp_serde::from_slice(data).map_err(decode_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mimetype::WellKnownMimeType;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Greeting {
+        name: String,
+        tags: Vec<String>,
+        scores: HashMap<String, u32>,
+    }
+
+    fn greeting() -> Greeting {
+        Greeting {
+            name: "binate".to_owned(),
+            tags: vec!["rsocket".to_owned()],
+            scores: vec![("a".to_owned(), 1)].into_iter().collect(),
+        }
+    }
+
+    fn codecs() -> Vec<Codec> {
+        vec![
+            #[cfg(feature = "json")]
+            Codec::Json,
+            #[cfg(feature = "cbor")]
+            Codec::Cbor,
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack,
+        ]
+    }
+
+    #[test]
+    fn encode_decode() {
+        for codec in codecs() {
+            let payload = codec.encode_payload(&greeting()).unwrap();
+            assert!(!payload.has_metadata());
+            let decoded: Greeting = codec.decode_payload(&payload).unwrap();
+            assert_eq!(decoded, greeting());
+        }
+    }
+
+    #[test]
+    fn decode_malformed() {
+        for codec in codecs() {
+            let data = codec.encode(&vec![1, 2, 3]).unwrap();
+            let err = codec.decode::<Greeting>(&data).unwrap_err();
+            assert!(err.is_invalid(), "{:?}", codec);
+            let err = codec.decode_payload::<Greeting>(&Payload::default());
+            assert!(err.unwrap_err().is_invalid(), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn from_mime_type() {
+        for codec in codecs() {
+            assert_eq!(Codec::from_mime_type(codec.mime_type()), Some(codec));
+        }
+        assert_eq!(
+            Codec::from_mime_type(WellKnownMimeType::APPLICATION_XML),
+            None
+        );
+        assert_eq!(Codec::from_mime_type("application/x-custom"), None);
+        let err = Codec::try_from_mime_type(&MimeType::from("text/plain"));
+        assert!(err.unwrap_err().is_invalid());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let data = JsonCodec.encode(&greeting()).unwrap();
+        assert_eq!(
+            data,
+            r#"{"name":"binate","tags":["rsocket"],"scores":{"a":1}}"#
+        );
+        assert_eq!(
+            Codec::from_mime_type("application/json"),
+            Some(Codec::Json)
+        );
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_named_fields() {
+        #[derive(Serialize)]
+        struct Point {
+            x: u8,
+        }
+
+        let data = MessagePackCodec.encode(&Point { x: 1 }).unwrap();
+        assert_eq!(data, Bytes::from_static(b"\x81\xA1x\x01"));
+        assert_eq!(
+            Codec::from_mime_type(MSGPACK_MIME_TYPE),
+            Some(Codec::MessagePack)
+        );
+    }
+}
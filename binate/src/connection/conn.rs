@@ -50,13 +50,20 @@ pub enum ConnectionStatus {
 }
 
 /// Represents a server that accepts connections and turns them into `DuplexConnection`.
-pub(crate) trait ConnectionAcceptor {
-    /// Allocate required resources and begin listening for new connections.
+//
+// This trait is public but not exported, so that it can bound the public APIs that serve any of
+// the acceptors, while still leaving the trait itself an implementation detail.
+pub trait ConnectionAcceptor {
+    /// The type of connections accepted by this acceptor.
+    type Connection: DuplexConnection;
+
+    /// Allocate required resources and begin listening for new connections, calling `on_accept`
+    /// for every connection accepted.
     ///
     /// This can only be called once.
     fn start<F>(&self, on_accept: F)
     where
-        F: FnOnce(&dyn DuplexConnection);
+        F: FnMut(Self::Connection) + Send + 'static;
 
     /// Stop listening for new connections.
     ///
@@ -23,8 +23,29 @@ impl RequestCounter {
     }
 
     /// Adds permits to this counter.
+    ///
+    /// The count saturates at `2^31 - 1`, which stands for an unbounded number of permits.
     pub fn add(&self, n: u32) {
-        self.0.fetch_add(n as i32, Ordering::SeqCst);
+        let _ =
+            self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                let sum = count as i64 + (n & COUNTER_MASK) as i64;
+                Some(sum.min(COUNTER_MASK as i64) as i32)
+            });
+    }
+
+    /// Takes a permit from this counter, returning false if there is none left.
+    ///
+    /// An unbounded counter (see [`add`](RequestCounter::add)) never runs out of permits.
+    pub fn try_acquire(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                match count {
+                    count if count == COUNTER_MASK as i32 => Some(count),
+                    count if count > 0 => Some(count - 1),
+                    _ => None,
+                }
+            })
+            .is_ok()
     }
 
     /// Returns the count this counter stores.
@@ -64,6 +85,26 @@ mod tests {
         assert_eq!(counter.load(), 50);
     }
 
+    #[test]
+    fn add_saturates() {
+        let counter = RequestCounter::new(COUNTER_MASK - 1);
+        counter.add(COUNTER_MASK);
+        assert_eq!(counter.load(), COUNTER_MASK as i32);
+    }
+
+    #[test]
+    fn try_acquire() {
+        let counter = RequestCounter::new(1);
+        assert!(counter.try_acquire());
+        assert!(!counter.try_acquire());
+        assert_eq!(counter.load(), 0);
+
+        // Unbounded counters are never decremented.
+        counter.add(COUNTER_MASK);
+        assert!(counter.try_acquire());
+        assert_eq!(counter.load(), COUNTER_MASK as i32);
+    }
+
     #[test]
     fn is_zero() {
         let counter = RequestCounter::new(1);
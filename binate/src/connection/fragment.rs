@@ -0,0 +1,705 @@
+use crate::connection::buf::BufList;
+use crate::consts::MIN_MTU;
+use crate::error::{Code, Error, Kind, Result};
+use crate::frame::codec::*;
+use crate::frame::{Encode, Flags, Frame};
+use crate::payload::Payload;
+
+use bytes::{Buf, Bytes};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// The length of the stream ID and flags that every frame starts with.
+const HEADER_LEN: usize = 6;
+
+/// The length of the initial request N of REQUEST_STREAM and REQUEST_CHANNEL frames.
+const REQUEST_N_LEN: usize = 4;
+
+/// The length of the metadata length field that precedes the metadata.
+const METADATA_LEN_LEN: usize = 3;
+
+/// Splits the payloads of the frames sent into fragments that fit in the MTU of the connection.
+///
+/// Each fragment is a whole frame, whose encoded length (headers included) is at most the MTU.
+/// The first fragment is the original frame with the FOLLOWS flag set, and the rest are PAYLOAD
+/// frames on the same stream. Metadata is sent before data.
+pub(crate) struct Fragmenter {
+    mtu: Mutex<Option<usize>>,
+}
+
+/// Reassembles the fragments of the payloads received, keyed by their stream IDs.
+///
+/// A fragmented payload starts with a request or PAYLOAD frame that has the FOLLOWS flag set,
+/// followed by PAYLOAD frames on the same stream, until one without the FOLLOWS flag.
+pub(crate) struct Reassembler {
+    max_size: Mutex<usize>,
+    streams: Mutex<HashMap<u32, Fragments>>,
+}
+
+/// The fragments received so far on a stream.
+struct Fragments {
+    head: Head,
+    metadata: BufList<Bytes>,
+    data: BufList<Bytes>,
+}
+
+/// The frame that the first fragment comes with.
+#[derive(Clone, Copy)]
+enum Head {
+    RequestResponse,
+    RequestFnf,
+    RequestStream { initial_request_n: u32 },
+    RequestChannel { initial_request_n: u32, complete: bool },
+    Payload { next: bool },
+}
+
+impl Fragmenter {
+    /// Creates a new `Fragmenter`, which does not fragment any frames until the MTU is set.
+    pub(crate) fn new() -> Fragmenter {
+        Fragmenter { mtu: Mutex::new(None) }
+    }
+
+    /// Sets the maximum length (in bytes) of the frames sent.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `mtu` is less than `MIN_MTU`.
+    pub(crate) fn set_mtu(&self, mtu: usize) {
+        assert!(mtu >= MIN_MTU);
+        *self.mtu.lock().unwrap() = Some(mtu);
+    }
+
+    /// Splits the given frame into fragments that fit in the MTU, or returns the frame as it is
+    /// if it fits already, or cannot be fragmented.
+    pub(crate) fn fragment(&self, frame: Frame) -> Vec<Frame> {
+        let mtu = match *self.mtu.lock().unwrap() {
+            Some(mtu) if frame.len() > mtu => mtu,
+            _ => return vec![frame],
+        };
+        let (stream_id, head, complete, payload) = match frame {
+            Frame::RequestResponse(frame) => (
+                frame.stream_id(),
+                Head::RequestResponse,
+                false,
+                frame.payload(),
+            ),
+            Frame::RequestFnf(frame) => {
+                (frame.stream_id(), Head::RequestFnf, false, frame.payload())
+            }
+            Frame::RequestStream(frame) => {
+                let head = Head::RequestStream {
+                    initial_request_n: frame.initial_request_n(),
+                };
+                (frame.stream_id(), head, false, frame.payload())
+            }
+            Frame::RequestChannel(frame) => {
+                let head = Head::RequestChannel {
+                    initial_request_n: frame.initial_request_n(),
+                    complete: false,
+                };
+                (frame.stream_id(), head, frame.is_complete(), frame.payload())
+            }
+            Frame::Payload(frame) => {
+                let head = Head::Payload { next: frame.is_next() };
+                (frame.stream_id(), head, frame.is_complete(), frame.payload())
+            }
+            frame => return vec![frame],
+        };
+
+        let (mut metadata, data) = payload.split();
+        let mut data = data.unwrap_or_default();
+        let mut fragments = Vec::new();
+        loop {
+            let mut budget = mtu - HEADER_LEN;
+            if fragments.is_empty() && head.has_request_n() {
+                budget -= REQUEST_N_LEN;
+            }
+            let mut fragment_metadata = None;
+            if let Some(mut rest) = metadata.take() {
+                budget -= METADATA_LEN_LEN;
+                let len = budget.min(rest.len());
+                fragment_metadata = Some(rest.split_to(len));
+                budget -= len;
+                if !rest.is_empty() {
+                    metadata = Some(rest);
+                }
+            }
+            let fragment_data = data.split_to(budget.min(data.len()));
+            let payload = Payload::new(
+                fragment_metadata,
+                Some(fragment_data).filter(|data| !data.is_empty()),
+            );
+
+            let follows = metadata.is_some() || !data.is_empty();
+            let frame = if fragments.is_empty() {
+                head.first_fragment(stream_id, payload)
+            } else {
+                let mut flags = Flags::NEXT;
+                if follows {
+                    flags |= Flags::FOLLOWS;
+                } else if complete {
+                    flags |= Flags::COMPLETE;
+                }
+                Frame::Payload(PayloadFrame::new(stream_id, flags, payload))
+            };
+            fragments.push(frame);
+            if !follows {
+                return fragments;
+            }
+        }
+    }
+}
+
+impl Head {
+    fn has_request_n(self) -> bool {
+        matches!(
+            self,
+            Head::RequestStream { .. } | Head::RequestChannel { .. }
+        )
+    }
+
+    /// Builds the first fragment of a payload, which has the FOLLOWS flag set.
+    fn first_fragment(self, stream_id: u32, payload: Payload) -> Frame {
+        match self {
+            Head::RequestResponse => Frame::RequestResponse(
+                RequestResponseFrame::new(stream_id, true, payload),
+            ),
+            Head::RequestFnf => Frame::RequestFnf(RequestFnfFrame::new(
+                stream_id, true, payload,
+            )),
+            Head::RequestStream { initial_request_n } => {
+                Frame::RequestStream(RequestStreamFrame::new(
+                    stream_id,
+                    true,
+                    initial_request_n,
+                    payload,
+                ))
+            }
+            Head::RequestChannel { initial_request_n, complete } => {
+                Frame::RequestChannel(RequestChannelFrame::new(
+                    stream_id,
+                    true,
+                    complete,
+                    initial_request_n,
+                    payload,
+                ))
+            }
+            Head::Payload { next } => {
+                let mut flags = Flags::FOLLOWS;
+                if next {
+                    flags |= Flags::NEXT;
+                }
+                Frame::Payload(PayloadFrame::new(stream_id, flags, payload))
+            }
+        }
+    }
+}
+
+impl Reassembler {
+    /// Creates a new `Reassembler`, which rejects the payloads larger than `max_size` bytes.
+    pub(crate) fn new(max_size: usize) -> Reassembler {
+        Reassembler {
+            max_size: Mutex::new(max_size),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum size (in bytes) of the payloads reassembled.
+    pub(crate) fn set_max_size(&self, max_size: usize) {
+        *self.max_size.lock().unwrap() = max_size;
+    }
+
+    /// Takes a frame received, and returns the frame with the whole payload once its last
+    /// fragment is received.
+    ///
+    /// Frames that are not fragments are returned as they are. A `CONNECTION_ERROR` is returned
+    /// if the payload exceeds the maximum size, or a frame other than PAYLOAD interrupts the
+    /// fragments of a stream.
+    pub(crate) fn reassemble(&self, frame: Frame) -> Result<Option<Frame>> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream_id = match stream_id(&frame) {
+            Some(stream_id) => stream_id,
+            None => return Ok(Some(frame)),
+        };
+
+        if let Some(fragments) = streams.get_mut(&stream_id) {
+            let frame = match frame {
+                Frame::Payload(frame) => frame,
+                // The stream is terminated before the payload is complete.
+                Frame::Cancel(_) | Frame::Error(_) => {
+                    streams.remove(&stream_id);
+                    return Ok(Some(frame));
+                }
+                // Demand in the other direction of a channel.
+                Frame::RequestN(_) => return Ok(Some(frame)),
+                frame => {
+                    return Err(Error::unexpected_frame(
+                        Code::ConnectionError,
+                        format_args!(
+                            "a PAYLOAD fragment on stream {}",
+                            stream_id
+                        ),
+                        &frame,
+                    ))
+                }
+            };
+            let follows = frame.is_follows();
+            let complete = frame.is_complete();
+            let next = frame.is_next();
+            fragments.push(frame.payload());
+            self.check_size(stream_id, fragments)?;
+            if follows {
+                return Ok(None);
+            }
+            let fragments = streams.remove(&stream_id).unwrap();
+            return Ok(Some(fragments.finish(stream_id, complete, next)));
+        }
+
+        let (head, payload) = match frame {
+            Frame::RequestResponse(frame) if frame.is_follows() => {
+                (Head::RequestResponse, frame.payload())
+            }
+            Frame::RequestFnf(frame) if frame.is_follows() => {
+                (Head::RequestFnf, frame.payload())
+            }
+            Frame::RequestStream(frame) if frame.is_follows() => {
+                let initial_request_n = frame.initial_request_n();
+                (Head::RequestStream { initial_request_n }, frame.payload())
+            }
+            Frame::RequestChannel(frame) if frame.is_follows() => {
+                let head = Head::RequestChannel {
+                    initial_request_n: frame.initial_request_n(),
+                    complete: frame.is_complete(),
+                };
+                (head, frame.payload())
+            }
+            Frame::Payload(frame) if frame.is_follows() => {
+                (Head::Payload { next: frame.is_next() }, frame.payload())
+            }
+            frame => return Ok(Some(frame)),
+        };
+        let mut fragments =
+            Fragments { head, metadata: BufList::new(), data: BufList::new() };
+        fragments.push(payload);
+        self.check_size(stream_id, &fragments)?;
+        streams.insert(stream_id, fragments);
+        Ok(None)
+    }
+
+    /// Drops the fragments received on the given stream, once this side terminates it.
+    pub(crate) fn remove(&self, stream_id: u32) {
+        self.streams.lock().unwrap().remove(&stream_id);
+    }
+
+    /// Drops the fragments received on every stream, once the connection is terminated.
+    pub(crate) fn clear(&self) {
+        self.streams.lock().unwrap().clear();
+    }
+
+    /// Returns the number of streams whose payloads are being reassembled.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    fn check_size(&self, stream_id: u32, fragments: &Fragments) -> Result<()> {
+        let max_size = *self.max_size.lock().unwrap();
+        let size = fragments.metadata.remaining() + fragments.data.remaining();
+        if size > max_size {
+            return Err(Error::new(
+                Kind::ConnectionError,
+                Some(format!(
+                    "payload on stream {} exceeds the maximum size {}",
+                    stream_id, max_size
+                )),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Fragments {
+    fn push(&mut self, payload: Payload) {
+        let (metadata, data) = payload.split();
+        if let Some(metadata) = metadata.filter(|b| !b.is_empty()) {
+            self.metadata.push(metadata);
+        }
+        if let Some(data) = data.filter(|b| !b.is_empty()) {
+            self.data.push(data);
+        }
+    }
+
+    /// Builds the frame with the whole payload, where `complete` and `next` are the flags of
+    /// the last fragment.
+    fn finish(mut self, stream_id: u32, complete: bool, next: bool) -> Frame {
+        let metadata = match self.metadata.remaining() {
+            0 => None,
+            len => Some(self.metadata.copy_to_bytes(len)),
+        };
+        let data = match self.data.remaining() {
+            0 => None,
+            len => Some(self.data.copy_to_bytes(len)),
+        };
+        let payload = Payload::new(metadata, data);
+        match self.head {
+            Head::RequestResponse => Frame::RequestResponse(
+                RequestResponseFrame::new(stream_id, false, payload),
+            ),
+            Head::RequestFnf => Frame::RequestFnf(RequestFnfFrame::new(
+                stream_id, false, payload,
+            )),
+            Head::RequestStream { initial_request_n } => {
+                Frame::RequestStream(RequestStreamFrame::new(
+                    stream_id,
+                    false,
+                    initial_request_n,
+                    payload,
+                ))
+            }
+            Head::RequestChannel { initial_request_n, complete: first } => {
+                Frame::RequestChannel(RequestChannelFrame::new(
+                    stream_id,
+                    false,
+                    first || complete,
+                    initial_request_n,
+                    payload,
+                ))
+            }
+            Head::Payload { next: first } => {
+                let mut flags = Flags::empty();
+                if first || next {
+                    flags |= Flags::NEXT;
+                }
+                if complete {
+                    flags |= Flags::COMPLETE;
+                }
+                Frame::Payload(PayloadFrame::new(stream_id, flags, payload))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Fragmenter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fragmenter")
+            .field("mtu", &*self.mtu.lock().unwrap())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Reassembler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reassembler")
+            .field("max_size", &*self.max_size.lock().unwrap())
+            .finish()
+    }
+}
+
+/// Returns the stream ID of a frame that may take part in the fragments of a stream.
+fn stream_id(frame: &Frame) -> Option<u32> {
+    match frame {
+        Frame::RequestResponse(frame) => Some(frame.stream_id()),
+        Frame::RequestFnf(frame) => Some(frame.stream_id()),
+        Frame::RequestStream(frame) => Some(frame.stream_id()),
+        Frame::RequestChannel(frame) => Some(frame.stream_id()),
+        Frame::RequestN(frame) => Some(frame.stream_id()),
+        Frame::Payload(frame) => Some(frame.stream_id()),
+        Frame::Cancel(frame) => Some(frame.stream_id()),
+        Frame::Error(frame) if frame.stream_id() != 0 => {
+            Some(frame.stream_id())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn payload(metadata: Option<&'static str>, data: &'static str) -> Payload {
+        Payload::new(metadata.map(Bytes::from), Some(Bytes::from(data)))
+    }
+
+    fn fragment(stream_id: u32, follows: bool, data: &'static str) -> Frame {
+        let mut flags = Flags::NEXT;
+        if follows {
+            flags |= Flags::FOLLOWS;
+        }
+        Frame::Payload(PayloadFrame::new(
+            stream_id,
+            flags,
+            payload(None, data),
+        ))
+    }
+
+    fn bytes(len: usize) -> Bytes {
+        (0..len).map(|i| i as u8).collect::<Vec<_>>().into()
+    }
+
+    /// Fragments the given frame, and checks that the fragments fit in the MTU and reassemble
+    /// into the original frame.
+    fn round_trip(mtu: usize, frame: Frame) -> Vec<Frame> {
+        let fragmenter = Fragmenter::new();
+        fragmenter.set_mtu(mtu);
+        let fragments = fragmenter.fragment(frame.clone());
+        let reassembler = Reassembler::new(usize::MAX);
+        let mut reassembled = None;
+        for (i, fragment) in fragments.iter().enumerate() {
+            assert!(fragment.len() <= mtu, "{} > {}", fragment.len(), mtu);
+            reassembled = reassembler.reassemble(fragment.clone()).unwrap();
+            assert_eq!(reassembled.is_some(), i + 1 == fragments.len());
+        }
+        assert_eq!(reassembled, Some(frame));
+        fragments
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Fragmenter>();
+        assert_sync::<Fragmenter>();
+        assert_send::<Reassembler>();
+        assert_sync::<Reassembler>();
+    }
+
+    #[test]
+    fn fragment_fits() {
+        let fragmenter = Fragmenter::new();
+        let frame = Frame::RequestFnf(RequestFnfFrame::new(
+            1,
+            false,
+            Payload::new(None, Some(bytes(1000))),
+        ));
+        assert_eq!(fragmenter.fragment(frame.clone()), vec![frame.clone()]);
+
+        fragmenter.set_mtu(1024);
+        assert_eq!(fragmenter.fragment(frame.clone()), vec![frame]);
+    }
+
+    #[test]
+    fn fragment_request() {
+        let frame = Frame::RequestStream(RequestStreamFrame::new(
+            1,
+            false,
+            42,
+            Payload::new(Some(bytes(100)), Some(bytes(200))),
+        ));
+        let fragments = round_trip(64, frame);
+        // 100 + 200 bytes of payload, with 51 bytes in the first fragment, 55 bytes in the
+        // next one with metadata, and 58 bytes in the rest.
+        assert_eq!(fragments.len(), 6);
+        match &fragments[0] {
+            Frame::RequestStream(frame) => {
+                assert!(frame.is_follows());
+                assert_eq!(frame.initial_request_n(), 42);
+                assert_eq!(frame.len(), 64);
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+        for fragment in &fragments[1..] {
+            assert!(matches!(fragment, Frame::Payload(_)));
+        }
+    }
+
+    #[test]
+    fn fragment_all_frame_types() {
+        for mtu in [64, 65, 100, 127] {
+            let payload = || Payload::new(Some(bytes(150)), Some(bytes(250)));
+            round_trip(
+                mtu,
+                Frame::RequestResponse(RequestResponseFrame::new(
+                    1,
+                    false,
+                    payload(),
+                )),
+            );
+            round_trip(
+                mtu,
+                Frame::RequestFnf(RequestFnfFrame::new(1, false, payload())),
+            );
+            round_trip(
+                mtu,
+                Frame::RequestChannel(RequestChannelFrame::new(
+                    1,
+                    false,
+                    true,
+                    7,
+                    payload(),
+                )),
+            );
+            round_trip(
+                mtu,
+                Frame::Payload(PayloadFrame::new(
+                    1,
+                    Flags::NEXT | Flags::COMPLETE,
+                    payload(),
+                )),
+            );
+            round_trip(
+                mtu,
+                Frame::Payload(PayloadFrame::new(
+                    1,
+                    Flags::NEXT,
+                    Payload::new(None, Some(bytes(250))),
+                )),
+            );
+            round_trip(
+                mtu,
+                Frame::Payload(PayloadFrame::new(
+                    1,
+                    Flags::NEXT,
+                    Payload::new(Some(bytes(250)), None),
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn fragment_complete_on_last() {
+        let frame = Frame::Payload(PayloadFrame::new(
+            1,
+            Flags::NEXT | Flags::COMPLETE,
+            Payload::new(None, Some(bytes(100))),
+        ));
+        let fragments = round_trip(64, frame);
+        let (last, rest) = fragments.split_last().unwrap();
+        for fragment in rest {
+            match fragment {
+                Frame::Payload(frame) => {
+                    assert!(frame.is_follows() && !frame.is_complete())
+                }
+                frame => panic!("unexpected frame: {:?}", frame),
+            }
+        }
+        match last {
+            Frame::Payload(frame) => {
+                assert!(!frame.is_follows() && frame.is_complete())
+            }
+            frame => panic!("unexpected frame: {:?}", frame),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn mtu_too_small() {
+        Fragmenter::new().set_mtu(MIN_MTU - 1);
+    }
+
+    #[test]
+    fn unfragmented() {
+        let reassembler = Reassembler::new(100);
+        let frame = fragment(1, false, "data");
+        assert_eq!(
+            reassembler.reassemble(frame.clone()).unwrap(),
+            Some(frame)
+        );
+    }
+
+    #[test]
+    fn request() {
+        let reassembler = Reassembler::new(100);
+        let first = Frame::RequestStream(RequestStreamFrame::new(
+            1,
+            true,
+            5,
+            payload(Some("meta"), "hello"),
+        ));
+        assert_eq!(reassembler.reassemble(first).unwrap(), None);
+        // Fragments of other streams are reassembled independently.
+        assert_eq!(
+            reassembler.reassemble(fragment(3, true, "a")).unwrap(),
+            None
+        );
+        assert_eq!(
+            reassembler.reassemble(fragment(1, true, " ")).unwrap(),
+            None
+        );
+        // Demand in the other direction passes through.
+        let request_n = Frame::RequestN(RequestNFrame::new(1, 1));
+        assert_eq!(
+            reassembler.reassemble(request_n.clone()).unwrap(),
+            Some(request_n)
+        );
+
+        assert_eq!(
+            reassembler.reassemble(fragment(1, false, "world")).unwrap(),
+            Some(Frame::RequestStream(RequestStreamFrame::new(
+                1,
+                false,
+                5,
+                payload(Some("meta"), "hello world"),
+            )))
+        );
+        assert_eq!(
+            reassembler.reassemble(fragment(3, false, "b")).unwrap(),
+            Some(fragment(3, false, "ab"))
+        );
+    }
+
+    #[test]
+    fn payload_flags() {
+        let reassembler = Reassembler::new(100);
+        reassembler.reassemble(fragment(1, true, "a")).unwrap();
+        let last = Frame::Payload(PayloadFrame::new(
+            1,
+            Flags::COMPLETE,
+            payload(None, "b"),
+        ));
+        assert_eq!(
+            reassembler.reassemble(last).unwrap(),
+            Some(Frame::Payload(PayloadFrame::new(
+                1,
+                Flags::NEXT | Flags::COMPLETE,
+                payload(None, "ab"),
+            )))
+        );
+    }
+
+    #[test]
+    fn cancelled() {
+        let reassembler = Reassembler::new(100);
+        reassembler.reassemble(fragment(1, true, "a")).unwrap();
+        let cancel = Frame::Cancel(CancelFrame::new(1));
+        assert_eq!(
+            reassembler.reassemble(cancel.clone()).unwrap(),
+            Some(cancel)
+        );
+        assert!(reassembler.streams.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exceeds_max_size() {
+        let reassembler = Reassembler::new(4);
+        reassembler.reassemble(fragment(1, true, "abc")).unwrap();
+        let err = reassembler.reassemble(fragment(1, true, "de")).unwrap_err();
+        assert!(err.is_connection_error());
+    }
+
+    #[test]
+    fn interleaved() {
+        let reassembler = Reassembler::new(100);
+        reassembler.reassemble(fragment(1, true, "a")).unwrap();
+        let frame = Frame::RequestResponse(RequestResponseFrame::new(
+            1,
+            false,
+            payload(None, "b"),
+        ));
+        let err = reassembler.reassemble(frame).unwrap_err();
+        assert!(err.is_connection_error());
+        let message = err.to_string();
+        assert!(message.contains("REQUEST_RESPONSE"), "{}", message);
+        assert!(!message.contains("b\""), "{}", message);
+    }
+
+    #[test]
+    fn removed() {
+        let reassembler = Reassembler::new(100);
+        reassembler.reassemble(fragment(1, true, "a")).unwrap();
+        reassembler.reassemble(fragment(3, true, "b")).unwrap();
+        reassembler.remove(1);
+        assert_eq!(reassembler.streams.lock().unwrap().len(), 1);
+        reassembler.clear();
+        assert!(reassembler.streams.lock().unwrap().is_empty());
+    }
+}
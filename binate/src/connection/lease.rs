@@ -0,0 +1,161 @@
+use crate::error::{Error, Kind, Result};
+use crate::frame::codec::LeaseFrame;
+use crate::lease::Lease;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Tracks the leases of a connection, both the one received from the peer, which admits the
+/// requests sent, and the one issued to the peer, which admits the requests received.
+///
+/// Every request is admitted until leases are enabled.
+#[derive(Debug)]
+pub(crate) struct Leases {
+    enabled: AtomicBool,
+    received: Mutex<Option<LeaseWindow>>,
+    issued: Mutex<Option<LeaseWindow>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// The requests left in a lease, until it expires.
+#[derive(Debug)]
+struct LeaseWindow {
+    expires_at: Instant,
+    remaining: u32,
+}
+
+impl Leases {
+    pub(crate) fn new() -> Leases {
+        Leases {
+            enabled: AtomicBool::new(false),
+            received: Mutex::new(None),
+            issued: Mutex::new(None),
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Requires a valid lease for every request from now on.
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Records the lease received from the peer, replacing the previous one.
+    pub(crate) fn receive(&self, frame: &LeaseFrame) {
+        let window = LeaseWindow::new(frame.ttl(), frame.number_of_requests());
+        *self.received.lock().unwrap() = Some(window);
+    }
+
+    /// Records the lease issued to the peer, replacing the previous one.
+    pub(crate) fn issue(&self, lease: &Lease) {
+        let window = LeaseWindow::new(lease.ttl(), lease.number_of_requests());
+        *self.issued.lock().unwrap() = Some(window);
+    }
+
+    /// Takes a request from the lease received, or returns a `REJECTED` error if no valid lease
+    /// is held.
+    pub(crate) fn acquire(&self) -> Result<()> {
+        if self.admit(&self.received) {
+            Ok(())
+        } else {
+            Err(Error::new(Kind::Rejected, Some("no valid lease")))
+        }
+    }
+
+    /// Takes a request from the lease issued, returning false if the peer holds no valid lease.
+    pub(crate) fn accept(&self) -> bool {
+        self.admit(&self.issued)
+    }
+
+    /// Sets the task that issues leases to the peer.
+    pub(crate) fn set_task(&self, task: JoinHandle<()>) {
+        if let Some(previous) = self.task.lock().unwrap().replace(task) {
+            previous.abort();
+        }
+    }
+
+    /// Stops issuing leases to the peer.
+    pub(crate) fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    fn admit(&self, window: &Mutex<Option<LeaseWindow>>) -> bool {
+        if !self.enabled.load(Ordering::SeqCst) {
+            return true;
+        }
+        match &mut *window.lock().unwrap() {
+            Some(window) => window.take(),
+            None => false,
+        }
+    }
+}
+
+impl LeaseWindow {
+    fn new(ttl: Duration, number_of_requests: u32) -> LeaseWindow {
+        LeaseWindow {
+            expires_at: Instant::now() + ttl,
+            remaining: number_of_requests,
+        }
+    }
+
+    /// Takes a request, returning false if the lease has expired or run out of requests.
+    fn take(&mut self) -> bool {
+        if self.remaining == 0 || Instant::now() >= self.expires_at {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Leases>();
+        assert_sync::<Leases>();
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        let leases = Leases::new();
+        assert!(leases.acquire().is_ok());
+        assert!(leases.accept());
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let leases = Leases::new();
+        leases.enable();
+        let err = leases.acquire().unwrap_err();
+        assert!(err.is_rejected());
+        assert!(!leases.accept());
+
+        leases.receive(&LeaseFrame::new(60_000, 2, None));
+        assert!(leases.acquire().is_ok());
+        assert!(leases.acquire().is_ok());
+        assert!(leases.acquire().is_err());
+
+        leases.issue(&Lease::new(Duration::from_secs(60), 1));
+        assert!(leases.accept());
+        assert!(!leases.accept());
+    }
+
+    #[tokio::test]
+    async fn expires() {
+        tokio::time::pause();
+        let leases = Leases::new();
+        leases.enable();
+        leases.receive(&LeaseFrame::new(10, 100, None));
+        assert!(leases.acquire().is_ok());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(leases.acquire().is_err());
+    }
+}
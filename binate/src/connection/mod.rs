@@ -3,9 +3,18 @@ mod buf;
 
 mod conn;
 mod counter;
+mod fragment;
+mod lease;
+mod resume;
 mod socket;
 mod stream_id;
+mod subject;
 
+pub(crate) use self::conn::ConnectionAcceptor;
 pub use self::conn::{ConnectionStatus, DuplexConnection};
 pub use self::counter::RequestCounter;
+pub(crate) use self::fragment::{Fragmenter, Reassembler};
+pub(crate) use self::lease::Leases;
+pub(crate) use self::resume::ResumableConnection;
+pub(crate) use self::socket::{Prefetch, RSocketMachine, Role};
 pub use self::stream_id::StreamIdProvider;
@@ -0,0 +1,642 @@
+use super::{ConnectionStatus, DuplexConnection};
+use crate::error::{Code, Error, Kind, Result};
+use crate::frame::codec::{KeepaliveFrame, ResumeFrame, ResumeOkFrame};
+use crate::frame::{Encode, Frame, Version};
+use crate::resume::ResumableFramesStore;
+use crate::runtime;
+use crate::transport::{not_connected, Status};
+use crate::Flux;
+use crate::Mono;
+
+use bytes::Bytes;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::warn;
+
+/// The state of the transport underlying a [`ResumableConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Link {
+    Connected,
+    Disconnected,
+    Closed,
+}
+
+/// A `DuplexConnection` that outlives its transport.
+///
+/// The implied positions of the resumable frames sent and received are tracked, and the frames
+/// sent are kept until the peer acknowledges them with a KEEPALIVE frame. When the transport is
+/// lost, the session can be resumed over a new transport, where the frames that the peer has
+/// not received are replayed.
+#[derive(Clone)]
+pub(crate) struct ResumableConnection {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    token: Bytes,
+    store: Arc<dyn ResumableFramesStore>,
+    state: Mutex<State>,
+    inbound_rx: Mutex<Option<mpsc::UnboundedReceiver<Frame>>>,
+    status: Status,
+    link: watch::Sender<Link>,
+    link_rx: watch::Receiver<Link>,
+}
+
+struct State {
+    transport: Option<Arc<dyn DuplexConnection>>,
+    // Identifies the current transport, so that the frames still received from a replaced
+    // transport are dropped.
+    generation: u64,
+    inbound: Option<mpsc::UnboundedSender<Frame>>,
+    sent_position: u64,
+    received_position: u64,
+}
+
+impl ResumableConnection {
+    /// Creates a new `ResumableConnection` for the session identified by `token`, which keeps
+    /// the frames sent in the given store.
+    ///
+    /// Any frames left in the store for the same token are removed. No frames are sent or
+    /// received until a transport is attached.
+    pub(crate) fn new(
+        token: Bytes,
+        store: Arc<dyn ResumableFramesStore>,
+    ) -> ResumableConnection {
+        if let Err(err) = store.remove(&token) {
+            warn!("failed to remove frames of a previous session: {}", err);
+        }
+        ResumableConnection::restore(token, store, 0, 0)
+    }
+
+    /// Creates a `ResumableConnection` for a session saved in the given store by a previous
+    /// process, which continues from the given positions.
+    pub(crate) fn restore(
+        token: Bytes,
+        store: Arc<dyn ResumableFramesStore>,
+        sent_position: u64,
+        received_position: u64,
+    ) -> ResumableConnection {
+        let (inbound, inbound_rx) = mpsc::unbounded_channel();
+        let (link, link_rx) = watch::channel(Link::Disconnected);
+        let state = State {
+            transport: None,
+            generation: 0,
+            inbound: Some(inbound),
+            sent_position,
+            received_position,
+        };
+        ResumableConnection {
+            inner: Arc::new(Inner {
+                token,
+                store,
+                state: Mutex::new(state),
+                inbound_rx: Mutex::new(Some(inbound_rx)),
+                status: Status::new(ConnectionStatus::Connected),
+                link,
+                link_rx,
+            }),
+        }
+    }
+
+    /// Returns the token that identifies the session.
+    pub(crate) fn token(&self) -> &Bytes {
+        &self.inner.token
+    }
+
+    /// Returns true if both connections share the same session.
+    pub(crate) fn ptr_eq(&self, other: &ResumableConnection) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Attaches the transport that the session is set up on.
+    pub(crate) fn attach(
+        &self,
+        transport: Arc<dyn DuplexConnection>,
+        frames: Flux<Frame>,
+    ) {
+        let mut state = self.inner.state.lock().unwrap();
+        self.attach_locked(&mut state, transport, frames);
+    }
+
+    /// Resumes the session over the given transport as the client.
+    ///
+    /// This sends a RESUME frame and waits for the server to reply a RESUME_OK frame. An error
+    /// with the code [`Code::RejectedResume`] is returned if the session cannot be resumed.
+    pub(crate) async fn resume(
+        &self,
+        transport: Arc<dyn DuplexConnection>,
+    ) -> Result<()> {
+        let mut frames = transport.receive();
+        let frame = {
+            let state = self.inner.state.lock().unwrap();
+            ResumeFrame::new(
+                Version::default(),
+                self.inner.token.clone(),
+                state.received_position,
+                self.inner.store.first_available_position(&self.inner.token),
+            )
+        };
+        transport.send(Frame::Resume(frame)).await?;
+
+        let position = match frames.next().await {
+            Some(Frame::ResumeOk(frame)) => {
+                frame.last_received_server_position()
+            }
+            Some(Frame::Error(frame)) => return Err(frame.into()),
+            Some(frame) => {
+                return Err(Error::new(
+                    Kind::ConnectionError,
+                    Some(format!("expected RESUME_OK but got {:?}", frame)),
+                ))
+            }
+            None => {
+                return Err(Error::new(
+                    Kind::ConnectionError,
+                    Some("connection was closed before RESUME_OK"),
+                ))
+            }
+        };
+
+        let mut state = self.inner.state.lock().unwrap();
+        if self.inner.status.is_closed() {
+            return Err(not_connected());
+        }
+        if let Err(err) = self.replay(&state, &*transport, position) {
+            let frame =
+                Error::new(Kind::ConnectionError, Some(err.to_string()))
+                    .to_frame(0);
+            let _ = transport.send_and_forget(Frame::Error(frame));
+            transport.close();
+            return Err(err);
+        }
+        self.attach_locked(&mut state, transport, frames);
+        Ok(())
+    }
+
+    /// Resumes the session over the given transport as the server, replying the RESUME frame
+    /// received with a RESUME_OK frame.
+    ///
+    /// An error with the code [`Code::RejectedResume`] is returned if the session cannot be
+    /// resumed, which should be sent to the client.
+    pub(crate) fn accept_resume(
+        &self,
+        transport: Arc<dyn DuplexConnection>,
+        frames: Flux<Frame>,
+        frame: &ResumeFrame,
+    ) -> Result<()> {
+        let mut state = self.inner.state.lock().unwrap();
+        if self.inner.status.is_closed() {
+            return Err(Error::with_code(
+                Code::RejectedResume,
+                "session has been closed",
+            ));
+        }
+        let position = frame.last_received_server_position();
+        let frames_to_replay = self.load(&state, position)?;
+        if frame.first_available_client_position() > state.received_position {
+            return Err(Error::with_code(
+                Code::RejectedResume,
+                format!(
+                    "frames from position {} have been lost",
+                    state.received_position
+                ),
+            ));
+        }
+
+        // The previous transport may not be found lost yet.
+        if let Some(previous) = state.transport.take() {
+            previous.close();
+        }
+        let frame = ResumeOkFrame::new(state.received_position);
+        transport.send_and_forget(Frame::ResumeOk(frame))?;
+        for frame in frames_to_replay {
+            let _ = transport.send_and_forget(frame);
+        }
+        self.attach_locked(&mut state, transport, frames);
+        Ok(())
+    }
+
+    /// Closes the current transport, leaving the session to be resumed.
+    pub(crate) fn disconnect(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        self.detach_locked(&mut state);
+    }
+
+    /// Waits until the transport is lost, returning `false` if the session is closed instead.
+    pub(crate) async fn disconnected(&self) -> bool {
+        self.wait_link(|link| link != Link::Connected).await
+            == Link::Disconnected
+    }
+
+    /// Waits until the session is resumed, returning `false` if the session is closed instead.
+    pub(crate) async fn reconnected(&self) -> bool {
+        self.wait_link(|link| link != Link::Disconnected).await
+            == Link::Connected
+    }
+
+    /// Closes the session because it cannot be resumed.
+    pub(crate) fn expire<E: Into<String>>(&self, reason: E) {
+        self.shutdown(ConnectionStatus::Error(reason.into()));
+    }
+
+    /// Returns true if the session has been closed.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.inner.status.is_closed()
+    }
+
+    fn attach_locked(
+        &self,
+        state: &mut State,
+        transport: Arc<dyn DuplexConnection>,
+        mut frames: Flux<Frame>,
+    ) {
+        state.generation += 1;
+        state.transport = Some(transport);
+        let _ = self.inner.link.send(Link::Connected);
+
+        let generation = state.generation;
+        let conn = self.clone();
+        runtime::spawn(async move {
+            while let Some(frame) = frames.next().await {
+                if !conn.handle_inbound(generation, frame) {
+                    return;
+                }
+            }
+            let mut state = conn.inner.state.lock().unwrap();
+            if state.generation == generation {
+                conn.detach_locked(&mut state);
+            }
+        });
+    }
+
+    fn detach_locked(&self, state: &mut State) {
+        if let Some(transport) = state.transport.take() {
+            state.generation += 1;
+            transport.close();
+            if *self.inner.link_rx.borrow() == Link::Connected {
+                let _ = self.inner.link.send(Link::Disconnected);
+            }
+        }
+    }
+
+    /// Passes a frame received on the transport of the given generation to the receiver,
+    /// returning false if the transport has been replaced.
+    fn handle_inbound(&self, generation: u64, frame: Frame) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.generation != generation {
+            return false;
+        }
+        if is_resumable(&frame) {
+            state.received_position += frame.len() as u64;
+            let result = self.inner.store.save_received_position(
+                &self.inner.token,
+                state.received_position,
+            );
+            if let Err(err) = result {
+                warn!("failed to save received position: {}", err);
+            }
+        }
+        if let Frame::Keepalive(frame) = &frame {
+            self.release(frame.last_received_position());
+        }
+        match &state.inbound {
+            Some(inbound) => inbound.send(frame).is_ok(),
+            None => false,
+        }
+    }
+
+    fn shutdown(&self, status: ConnectionStatus) {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(transport) = state.transport.take() {
+            transport.close();
+        }
+        state.generation += 1;
+        state.inbound = None;
+        if let Err(err) = self.inner.store.remove(&self.inner.token) {
+            warn!("failed to remove frames of a closed session: {}", err);
+        }
+        self.inner.status.set(status);
+        let _ = self.inner.link.send(Link::Closed);
+    }
+
+    /// Loads the frames from the given position, which the peer has not received.
+    ///
+    /// An error with the code [`Code::RejectedResume`] is returned if the frames have been
+    /// evicted from the store.
+    fn load(&self, state: &State, position: u64) -> Result<Vec<Frame>> {
+        if position > state.sent_position {
+            return Err(Error::with_code(
+                Code::RejectedResume,
+                format!("cannot replay frames from position {}", position),
+            ));
+        }
+        self.release(position);
+        self.inner
+            .store
+            .load(&self.inner.token, position)?
+            .into_iter()
+            .map(|mut buf| Ok(Frame::decode(&mut buf)?))
+            .collect()
+    }
+
+    /// Resends the frames from the given position, which the peer has not received.
+    fn replay(
+        &self,
+        state: &State,
+        transport: &dyn DuplexConnection,
+        position: u64,
+    ) -> Result<()> {
+        for frame in self.load(state, position)? {
+            let _ = transport.send_and_forget(frame);
+        }
+        Ok(())
+    }
+
+    /// Drops the frames that the peer has received.
+    fn release(&self, position: u64) {
+        if let Err(err) = self.inner.store.release(&self.inner.token, position)
+        {
+            warn!("failed to release resumable frames: {}", err);
+        }
+    }
+
+    async fn wait_link<F>(&self, mut predicate: F) -> Link
+    where
+        F: FnMut(Link) -> bool,
+    {
+        let mut link_rx = self.inner.link_rx.clone();
+        loop {
+            let link = *link_rx.borrow();
+            if predicate(link) {
+                return link;
+            }
+            if link_rx.changed().await.is_err() {
+                return Link::Closed;
+            }
+        }
+    }
+}
+
+impl DuplexConnection for ResumableConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        let result = self.send_and_forget(frame);
+        Box::pin(async move { result })
+    }
+
+    /// Sends a frame on the current transport.
+    ///
+    /// Resumable frames are kept for replaying, even if there is no transport at the moment,
+    /// while other frames are dropped if there is no transport.
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        let mut state = self.inner.state.lock().unwrap();
+        if self.inner.status.is_closed() {
+            return Err(not_connected());
+        }
+        let frame = match frame {
+            // Acknowledges the frames received so far.
+            Frame::Keepalive(frame) => Frame::Keepalive(KeepaliveFrame::new(
+                state.received_position,
+                frame.data().cloned(),
+                frame.is_respond(),
+            )),
+            frame => frame,
+        };
+        if is_resumable(&frame) {
+            let position = state.sent_position;
+            state.sent_position += frame.len() as u64;
+            let result = self.inner.store.save(
+                &self.inner.token,
+                position,
+                frame.to_bytes(),
+            );
+            if let Err(err) = result {
+                warn!("failed to save resumable frame: {}", err);
+            }
+        }
+        if let Some(transport) = &state.transport {
+            // Lost frames are either replayed or not needed after resumption.
+            let _ = transport.send_and_forget(frame);
+        }
+        Ok(())
+    }
+
+    fn send_stream(&self, mut frames: Flux<Frame>) {
+        let conn = self.clone();
+        runtime::spawn(async move {
+            while let Some(frame) = frames.next().await {
+                if conn.send_and_forget(frame).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        match self.inner.inbound_rx.lock().unwrap().take() {
+            Some(rx) => Box::pin(UnboundedReceiverStream::new(rx)),
+            None => Box::pin(tokio_stream::empty()),
+        }
+    }
+
+    fn connect(&self) {}
+
+    fn close(&self) {
+        self.shutdown(ConnectionStatus::Closed);
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.inner.status.stream()
+    }
+}
+
+impl fmt::Debug for ResumableConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.inner.state.lock().unwrap();
+        f.debug_struct("ResumableConnection")
+            .field("token", &self.inner.token)
+            .field("status", &self.inner.status.get())
+            .field("sent_position", &state.sent_position)
+            .field("received_position", &state.received_position)
+            .finish()
+    }
+}
+
+/// Returns true if the frame is sent on a stream, which counts towards the implied position.
+fn is_resumable(frame: &Frame) -> bool {
+    match frame {
+        Frame::RequestResponse(_)
+        | Frame::RequestFnf(_)
+        | Frame::RequestStream(_)
+        | Frame::RequestChannel(_)
+        | Frame::RequestN(_)
+        | Frame::Cancel(_)
+        | Frame::Payload(_) => true,
+        Frame::Error(frame) => frame.stream_id() != 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::frame::Flags;
+    use crate::payload::Payload;
+    use crate::resume::InMemoryFramesStore;
+    use crate::test_helpers::*;
+    use crate::transport::LocalConnection;
+
+    fn next(stream_id: u32, data: &'static str) -> Frame {
+        Frame::Payload(PayloadFrame::new(
+            stream_id,
+            Flags::NEXT,
+            Payload::builder().set_data(data).build(),
+        ))
+    }
+
+    fn new_conn(store: InMemoryFramesStore) -> ResumableConnection {
+        ResumableConnection::new(Bytes::from("token"), Arc::new(store))
+    }
+
+    fn keepalive(position: u64) -> Frame {
+        Frame::Keepalive(KeepaliveFrame::new(position, None, false))
+    }
+
+    /// Attaches a new transport to the given connection, returning the peer end.
+    fn attach(conn: &ResumableConnection) -> LocalConnection {
+        let (transport, peer) = LocalConnection::pair();
+        let frames = transport.receive();
+        conn.attach(Arc::new(transport), frames);
+        peer
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<ResumableConnection>();
+        assert_sync::<ResumableConnection>();
+    }
+
+    #[tokio::test]
+    async fn tracks_positions() {
+        let conn = new_conn(InMemoryFramesStore::default());
+        let peer = attach(&conn);
+        let mut peer_frames = peer.receive();
+        let mut frames = conn.receive();
+        let len = next(1, "a").len() as u64;
+
+        peer.send_and_forget(next(2, "a")).unwrap();
+        peer.send_and_forget(keepalive(0)).unwrap();
+        assert_eq!(frames.next().await.unwrap(), next(2, "a"));
+        assert_eq!(frames.next().await.unwrap(), keepalive(0));
+
+        // KEEPALIVE frames carry the position received.
+        conn.send_and_forget(next(1, "a")).unwrap();
+        conn.send_and_forget(keepalive(0)).unwrap();
+        assert_eq!(peer_frames.next().await.unwrap(), next(1, "a"));
+        assert_eq!(peer_frames.next().await.unwrap(), keepalive(len));
+
+        // Frames are released once acknowledged by the peer.
+        peer.send_and_forget(keepalive(len)).unwrap();
+        frames.next().await.unwrap();
+        let state = conn.inner.state.lock().unwrap();
+        assert_eq!(
+            conn.inner.store.first_available_position(conn.token()),
+            len
+        );
+        assert_eq!(state.sent_position, len);
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let client = new_conn(InMemoryFramesStore::default());
+        let server = new_conn(InMemoryFramesStore::default());
+        let (transport, peer) = LocalConnection::pair();
+        let (frames, peer_frames) = (transport.receive(), peer.receive());
+        client.attach(Arc::new(transport), frames);
+        server.attach(Arc::new(peer), peer_frames);
+        let mut client_frames = client.receive();
+        let mut server_frames = server.receive();
+
+        client.send_and_forget(next(1, "before")).unwrap();
+        assert_eq!(server_frames.next().await.unwrap(), next(1, "before"));
+
+        client.disconnect();
+        assert!(client.disconnected().await);
+        assert!(server.disconnected().await);
+        // Both are lost, and replayed after resumption.
+        client.send_and_forget(next(1, "client")).unwrap();
+        server.send_and_forget(next(1, "server")).unwrap();
+
+        let (transport, peer) = LocalConnection::pair();
+        let mut peer_frames = peer.receive();
+        let resume = tokio::spawn({
+            let client = client.clone();
+            async move { client.resume(Arc::new(transport)).await }
+        });
+        let frame = match peer_frames.next().await.unwrap() {
+            Frame::Resume(frame) => frame,
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        assert_eq!(frame.resume_token(), "token");
+        assert_eq!(frame.last_received_server_position(), 0);
+        server.accept_resume(Arc::new(peer), peer_frames, &frame).unwrap();
+        resume.await.unwrap().unwrap();
+
+        assert_eq!(server_frames.next().await.unwrap(), next(1, "client"));
+        assert_eq!(client_frames.next().await.unwrap(), next(1, "server"));
+    }
+
+    #[tokio::test]
+    async fn rejected_resume() {
+        let server = new_conn(InMemoryFramesStore::default());
+        let _peer = attach(&server);
+        server.send_and_forget(next(2, "a")).unwrap();
+
+        let (transport, _peer) = LocalConnection::pair();
+        let frames = transport.receive();
+        let frame = ResumeFrame::new(
+            Version::default(),
+            Bytes::from("token"),
+            1000,
+            0,
+        );
+        let err = server
+            .accept_resume(Arc::new(transport), frames, &frame)
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+    }
+
+    #[tokio::test]
+    async fn evicted_resume() {
+        let len = next(2, "a").len();
+        let server = new_conn(InMemoryFramesStore::new(len));
+        let _peer = attach(&server);
+        server.send_and_forget(next(2, "a")).unwrap();
+        server.send_and_forget(next(2, "b")).unwrap();
+
+        let (transport, _peer) = LocalConnection::pair();
+        let frames = transport.receive();
+        let frame =
+            ResumeFrame::new(Version::default(), Bytes::from("token"), 0, 0);
+        let err = server
+            .accept_resume(Arc::new(transport), frames, &frame)
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let conn = new_conn(InMemoryFramesStore::default());
+        let peer = attach(&conn);
+        let mut frames = conn.receive();
+        conn.close();
+
+        assert!(!conn.disconnected().await);
+        assert!(conn.send_and_forget(next(1, "a")).is_err());
+        assert!(frames.next().await.is_none());
+        assert!(peer.receive().next().await.is_none());
+    }
+}
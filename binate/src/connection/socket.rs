@@ -1,9 +1,17 @@
+use crate::connection::subject::{
+    CancelHandle, FluxGuard, FluxSubject, GuardedFlux, MonoSubject,
+};
 use crate::connection::{
-    ConnectionStatus, DuplexConnection, RequestCounter, StreamIdProvider,
+    ConnectionStatus, DuplexConnection, Fragmenter, Leases, Reassembler,
+    RequestCounter, ResumableConnection, StreamIdProvider,
+};
+use crate::consts::{
+    DEFAULT_LOW_WATER_MARK, DEFAULT_MAX_REASSEMBLED_SIZE, DEFAULT_PREFETCH,
 };
-use crate::error::Result;
 use crate::error::Timeout as KeepaliveTimeout;
-use crate::frame::{codec::*, Frame};
+use crate::error::{Code, Error, Kind, Result};
+use crate::frame::{codec::*, Flags, Frame, MAX_U31};
+use crate::lease::LeasePolicy;
 use crate::payload::Payload;
 use crate::runtime;
 use crate::types::{Subject, Subscription};
@@ -11,9 +19,11 @@ use crate::{Flux, Metadata, Mono, RSocket};
 
 use dashmap::DashMap;
 use std::fmt;
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::task::JoinHandle;
 use tokio::time::Instant;
 use tokio_stream::StreamExt;
 use tracing::error;
@@ -24,6 +34,26 @@ pub enum Role {
     Server,
 }
 
+/// The number of payloads that a requester asks for at a time.
+///
+/// A requester asks for `prefetch` payloads when it starts to receive a stream, and asks for
+/// more as the payloads are consumed, once the number of outstanding payloads falls to
+/// `low_water_mark`. A `prefetch` of `2^31 - 1` asks for an unbounded number of payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Prefetch {
+    pub(crate) prefetch: u32,
+    pub(crate) low_water_mark: u32,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Prefetch {
+            prefetch: DEFAULT_PREFETCH,
+            low_water_mark: DEFAULT_LOW_WATER_MARK,
+        }
+    }
+}
+
 #[derive(Clone)]
 struct RequestHanlder(Arc<RwLock<Box<dyn RSocket>>>);
 
@@ -32,19 +62,80 @@ pub(crate) struct RSocketMachine {
     role: Role,
     stream_id: Arc<StreamIdProvider>,
     connection: Arc<dyn DuplexConnection>,
+    resumable: Option<ResumableConnection>,
     request_handler: RequestHanlder,
-    receivers: Arc<DashMap<u32, Box<dyn Subject<Item = Frame>>>>,
+    receivers: Arc<DashMap<u32, Box<dyn Subject<Item = Payload>>>>,
     subscriptions: Arc<DashMap<u32, Box<dyn Subscription>>>,
-    request_n: Arc<RequestCounter>,
-    chunk_payload: Option<usize>,
+    demands: Arc<DashMap<u32, Arc<Demand>>>,
+    prefetch: Arc<Mutex<Prefetch>>,
+    fragmenter: Arc<Fragmenter>,
+    reassembler: Arc<Reassembler>,
+    keepalive_interval: Duration,
     keepalive_timeout: Duration,
-    keepalive_last_received: Instant,
+    keepalive_last_received: Arc<Mutex<Instant>>,
+    keepalive_task: Arc<Mutex<Option<JoinHandle<()>>>>,
+    leases: Arc<Leases>,
 }
 
 impl RSocketMachine {
     pub(crate) async fn new(
         role: Role,
         connection: impl DuplexConnection + 'static,
+        keepalive_interval: Duration,
+        keepalive_timeout: Duration,
+    ) -> RSocketMachine {
+        let rsm = RSocketMachine::unstarted(
+            role,
+            connection,
+            keepalive_interval,
+            keepalive_timeout,
+        );
+        rsm.start(rsm.connection.receive());
+        rsm
+    }
+
+    /// Creates a new `RSocketMachine` that does not handle any frames received until `start` is
+    /// called.
+    ///
+    /// The client sends a KEEPALIVE frame every `keepalive_interval`, and either side closes the
+    /// connection if no KEEPALIVE frame is received from its peer within `keepalive_timeout`.
+    pub(crate) fn unstarted(
+        role: Role,
+        connection: impl DuplexConnection + 'static,
+        keepalive_interval: Duration,
+        keepalive_timeout: Duration,
+    ) -> RSocketMachine {
+        RSocketMachine::build(
+            role,
+            Arc::new(connection),
+            None,
+            keepalive_interval,
+            keepalive_timeout,
+        )
+    }
+
+    /// Creates a new `RSocketMachine` over a resumable connection, which is disconnected rather
+    /// than closed when the keepalive times out.
+    pub(crate) fn unstarted_resumable(
+        role: Role,
+        connection: ResumableConnection,
+        keepalive_interval: Duration,
+        keepalive_timeout: Duration,
+    ) -> RSocketMachine {
+        RSocketMachine::build(
+            role,
+            Arc::new(connection.clone()),
+            Some(connection),
+            keepalive_interval,
+            keepalive_timeout,
+        )
+    }
+
+    fn build(
+        role: Role,
+        connection: Arc<dyn DuplexConnection>,
+        resumable: Option<ResumableConnection>,
+        keepalive_interval: Duration,
         keepalive_timeout: Duration,
     ) -> RSocketMachine {
         let stream_id = match role {
@@ -55,29 +146,38 @@ impl RSocketMachine {
         let rsm = RSocketMachine {
             role,
             stream_id,
-            connection: Arc::new(connection),
+            connection,
+            resumable,
             request_handler: RequestHanlder(Arc::new(RwLock::new(Box::new(
                 crate::rsocket::DummyRSocket,
             )))),
             receivers: Arc::new(DashMap::new()),
             subscriptions: Arc::new(DashMap::new()),
-            request_n: Arc::new(RequestCounter::new(0)),
-            chunk_payload: None,
+            demands: Arc::new(DashMap::new()),
+            prefetch: Arc::new(Mutex::new(Prefetch::default())),
+            fragmenter: Arc::new(Fragmenter::new()),
+            reassembler: Arc::new(Reassembler::new(
+                DEFAULT_MAX_REASSEMBLED_SIZE,
+            )),
+            keepalive_interval,
             keepalive_timeout,
-            keepalive_last_received: Instant::now(),
+            keepalive_last_received: Arc::new(Mutex::new(Instant::now())),
+            keepalive_task: Arc::new(Mutex::new(None)),
+            leases: Arc::new(Leases::new()),
         };
 
         // Listens to the connection status.
-        let mut cloned_rsm = rsm.clone();
+        let cloned_rsm = rsm.clone();
         runtime::spawn(async move {
-            while let Some(status) =
-                cloned_rsm.connection.connection_status().next().await
-            {
+            let mut status = cloned_rsm.connection.connection_status();
+            while let Some(status) = status.next().await {
                 match status {
                     ConnectionStatus::Closed => {
                         cloned_rsm.handle_transport_close();
                     }
                     ConnectionStatus::Error(err) => {
+                        cloned_rsm.stop_keepalive();
+                        cloned_rsm.leases.stop();
                         cloned_rsm.handle_error(&err);
                     }
                     _ => (),
@@ -85,40 +185,668 @@ impl RSocketMachine {
             }
         });
 
-        let mut cloned_rsm = rsm.clone();
+        rsm.start_keepalive();
+        rsm
+    }
+
+    /// Dispatches the given frames, which are received on the underlying connection.
+    pub(crate) fn start(&self, mut frames: Flux<Frame>) {
+        let rsm = self.clone();
         runtime::spawn(async move {
-            loop {
-                tokio::time::sleep(cloned_rsm.keepalive_timeout).await;
-                let now = Instant::now();
-                if now - cloned_rsm.keepalive_last_received
-                    > cloned_rsm.keepalive_timeout
+            while let Some(frame) = frames.next().await {
+                rsm.handle_frame(frame);
+            }
+            rsm.handle_transport_close();
+        });
+    }
+
+    /// Requires both sides to hold a valid lease to send requests, and issues leases to the peer
+    /// from the given policy (if any).
+    ///
+    /// No requests from the peer are accepted if no policy is given.
+    pub(crate) fn enable_lease(&self, policy: Option<Arc<dyn LeasePolicy>>) {
+        self.leases.enable();
+        if let Some(policy) = policy {
+            self.issue_leases(policy);
+        }
+    }
+
+    /// Issues leases to the peer from the given policy.
+    ///
+    /// A client issues leases only after its SETUP frame is sent, as a LEASE frame must not be
+    /// sent before it.
+    pub(crate) fn issue_leases(&self, policy: Arc<dyn LeasePolicy>) {
+        let rsm = self.clone();
+        let task = runtime::spawn(async move {
+            let mut leases = policy.leases();
+            while let Some(lease) = leases.next().await {
+                rsm.leases.issue(&lease);
+                let frame = LeaseFrame::new(
+                    lease.ttl().as_millis() as u32,
+                    lease.number_of_requests(),
+                    lease.metadata().cloned(),
+                );
+                if rsm.connection.send_and_forget(Frame::Lease(frame)).is_err()
                 {
-                    cloned_rsm.handle_connection_error(&KeepaliveTimeout);
                     break;
                 }
             }
         });
+        self.leases.set_task(task);
+    }
 
-        rsm
+    /// Sets the number of payloads to ask for on the streams requested from now on.
+    pub(crate) fn set_prefetch(&self, prefetch: Prefetch) {
+        *self.prefetch.lock().unwrap() = prefetch;
+    }
+
+    /// Sets the maximum length (in bytes) of the frames sent, over which payloads are fragmented.
+    pub(crate) fn set_mtu(&self, mtu: usize) {
+        self.fragmenter.set_mtu(mtu);
     }
 
-    pub fn close(&mut self) {
+    /// Sets the maximum size (in bytes) of the payloads reassembled from the fragments received.
+    pub(crate) fn set_max_reassembled_size(&self, max_size: usize) {
+        self.reassembler.set_max_size(max_size);
+    }
+
+    /// Sends a frame on the underlying connection.
+    pub(crate) async fn send(&self, frame: Frame) -> Result<()> {
+        self.connection.send(frame).await
+    }
+
+    /// Closes the underlying connection.
+    pub(crate) fn close(&self) {
         self.connection.close()
     }
+
+    /// Drops the transport of a resumable connection, as if the network is lost.
+    #[cfg(test)]
+    pub(crate) fn disconnect(&self) {
+        if let Some(resumable) = &self.resumable {
+            resumable.disconnect();
+        }
+    }
 }
 
 impl RSocketMachine {
-    fn handle_connection_error(&mut self, error: &impl fmt::Display) {
+    /// Spawns a task that sends KEEPALIVE frames (if this is the client), and closes the
+    /// connection with a `CONNECTION_ERROR` once the keepalive times out.
+    fn start_keepalive(&self) {
+        let rsm = self.clone();
+        let task = runtime::spawn(async move {
+            let interval = rsm.keepalive_interval;
+            let mut ticker =
+                tokio::time::interval_at(Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                let last_received =
+                    *rsm.keepalive_last_received.lock().unwrap();
+                if last_received.elapsed() > rsm.keepalive_timeout {
+                    if let Some(resumable) = &rsm.resumable {
+                        // Leaves the session to be resumed over a new transport.
+                        resumable.disconnect();
+                        *rsm.keepalive_last_received.lock().unwrap() =
+                            Instant::now();
+                        continue;
+                    }
+                    let err = Error::new(
+                        Kind::ConnectionError,
+                        Some(KeepaliveTimeout),
+                    );
+                    let _ = rsm
+                        .connection
+                        .send_and_forget(Frame::Error(err.to_frame(0)));
+                    rsm.handle_connection_error(&err);
+                    break;
+                }
+                if rsm.role == Role::Client {
+                    let frame =
+                        Frame::Keepalive(KeepaliveFrame::new(0, None, true));
+                    if rsm.connection.send_and_forget(frame).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        *self.keepalive_task.lock().unwrap() = Some(task);
+    }
+
+    fn stop_keepalive(&self) {
+        if let Some(task) = self.keepalive_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    fn handle_connection_error(&self, error: &impl fmt::Display) {
+        self.stop_keepalive();
+        self.leases.stop();
         self.handle_error(error);
+        self.terminate_streams(error);
         self.connection.close();
     }
 
-    fn handle_error(&mut self, error: &impl fmt::Display) {
+    fn handle_error(&self, error: &impl fmt::Display) {
         error!("{}", error);
     }
 
-    fn handle_transport_close(&mut self) {
+    fn handle_transport_close(&self) {
+        self.stop_keepalive();
+        self.leases.stop();
         self.handle_error(&"connection was closed");
+        self.terminate_streams(&"connection was closed");
+    }
+
+    /// Terminates all outstanding streams with a `CONNECTION_ERROR`.
+    fn terminate_streams(&self, reason: &impl fmt::Display) {
+        let stream_ids: Vec<u32> =
+            self.receivers.iter().map(|entry| *entry.key()).collect();
+        for stream_id in stream_ids {
+            if let Some((_, mut receiver)) = self.receivers.remove(&stream_id)
+            {
+                let reason = reason.to_string();
+                receiver
+                    .on_error(Error::new(Kind::ConnectionError, Some(reason)));
+            }
+        }
+        // Dropping the subscriptions cancels them.
+        self.subscriptions.clear();
+        self.reassembler.clear();
+    }
+}
+
+impl RSocketMachine {
+    fn handle_frame(&self, frame: Frame) {
+        let frame = match self.reassembler.reassemble(frame) {
+            Ok(Some(frame)) => frame,
+            // Waits for the rest of the fragments.
+            Ok(None) => return,
+            Err(err) => {
+                let frame = Frame::Error(err.to_frame(0));
+                let _ = self.connection.send_and_forget(frame);
+                self.handle_connection_error(&err);
+                return;
+            }
+        };
+        match frame {
+            Frame::Payload(frame) => self.handle_payload(frame),
+            Frame::Error(frame) => self.handle_error_frame(frame),
+            Frame::Cancel(frame) => self.handle_cancel(frame.stream_id()),
+            Frame::RequestResponse(frame) => {
+                self.handle_request_response(frame)
+            }
+            Frame::RequestFnf(frame) => self.handle_fire_and_forget(frame),
+            Frame::RequestStream(frame) => self.handle_request_stream(frame),
+            Frame::RequestChannel(frame) => self.handle_request_channel(frame),
+            Frame::MetadataPush(frame) => self.handle_metadata_push(frame),
+            Frame::Keepalive(frame) => self.handle_keepalive(frame),
+            Frame::Lease(frame) => self.leases.receive(&frame),
+            Frame::RequestN(frame) => self.handle_request_n(frame),
+            _ => (),
+        }
+    }
+
+    fn handle_keepalive(&self, frame: KeepaliveFrame) {
+        *self.keepalive_last_received.lock().unwrap() = Instant::now();
+        if frame.is_respond() {
+            let data = frame.data().cloned();
+            let frame = Frame::Keepalive(KeepaliveFrame::new(0, data, false));
+            let _ = self.connection.send_and_forget(frame);
+        }
+    }
+
+    fn handle_request_n(&self, frame: RequestNFrame) {
+        if let Some(demand) = self.demands.get(&frame.stream_id()) {
+            demand.add(frame.request_n());
+        }
+    }
+
+    fn handle_payload(&self, frame: PayloadFrame) {
+        let stream_id = frame.stream_id();
+        let (next, complete) = (frame.is_next(), frame.is_complete());
+
+        let result = match self.receivers.get_mut(&stream_id) {
+            Some(mut receiver) => {
+                let mut result = Ok(());
+                if next {
+                    result = receiver.on_next(frame.payload());
+                }
+                if complete && result.is_ok() {
+                    result = receiver.on_complete();
+                }
+                result
+            }
+            // PAYLOAD frames on unknown streams are ignored.
+            None => return,
+        };
+
+        if complete || result.is_err() {
+            self.receivers.remove(&stream_id);
+        }
+        if result.is_err() {
+            // The subscriber is gone, so there is no point to receive more payloads.
+            self.reassembler.remove(stream_id);
+            let frame = Frame::Cancel(CancelFrame::new(stream_id));
+            let _ = self.connection.send_and_forget(frame);
+        }
+    }
+
+    fn handle_error_frame(&self, frame: ErrorFrame) {
+        let stream_id = frame.stream_id();
+        if stream_id == 0 {
+            self.handle_connection_error(&Error::from(frame));
+            return;
+        }
+
+        self.handle_cancel(stream_id);
+        if let Some((_, mut receiver)) = self.receivers.remove(&stream_id) {
+            receiver.on_error(frame.into());
+        }
+    }
+
+    fn handle_cancel(&self, stream_id: u32) {
+        if let Some((_, mut subscription)) =
+            self.subscriptions.remove(&stream_id)
+        {
+            let _ = subscription.cancel();
+        }
+    }
+
+    fn handle_request_response(&self, frame: RequestResponseFrame) {
+        let stream_id = frame.stream_id();
+        if !self.accept_request(stream_id) {
+            return;
+        }
+        let rsm = self.clone();
+        self.spawn_subscription(stream_id, async move {
+            let response =
+                rsm.responder().await.request_response(frame.payload());
+            let frame = match response.await {
+                Ok(payload) => Frame::Payload(PayloadFrame::new(
+                    stream_id,
+                    Flags::NEXT | Flags::COMPLETE,
+                    payload,
+                )),
+                Err(err) => Frame::Error(err.to_frame(stream_id)),
+            };
+            let _ = rsm.send_fragmented(frame);
+        });
+    }
+
+    fn handle_fire_and_forget(&self, frame: RequestFnfFrame) {
+        if !self.leases.accept() {
+            // There is no stream to reject the request on.
+            self.handle_error(
+                &"fire-and-forget dropped without a valid lease",
+            );
+            return;
+        }
+        let rsm = self.clone();
+        runtime::spawn(async move {
+            if let Err(err) =
+                rsm.responder().await.fire_and_forget(frame.payload())
+            {
+                rsm.handle_error(&err);
+            }
+        });
+    }
+
+    fn handle_request_stream(&self, frame: RequestStreamFrame) {
+        let stream_id = frame.stream_id();
+        if !self.accept_request(stream_id) {
+            return;
+        }
+        let demand =
+            self.register_demand(stream_id, frame.initial_request_n());
+        let rsm = self.clone();
+        self.spawn_subscription(stream_id, async move {
+            let payloads =
+                rsm.responder().await.request_stream(frame.payload());
+            rsm.send_payloads(stream_id, payloads, demand).await;
+        });
+    }
+
+    fn handle_request_channel(&self, frame: RequestChannelFrame) {
+        let stream_id = frame.stream_id();
+        if !self.accept_request(stream_id) {
+            return;
+        }
+        let complete = frame.is_complete();
+        let demand =
+            self.register_demand(stream_id, frame.initial_request_n());
+
+        let prefetch = self.prefetch();
+        let (mut subject, rx) = FluxSubject::new();
+        let _ = subject.on_next(frame.payload());
+        if complete {
+            let _ = subject.on_complete();
+        } else {
+            self.receivers.insert(stream_id, Box::new(subject));
+            // The first payload comes along with the REQUEST_CHANNEL frame.
+            let n = match prefetch.prefetch {
+                MAX_U31 => MAX_U31,
+                n => n - 1,
+            };
+            if n > 0 {
+                let frame = Frame::RequestN(RequestNFrame::new(stream_id, n));
+                let _ = self.connection.send_and_forget(frame);
+            }
+        }
+        let guard = CancelGuard {
+            stream_id,
+            rsm: self.clone(),
+            requester: false,
+            replenish: Some(Replenish::new(prefetch)),
+        };
+        let payloads = Box::pin(GuardedFlux::new(rx, guard));
+
+        let rsm = self.clone();
+        self.spawn_subscription(stream_id, async move {
+            let payloads = rsm.responder().await.request_channel(payloads);
+            rsm.send_payloads(stream_id, payloads, demand).await;
+        });
+    }
+
+    fn handle_metadata_push(&self, frame: MetadataPushFrame) {
+        let rsm = self.clone();
+        runtime::spawn(async move {
+            let metadata = frame.metadata().clone();
+            let result = rsm.responder().await.metadata_push(metadata);
+            if let Err(err) = result.await {
+                rsm.handle_error(&err);
+            }
+        });
+    }
+
+    /// Takes a request from the lease issued to the peer, and rejects the request with an ERROR
+    /// frame if the peer holds no valid lease.
+    fn accept_request(&self, stream_id: u32) -> bool {
+        if self.leases.accept() {
+            return true;
+        }
+        let err = Error::with_code(Code::Rejected, "no valid lease");
+        let frame = Frame::Error(err.to_frame(stream_id));
+        let _ = self.connection.send_and_forget(frame);
+        false
+    }
+
+    /// Returns the responder that handles requests from the peer.
+    async fn responder(&self) -> RwLockReadGuard<'_, Box<dyn RSocket>> {
+        self.request_handler.0.read().await
+    }
+
+    /// Spawns a task that publishes the given stream until it either finishes or gets
+    /// cancelled by the peer.
+    fn spawn_subscription<F>(&self, stream_id: u32, publisher: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (handle, cancelled) = CancelHandle::new();
+        self.subscriptions.insert(stream_id, Box::new(handle));
+
+        let subscriptions = self.subscriptions.clone();
+        let demands = self.demands.clone();
+        runtime::spawn(async move {
+            tokio::select! {
+                _ = cancelled => (),
+                _ = publisher => (),
+            }
+            subscriptions.remove(&stream_id);
+            demands.remove(&stream_id);
+        });
+    }
+}
+
+impl RSocketMachine {
+    /// Allocates a new stream ID and registers the given subject as its receiver.
+    fn register(&self, subject: impl Subject<Item = Payload>) -> u32 {
+        let stream_id = self.stream_id.next_stream_id(&self.receivers);
+        self.receivers.insert(stream_id, Box::new(subject));
+        stream_id
+    }
+
+    /// Sends the request frame of a newly registered stream, deregistering the stream if the
+    /// frame cannot be sent.
+    fn send_request(&self, stream_id: u32, frame: Frame) -> Result<()> {
+        self.send_fragmented(frame).inspect_err(|_| {
+            self.receivers.remove(&stream_id);
+        })
+    }
+
+    /// Sends a frame that carries a payload, split into fragments if it exceeds the MTU.
+    fn send_fragmented(&self, frame: Frame) -> Result<()> {
+        for fragment in self.fragmenter.fragment(frame) {
+            self.connection.send_and_forget(fragment)?;
+        }
+        Ok(())
+    }
+
+    fn cancel_guard(
+        &self,
+        stream_id: u32,
+        prefetch: Option<Prefetch>,
+    ) -> CancelGuard {
+        CancelGuard {
+            stream_id,
+            rsm: self.clone(),
+            requester: true,
+            replenish: prefetch.map(Replenish::new),
+        }
+    }
+
+    /// Returns the number of payloads to ask for on a new stream.
+    fn prefetch(&self) -> Prefetch {
+        *self.prefetch.lock().unwrap()
+    }
+
+    /// Registers the demand of the peer on a stream that this side emits payloads to, which is
+    /// increased by the REQUEST_N frames received.
+    fn register_demand(&self, stream_id: u32, n: u32) -> Arc<Demand> {
+        let demand = Arc::new(Demand::new(n));
+        self.demands.insert(stream_id, demand.clone());
+        demand
+    }
+
+    /// Sends the given payloads as the requester side of a channel.
+    ///
+    /// The first payload is sent along with the REQUEST_CHANNEL frame.
+    async fn send_channel(
+        &self,
+        stream_id: u32,
+        initial_request_n: u32,
+        mut payloads: Flux<Result<Payload>>,
+    ) {
+        let frame = match payloads.next().await {
+            Some(Ok(payload)) => RequestChannelFrame::new(
+                stream_id,
+                false,
+                false,
+                initial_request_n,
+                payload,
+            ),
+            Some(Err(err)) => {
+                // The responder has not been contacted yet, so fail locally.
+                if let Some((_, mut receiver)) =
+                    self.receivers.remove(&stream_id)
+                {
+                    receiver.on_error(err);
+                }
+                return;
+            }
+            None => RequestChannelFrame::new(
+                stream_id,
+                false,
+                true,
+                initial_request_n,
+                Payload::default(),
+            ),
+        };
+        // The responder asks for the payloads after the first one.
+        let demand = self.register_demand(stream_id, 0);
+
+        let complete = frame.is_complete();
+        if let Err(err) =
+            self.send_request(stream_id, Frame::RequestChannel(frame))
+        {
+            error!("failed to send REQUEST_CHANNEL: {}", err);
+            return;
+        }
+        if !complete {
+            self.send_payloads(stream_id, payloads, demand).await;
+        }
+    }
+
+    /// Sends the given payloads as PAYLOAD frames, and terminates the stream with either a
+    /// COMPLETE or an ERROR frame.
+    ///
+    /// Each payload waits to be sent until the peer asks for it.
+    async fn send_payloads(
+        &self,
+        stream_id: u32,
+        mut payloads: Flux<Result<Payload>>,
+        demand: Arc<Demand>,
+    ) {
+        while let Some(item) = payloads.next().await {
+            let frame = match item {
+                Ok(payload) => {
+                    demand.acquire().await;
+                    Frame::Payload(PayloadFrame::new(
+                        stream_id,
+                        Flags::NEXT,
+                        payload,
+                    ))
+                }
+                Err(err) => {
+                    // The ERROR frame terminates the stream in both directions.
+                    let frame = Frame::Error(err.to_frame(stream_id));
+                    let _ = self.connection.send_and_forget(frame);
+                    self.reassembler.remove(stream_id);
+                    if let Some((_, mut receiver)) =
+                        self.receivers.remove(&stream_id)
+                    {
+                        receiver.on_error(err);
+                    }
+                    return;
+                }
+            };
+            if self.send_fragmented(frame).is_err() {
+                return;
+            }
+        }
+
+        let frame = Frame::Payload(PayloadFrame::new(
+            stream_id,
+            Flags::COMPLETE,
+            Payload::default(),
+        ));
+        let _ = self.connection.send_and_forget(frame);
+    }
+}
+
+/// Cancels a stream if its subscriber drops it before it terminates.
+///
+/// When a requester cancels a stream, the payloads it is sending on the same stream (if it is a
+/// channel) are cancelled as well. A responder, however, may keep sending payloads on a channel
+/// even if it is no longer interested in the payloads from the requester.
+struct CancelGuard {
+    stream_id: u32,
+    rsm: RSocketMachine,
+    requester: bool,
+    replenish: Option<Replenish>,
+}
+
+impl FluxGuard for CancelGuard {
+    fn on_consumed(&mut self) {
+        let n = match self.replenish.as_mut().and_then(Replenish::consumed) {
+            Some(n) => n,
+            None => return,
+        };
+        // No more payloads are needed once the stream terminates.
+        if self.rsm.receivers.contains_key(&self.stream_id) {
+            let frame = Frame::RequestN(RequestNFrame::new(self.stream_id, n));
+            let _ = self.rsm.connection.send_and_forget(frame);
+        }
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if self.rsm.receivers.remove(&self.stream_id).is_some() {
+            self.rsm.reassembler.remove(self.stream_id);
+            let frame = Frame::Cancel(CancelFrame::new(self.stream_id));
+            let _ = self.rsm.connection.send_and_forget(frame);
+            if self.requester {
+                self.rsm.handle_cancel(self.stream_id);
+            }
+        }
+    }
+}
+
+/// The number of payloads that the peer asks for on a stream that this side emits payloads to.
+struct Demand {
+    permits: RequestCounter,
+    notify: Notify,
+}
+
+impl Demand {
+    fn new(n: u32) -> Demand {
+        Demand { permits: RequestCounter::new(n), notify: Notify::new() }
+    }
+
+    fn add(&self, n: u32) {
+        self.permits.add(n);
+        self.notify.notify_one();
+    }
+
+    /// Waits until the peer asks for a payload.
+    async fn acquire(&self) {
+        while !self.permits.try_acquire() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Asks the peer for more payloads on a stream, as the payloads received are consumed.
+struct Replenish {
+    prefetch: Prefetch,
+    // The payloads asked for but not yet consumed.
+    outstanding: u32,
+}
+
+impl Replenish {
+    fn new(prefetch: Prefetch) -> Replenish {
+        Replenish { prefetch, outstanding: prefetch.prefetch }
+    }
+
+    /// Returns the number of payloads to ask for after a payload is consumed, if any.
+    fn consumed(&mut self) -> Option<u32> {
+        if self.prefetch.prefetch == MAX_U31 {
+            return None;
+        }
+        self.outstanding = self.outstanding.saturating_sub(1);
+        if self.outstanding > self.prefetch.low_water_mark {
+            return None;
+        }
+        let n = self.prefetch.prefetch - self.outstanding;
+        self.outstanding = self.prefetch.prefetch;
+        Some(n)
+    }
+}
+
+impl RSocketMachine {
+    /// Sets the responder that handles requests from the peer.
+    pub(crate) async fn set_request_handler(&self, handler: Box<dyn RSocket>) {
+        self.request_handler.set_request_handler(handler).await;
+    }
+}
+
+impl RSocketMachine {
+    /// Locks the responder, so that the requests from the peer are held until the returned
+    /// guard is dropped.
+    pub(crate) async fn lock_request_handler(
+        &self,
+    ) -> RwLockWriteGuard<'_, Box<dyn RSocket>> {
+        self.request_handler.0.write().await
     }
 }
 
@@ -130,42 +858,82 @@ impl RequestHanlder {
 }
 
 impl RSocket for RSocketMachine {
-    fn request_response(&self, _payload: Payload) -> Mono<Result<Payload>> {
-        unimplemented!()
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        if let Err(err) = self.leases.acquire() {
+            return Box::pin(async move { Err(err) });
+        }
+        let (subject, rx) = MonoSubject::new();
+        let stream_id = self.register(subject);
+
+        let frame = Frame::RequestResponse(RequestResponseFrame::new(
+            stream_id, false, payload,
+        ));
+        if let Err(err) = self.send_request(stream_id, frame) {
+            return Box::pin(async move { Err(err) });
+        }
+
+        let guard = self.cancel_guard(stream_id, None);
+        Box::pin(async move {
+            let _guard = guard;
+            match rx.await {
+                Ok(result) => result,
+                Err(_) => Err(Error::new(
+                    Kind::Canceled,
+                    Some("stream has been terminated"),
+                )),
+            }
+        })
     }
 
-    fn request_stream(&self, _payload: Payload) -> Flux<Result<Payload>> {
-        unimplemented!()
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        if let Err(err) = self.leases.acquire() {
+            return Box::pin(tokio_stream::once(Err(err)));
+        }
+        let (subject, rx) = FluxSubject::new();
+        let stream_id = self.register(subject);
+
+        let prefetch = self.prefetch();
+        let frame = Frame::RequestStream(RequestStreamFrame::new(
+            stream_id,
+            false,
+            prefetch.prefetch,
+            payload,
+        ));
+        if let Err(err) = self.send_request(stream_id, frame) {
+            return Box::pin(tokio_stream::once(Err(err)));
+        }
+
+        let guard = self.cancel_guard(stream_id, Some(prefetch));
+        Box::pin(GuardedFlux::new(rx, guard))
     }
 
     fn request_channel(
         &self,
-        _payloads: Flux<Result<Payload>>,
+        payloads: Flux<Result<Payload>>,
     ) -> Flux<Result<Payload>> {
-        unimplemented!()
+        if let Err(err) = self.leases.acquire() {
+            return Box::pin(tokio_stream::once(Err(err)));
+        }
+        let (subject, rx) = FluxSubject::new();
+        let stream_id = self.register(subject);
+
+        let prefetch = self.prefetch();
+        let rsm = self.clone();
+        self.spawn_subscription(stream_id, async move {
+            rsm.send_channel(stream_id, prefetch.prefetch, payloads).await;
+        });
+
+        let guard = self.cancel_guard(stream_id, Some(prefetch));
+        Box::pin(GuardedFlux::new(rx, guard))
     }
 
     fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        self.leases.acquire()?;
         let stream_id = self.stream_id.next_stream_id(&self.receivers);
 
-        if let Some(chunk_size) = self.chunk_payload {
-            let chunks = payload.chunks(chunk_size);
-            let chunks_len = chunks.len();
-            for (idx, chunked) in chunks.enumerate() {
-                let follows = idx + 1 != chunks_len;
-                let frame = Frame::RequestFnf(RequestFnfFrame::new(
-                    stream_id, follows, chunked,
-                ));
-                self.connection.send_and_forget(frame)?;
-            }
-        } else {
-            let frame = Frame::RequestFnf(RequestFnfFrame::new(
-                stream_id, false, payload,
-            ));
-            self.connection.send_and_forget(frame)?;
-        }
-
-        Ok(())
+        let frame =
+            Frame::RequestFnf(RequestFnfFrame::new(stream_id, false, payload));
+        self.send_fragmented(frame)
     }
 
     fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
@@ -173,3 +941,724 @@ impl RSocket for RSocketMachine {
         self.connection.send(frame)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionStatus;
+    use crate::frame::Encode;
+    use crate::transport::LocalConnection;
+    use crate::Code;
+    use bytes::Bytes;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+
+    /// A connection that records sent frames and replays injected ones.
+    struct MockConnection {
+        sent: mpsc::UnboundedSender<Frame>,
+        received: Mutex<Option<mpsc::UnboundedReceiver<Frame>>>,
+    }
+
+    impl DuplexConnection for MockConnection {
+        fn send(&self, frame: Frame) -> Mono<Result<()>> {
+            let result = self.send_and_forget(frame);
+            Box::pin(async move { result })
+        }
+
+        fn send_and_forget(&self, frame: Frame) -> Result<()> {
+            let _ = self.sent.send(frame);
+            Ok(())
+        }
+
+        fn send_stream(&self, _frames: Flux<Frame>) {
+            unimplemented!()
+        }
+
+        fn receive(&self) -> Flux<Frame> {
+            let rx = self.received.lock().unwrap().take().unwrap();
+            Box::pin(UnboundedReceiverStream::new(rx))
+        }
+
+        fn connect(&self) {}
+
+        fn close(&self) {}
+
+        fn connection_status(&self) -> Flux<ConnectionStatus> {
+            Box::pin(tokio_stream::pending())
+        }
+    }
+
+    struct Peer {
+        sent: mpsc::UnboundedReceiver<Frame>,
+        inject: mpsc::UnboundedSender<Frame>,
+    }
+
+    impl Peer {
+        async fn recv(&mut self) -> Frame {
+            self.sent.recv().await.unwrap()
+        }
+
+        fn send(&self, frame: Frame) {
+            self.inject.send(frame).unwrap();
+        }
+    }
+
+    async fn machine() -> (RSocketMachine, Peer) {
+        machine_with_keepalive(
+            Duration::from_secs(30),
+            Duration::from_secs(60),
+        )
+        .await
+    }
+
+    async fn machine_with_keepalive(
+        interval: Duration,
+        timeout: Duration,
+    ) -> (RSocketMachine, Peer) {
+        let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+        let (inject_tx, inject_rx) = mpsc::unbounded_channel();
+        let conn = MockConnection {
+            sent: sent_tx,
+            received: Mutex::new(Some(inject_rx)),
+        };
+        let rsm =
+            RSocketMachine::new(Role::Client, conn, interval, timeout).await;
+        (rsm, Peer { sent: sent_rx, inject: inject_tx })
+    }
+
+    fn payload(data: &'static str) -> Payload {
+        Payload::builder().set_data(data).build()
+    }
+
+    fn next(stream_id: u32, data: &'static str, complete: bool) -> Frame {
+        let mut flags = Flags::NEXT;
+        if complete {
+            flags |= Flags::COMPLETE;
+        }
+        Frame::Payload(PayloadFrame::new(stream_id, flags, payload(data)))
+    }
+
+    fn complete(stream_id: u32) -> Frame {
+        Frame::Payload(PayloadFrame::new(
+            stream_id,
+            Flags::COMPLETE,
+            Payload::default(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn request_response() {
+        let (rsm, mut peer) = machine().await;
+        let response = rsm.request_response(payload("ping"));
+
+        let frame = peer.recv().await;
+        assert_eq!(
+            frame,
+            Frame::RequestResponse(RequestResponseFrame::new(
+                1,
+                false,
+                payload("ping")
+            ))
+        );
+
+        peer.send(next(1, "pong", true));
+        assert_eq!(response.await.unwrap(), payload("pong"));
+        assert!(rsm.receivers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_response_error() {
+        let (rsm, mut peer) = machine().await;
+        let response = rsm.request_response(payload("ping"));
+        peer.recv().await;
+
+        peer.send(Frame::Error(ErrorFrame::new(
+            1,
+            ErrorFrame::APPLICATION_ERROR,
+            Some(Bytes::from("oops")),
+        )));
+        let err = response.await.unwrap_err();
+        assert!(err.is_application_error());
+        assert!(rsm.receivers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_response_cancel_on_drop() {
+        let (rsm, mut peer) = machine().await;
+        let response = rsm.request_response(payload("ping"));
+        peer.recv().await;
+
+        drop(response);
+        assert_eq!(peer.recv().await, Frame::Cancel(CancelFrame::new(1)));
+        assert!(rsm.receivers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_stream() {
+        let (rsm, mut peer) = machine().await;
+        let mut stream = rsm.request_stream(payload("ping"));
+
+        match peer.recv().await {
+            Frame::RequestStream(frame) => {
+                assert_eq!(frame.stream_id(), 1);
+                assert_eq!(frame.initial_request_n(), DEFAULT_PREFETCH);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        peer.send(next(1, "1", false));
+        peer.send(next(1, "2", false));
+        peer.send(complete(1));
+        assert_eq!(stream.next().await.unwrap().unwrap(), payload("1"));
+        assert_eq!(stream.next().await.unwrap().unwrap(), payload("2"));
+        assert!(stream.next().await.is_none());
+        assert!(rsm.receivers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn request_stream_ignores_unknown_streams() {
+        let (rsm, mut peer) = machine().await;
+        let mut stream = rsm.request_stream(payload("ping"));
+        peer.recv().await;
+
+        peer.send(next(3, "unknown", true));
+        peer.send(next(1, "1", true));
+        assert_eq!(stream.next().await.unwrap().unwrap(), payload("1"));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn request_channel() {
+        let (rsm, mut peer) = machine().await;
+        let outbound =
+            tokio_stream::iter(vec![Ok(payload("1")), Ok(payload("2"))]);
+        let mut inbound = rsm.request_channel(Box::pin(outbound));
+
+        assert_eq!(
+            peer.recv().await,
+            Frame::RequestChannel(RequestChannelFrame::new(
+                1,
+                false,
+                false,
+                DEFAULT_PREFETCH,
+                payload("1")
+            ))
+        );
+        peer.send(Frame::RequestN(RequestNFrame::new(1, 1)));
+        assert_eq!(peer.recv().await, next(1, "2", false));
+        assert_eq!(peer.recv().await, complete(1));
+
+        peer.send(next(1, "3", true));
+        assert_eq!(inbound.next().await.unwrap().unwrap(), payload("3"));
+        assert!(inbound.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn request_channel_cancelled_by_responder() {
+        let (rsm, mut peer) = machine().await;
+        let outbound = tokio_stream::iter(vec![Ok(payload("1"))])
+            .chain(tokio_stream::pending());
+        let _inbound = rsm.request_channel(Box::pin(outbound));
+        peer.recv().await;
+        assert!(rsm.subscriptions.contains_key(&1));
+
+        peer.send(Frame::Cancel(CancelFrame::new(1)));
+        while rsm.subscriptions.contains_key(&1) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_error_terminates_streams() {
+        let (rsm, mut peer) = machine().await;
+        let response = rsm.request_response(payload("ping"));
+        peer.recv().await;
+
+        peer.send(Frame::Error(ErrorFrame::new(
+            0,
+            ErrorFrame::CONNECTION_ERROR,
+            None,
+        )));
+        assert!(response.await.unwrap_err().is_connection_error());
+    }
+
+    /// A responder that echoes requests back.
+    struct EchoRSocket(mpsc::UnboundedSender<Payload>);
+
+    impl RSocket for EchoRSocket {
+        fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+            Box::pin(async move {
+                match payload.data_utf8() {
+                    Ok("error") => {
+                        Err(Error::with_code(Code::ApplicationError, "oops"))
+                    }
+                    _ => Ok(payload),
+                }
+            })
+        }
+
+        fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+            match payload.data_utf8() {
+                Ok("forever") => Box::pin(tokio_stream::pending()),
+                _ => Box::pin(tokio_stream::iter(vec![
+                    Ok(payload.clone()),
+                    Ok(payload),
+                ])),
+            }
+        }
+
+        fn request_channel(
+            &self,
+            payloads: Flux<Result<Payload>>,
+        ) -> Flux<Result<Payload>> {
+            payloads
+        }
+
+        fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+            self.0.send(payload).unwrap();
+            Ok(())
+        }
+
+        fn metadata_push(&self, metadata: Bytes) -> Mono<Result<()>> {
+            let payload = Payload::builder().set_metadata(metadata).build();
+            self.0.send(payload).unwrap();
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    async fn responder(
+    ) -> (RSocketMachine, Peer, mpsc::UnboundedReceiver<Payload>) {
+        let (rsm, peer) = machine().await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        rsm.set_request_handler(Box::new(EchoRSocket(tx))).await;
+        (rsm, peer, rx)
+    }
+
+    #[tokio::test]
+    async fn respond_request_response() {
+        let (_rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestResponse(RequestResponseFrame::new(
+            2,
+            false,
+            payload("ping"),
+        )));
+        assert_eq!(peer.recv().await, next(2, "ping", true));
+
+        peer.send(Frame::RequestResponse(RequestResponseFrame::new(
+            4,
+            false,
+            payload("error"),
+        )));
+        assert_eq!(
+            peer.recv().await,
+            Frame::Error(ErrorFrame::new(
+                4,
+                ErrorFrame::APPLICATION_ERROR,
+                Some(Bytes::from("oops"))
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn respond_request_stream() {
+        let (rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestStream(RequestStreamFrame::new(
+            2,
+            false,
+            MAX_U31,
+            payload("ping"),
+        )));
+        assert_eq!(peer.recv().await, next(2, "ping", false));
+        assert_eq!(peer.recv().await, next(2, "ping", false));
+        assert_eq!(peer.recv().await, complete(2));
+        while rsm.subscriptions.contains_key(&2) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn respond_request_stream_cancelled() {
+        let (rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestStream(RequestStreamFrame::new(
+            2,
+            false,
+            MAX_U31,
+            payload("forever"),
+        )));
+        while !rsm.subscriptions.contains_key(&2) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        peer.send(Frame::Cancel(CancelFrame::new(2)));
+        while rsm.subscriptions.contains_key(&2) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let sent =
+            tokio::time::timeout(Duration::from_millis(10), peer.recv());
+        assert!(sent.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn respond_request_channel() {
+        let (rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestChannel(RequestChannelFrame::new(
+            2,
+            false,
+            false,
+            MAX_U31,
+            payload("1"),
+        )));
+        peer.send(next(2, "2", false));
+        peer.send(complete(2));
+
+        assert_eq!(
+            peer.recv().await,
+            Frame::RequestN(RequestNFrame::new(2, DEFAULT_PREFETCH - 1))
+        );
+        assert_eq!(peer.recv().await, next(2, "1", false));
+        assert_eq!(peer.recv().await, next(2, "2", false));
+        assert_eq!(peer.recv().await, complete(2));
+        assert!(rsm.receivers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn respond_fire_and_forget_and_metadata_push() {
+        let (_rsm, peer, mut handled) = responder().await;
+        peer.send(Frame::RequestFnf(RequestFnfFrame::new(
+            2,
+            false,
+            payload("fnf"),
+        )));
+        assert_eq!(handled.recv().await.unwrap(), payload("fnf"));
+
+        peer.send(Frame::MetadataPush(MetadataPushFrame::new(Bytes::from(
+            "metadata",
+        ))));
+        assert_eq!(
+            handled.recv().await.unwrap(),
+            Payload::builder().set_metadata("metadata").build()
+        );
+    }
+
+    #[tokio::test]
+    async fn default_responder_rejects_requests() {
+        let (_rsm, mut peer) = machine().await;
+        peer.send(Frame::RequestResponse(RequestResponseFrame::new(
+            2,
+            false,
+            payload("ping"),
+        )));
+        match peer.recv().await {
+            Frame::Error(frame) => {
+                assert_eq!(frame.error_code(), ErrorFrame::REJECTED)
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn local_connection() {
+        let (client, server) = LocalConnection::pair_with_codec();
+        let (interval, timeout) =
+            (Duration::from_secs(30), Duration::from_secs(60));
+        let requester =
+            RSocketMachine::new(Role::Client, client, interval, timeout).await;
+        let rsm =
+            RSocketMachine::new(Role::Server, server, interval, timeout).await;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        rsm.set_request_handler(Box::new(EchoRSocket(tx))).await;
+
+        let response = requester.request_response(payload("ping")).await;
+        assert_eq!(response.unwrap(), payload("ping"));
+
+        let responses: Vec<_> =
+            requester.request_stream(payload("pong")).collect().await;
+        assert_eq!(responses.len(), 2);
+        for response in responses {
+            assert_eq!(response.unwrap(), payload("pong"));
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_keepalive() {
+        tokio::time::pause();
+        let interval = Duration::from_millis(10);
+        let (_rsm, mut peer) =
+            machine_with_keepalive(interval, Duration::from_secs(60)).await;
+        for _ in 0..3 {
+            assert!(peer.sent.try_recv().is_err());
+            tokio::time::advance(interval).await;
+            assert_eq!(
+                peer.recv().await,
+                Frame::Keepalive(KeepaliveFrame::new(0, None, true))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn responds_to_keepalive() {
+        let (_rsm, mut peer) = machine().await;
+        let data = Some(Bytes::from("data"));
+        peer.send(Frame::Keepalive(KeepaliveFrame::new(
+            0,
+            data.clone(),
+            false,
+        )));
+        peer.send(Frame::Keepalive(KeepaliveFrame::new(
+            0,
+            data.clone(),
+            true,
+        )));
+        assert_eq!(
+            peer.recv().await,
+            Frame::Keepalive(KeepaliveFrame::new(0, data, false))
+        );
+    }
+
+    #[tokio::test]
+    async fn keepalive_timeout() {
+        tokio::time::pause();
+        let interval = Duration::from_millis(10);
+        let (rsm, mut peer) =
+            machine_with_keepalive(interval, Duration::from_millis(50)).await;
+        let response = rsm.request_response(payload("ping"));
+        peer.recv().await;
+
+        let start = Instant::now();
+        let frame = loop {
+            tokio::time::advance(interval).await;
+            match peer.recv().await {
+                Frame::Keepalive(_) => {
+                    // Keeps the connection alive for a while before timing out.
+                    if start.elapsed() < Duration::from_millis(100) {
+                        peer.send(Frame::Keepalive(KeepaliveFrame::new(
+                            0, None, false,
+                        )));
+                    }
+                }
+                frame => break frame,
+            }
+        };
+        assert!(start.elapsed() > Duration::from_millis(100));
+        match frame {
+            Frame::Error(frame) => {
+                assert_eq!(frame.stream_id(), 0);
+                assert_eq!(frame.error_code(), ErrorFrame::CONNECTION_ERROR);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let err = response.await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::ConnectionError));
+    }
+
+    #[tokio::test]
+    async fn respond_request_stream_with_demand() {
+        let (rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestStream(RequestStreamFrame::new(
+            2,
+            false,
+            1,
+            payload("ping"),
+        )));
+        assert_eq!(peer.recv().await, next(2, "ping", false));
+        // Waits for the requester to ask for more.
+        let sent =
+            tokio::time::timeout(Duration::from_millis(10), peer.recv());
+        assert!(sent.await.is_err());
+
+        peer.send(Frame::RequestN(RequestNFrame::new(2, 5)));
+        assert_eq!(peer.recv().await, next(2, "ping", false));
+        assert_eq!(peer.recv().await, complete(2));
+        while rsm.demands.contains_key(&2) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn request_stream_replenishes() {
+        let (rsm, mut peer) = machine().await;
+        rsm.set_prefetch(Prefetch { prefetch: 4, low_water_mark: 1 });
+        let mut stream = rsm.request_stream(payload("ping"));
+        match peer.recv().await {
+            Frame::RequestStream(frame) => {
+                assert_eq!(frame.initial_request_n(), 4)
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        for i in 0..4 {
+            peer.send(next(1, "pong", false));
+            stream.next().await.unwrap().unwrap();
+            if i == 2 {
+                // Asks for more once 1 payload is left outstanding.
+                assert_eq!(
+                    peer.recv().await,
+                    Frame::RequestN(RequestNFrame::new(1, 3))
+                );
+            }
+        }
+        peer.send(complete(1));
+        assert!(stream.next().await.is_none());
+        // No more payloads are asked for after completion.
+        let sent =
+            tokio::time::timeout(Duration::from_millis(10), peer.recv());
+        assert!(sent.await.is_err());
+    }
+
+    #[test]
+    fn replenish() {
+        let mut replenish =
+            Replenish::new(Prefetch { prefetch: 3, low_water_mark: 0 });
+        assert_eq!(replenish.consumed(), None);
+        assert_eq!(replenish.consumed(), None);
+        assert_eq!(replenish.consumed(), Some(3));
+
+        let mut replenish =
+            Replenish::new(Prefetch { prefetch: MAX_U31, low_water_mark: 0 });
+        assert_eq!(replenish.consumed(), None);
+    }
+
+    #[tokio::test]
+    async fn respond_fragmented_request() {
+        let (_rsm, mut peer, _) = responder().await;
+        peer.send(Frame::RequestResponse(RequestResponseFrame::new(
+            2,
+            true,
+            payload("pi"),
+        )));
+        let mut flags = Flags::NEXT | Flags::FOLLOWS;
+        peer.send(Frame::Payload(PayloadFrame::new(2, flags, payload("n"))));
+        flags.remove(Flags::FOLLOWS);
+        peer.send(Frame::Payload(PayloadFrame::new(2, flags, payload("g"))));
+        assert_eq!(peer.recv().await, next(2, "ping", true));
+    }
+
+    #[tokio::test]
+    async fn fragment_by_mtu() {
+        let (rsm, mut peer) = machine().await;
+        rsm.set_mtu(64);
+        let data = Bytes::from(vec![b'a'; 100]);
+        let stream = rsm.request_stream(Payload::new(None, Some(data)));
+
+        match peer.recv().await {
+            Frame::RequestStream(frame) => {
+                assert!(frame.is_follows());
+                assert_eq!(frame.initial_request_n(), DEFAULT_PREFETCH);
+                assert_eq!(frame.len(), 64);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        match peer.recv().await {
+            Frame::Payload(frame) => {
+                assert!(!frame.is_follows());
+                assert_eq!(frame.data().unwrap().len(), 46);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        drop(stream);
+        assert_eq!(peer.recv().await, Frame::Cancel(CancelFrame::new(1)));
+
+        let data = Bytes::from(vec![b'b'; 60]);
+        rsm.fire_and_forget(Payload::new(None, Some(data))).unwrap();
+        match (peer.recv().await, peer.recv().await) {
+            (Frame::RequestFnf(first), Frame::Payload(last)) => {
+                assert!(first.is_follows() && !last.is_follows());
+                assert_eq!(first.len(), 64);
+            }
+            frames => panic!("unexpected frames {:?}", frames),
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_drops_fragments() {
+        let (rsm, mut peer) = machine().await;
+        let stream = rsm.request_stream(payload("ping"));
+        peer.recv().await;
+        let flags = Flags::NEXT | Flags::FOLLOWS;
+        peer.send(Frame::Payload(PayloadFrame::new(1, flags, payload("a"))));
+        while rsm.reassembler.len() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(stream);
+        assert_eq!(peer.recv().await, Frame::Cancel(CancelFrame::new(1)));
+        assert_eq!(rsm.reassembler.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn fragmented_payload_too_large() {
+        let (rsm, mut peer) = machine().await;
+        rsm.set_max_reassembled_size(4);
+        let response = rsm.request_response(payload("ping"));
+        peer.recv().await;
+
+        let flags = Flags::NEXT | Flags::FOLLOWS;
+        peer.send(Frame::Payload(PayloadFrame::new(1, flags, payload("abc"))));
+        peer.send(Frame::Payload(PayloadFrame::new(1, flags, payload("de"))));
+        match peer.recv().await {
+            Frame::Error(frame) => {
+                assert_eq!(frame.stream_id(), 0);
+                assert_eq!(frame.error_code(), ErrorFrame::CONNECTION_ERROR);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        assert!(response.await.unwrap_err().is_connection_error());
+    }
+
+    #[tokio::test]
+    async fn lease_requester() {
+        let (rsm, mut peer) = machine().await;
+        rsm.enable_lease(None);
+        let err = rsm.request_response(payload("ping")).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::Rejected));
+        assert!(rsm.fire_and_forget(payload("ping")).is_err());
+
+        peer.send(Frame::Lease(LeaseFrame::new(60_000, 1, None)));
+        // Waits for the lease to be received.
+        peer.send(Frame::Keepalive(KeepaliveFrame::new(0, None, true)));
+        peer.recv().await;
+
+        let response = rsm.request_response(payload("ping"));
+        assert!(matches!(peer.recv().await, Frame::RequestResponse(_)));
+        peer.send(next(1, "pong", true));
+        assert_eq!(response.await.unwrap(), payload("pong"));
+
+        // The lease has run out of requests.
+        let mut responses = rsm.request_stream(payload("ping"));
+        let err = responses.next().await.unwrap().unwrap_err();
+        assert_eq!(err.code(), Some(Code::Rejected));
+    }
+
+    #[tokio::test]
+    async fn lease_responder() {
+        use crate::lease::FixedLeasePolicy;
+
+        let (rsm, mut peer) = machine().await;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        rsm.set_request_handler(Box::new(EchoRSocket(tx))).await;
+        let policy = FixedLeasePolicy::new(Duration::from_secs(60), 1);
+        rsm.enable_lease(Some(Arc::new(policy)));
+        assert_eq!(
+            peer.recv().await,
+            Frame::Lease(LeaseFrame::new(60_000, 1, None))
+        );
+
+        let request = |stream_id| {
+            Frame::RequestResponse(RequestResponseFrame::new(
+                stream_id,
+                false,
+                payload("ping"),
+            ))
+        };
+        peer.send(request(2));
+        assert_eq!(peer.recv().await, next(2, "ping", true));
+        peer.send(request(4));
+        match peer.recv().await {
+            Frame::Error(frame) => {
+                assert_eq!(frame.stream_id(), 4);
+                assert_eq!(frame.error_code(), ErrorFrame::REJECTED);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::error::{Error, Kind, Result};
+use crate::payload::Payload;
+use crate::types::{Subject, Subscription};
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::Stream;
+
+/// A subject that completes a `Mono` with the first item it receives.
+pub(crate) struct MonoSubject(Option<oneshot::Sender<Result<Payload>>>);
+
+impl MonoSubject {
+    /// Creates a new `MonoSubject` along with the receiving half of it.
+    pub(crate) fn new() -> (MonoSubject, oneshot::Receiver<Result<Payload>>) {
+        let (tx, rx) = oneshot::channel();
+        (MonoSubject(Some(tx)), rx)
+    }
+
+    fn emit(&mut self, item: Result<Payload>) -> Result<()> {
+        match self.0.take() {
+            Some(tx) => tx.send(item).map_err(|_| subscriber_dropped()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Subject for MonoSubject {
+    type Item = Payload;
+
+    fn on_next(&mut self, item: Payload) -> Result<()> {
+        self.emit(Ok(item))
+    }
+
+    fn on_error(&mut self, err: Error) {
+        let _ = self.emit(Err(err));
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        // A request-response may complete without emitting any payload.
+        self.emit(Ok(Payload::default()))
+    }
+}
+
+/// A subject that feeds the items it receives into a `Flux`.
+pub(crate) struct FluxSubject(Option<mpsc::UnboundedSender<Result<Payload>>>);
+
+impl FluxSubject {
+    /// Creates a new `FluxSubject` along with the receiving half of it.
+    pub(crate) fn new(
+    ) -> (FluxSubject, mpsc::UnboundedReceiver<Result<Payload>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (FluxSubject(Some(tx)), rx)
+    }
+}
+
+impl Subject for FluxSubject {
+    type Item = Payload;
+
+    fn on_next(&mut self, item: Payload) -> Result<()> {
+        match &self.0 {
+            Some(tx) => tx.send(Ok(item)).map_err(|_| subscriber_dropped()),
+            None => Ok(()),
+        }
+    }
+
+    fn on_error(&mut self, err: Error) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(Err(err));
+        }
+    }
+
+    fn on_complete(&mut self) -> Result<()> {
+        // Dropping the sender terminates the stream.
+        self.0.take();
+        Ok(())
+    }
+}
+
+/// A subscription that signals cancellation to the task publishing a stream.
+///
+/// Dropping a `CancelHandle` cancels the subscription as well.
+pub(crate) struct CancelHandle(Option<oneshot::Sender<()>>);
+
+impl CancelHandle {
+    /// Creates a new `CancelHandle` along with a receiver that resolves when the subscription
+    /// gets cancelled.
+    pub(crate) fn new() -> (CancelHandle, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        (CancelHandle(Some(tx)), rx)
+    }
+}
+
+impl Subscription for CancelHandle {
+    fn cancel(&mut self) -> Result<()> {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(());
+        }
+        Ok(())
+    }
+}
+
+/// A guard of a [`GuardedFlux`], which is notified of the payloads consumed from it.
+pub(crate) trait FluxGuard {
+    /// Called every time a payload is consumed.
+    fn on_consumed(&mut self) {}
+}
+
+impl FluxGuard for () {}
+
+/// A `Flux` that runs a guard when it gets dropped.
+pub(crate) struct GuardedFlux<G> {
+    rx: mpsc::UnboundedReceiver<Result<Payload>>,
+    guard: G,
+}
+
+impl<G> GuardedFlux<G> {
+    pub(crate) fn new(
+        rx: mpsc::UnboundedReceiver<Result<Payload>>,
+        guard: G,
+    ) -> Self {
+        GuardedFlux { rx, guard }
+    }
+}
+
+impl<G: FluxGuard + Unpin> Stream for GuardedFlux<G> {
+    type Item = Result<Payload>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let poll = self.rx.poll_recv(cx);
+        if let Poll::Ready(Some(Ok(_))) = &poll {
+            self.guard.on_consumed();
+        }
+        poll
+    }
+}
+
+fn subscriber_dropped() -> Error {
+    Error::new(Kind::Canceled, Some("subscriber has been dropped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<MonoSubject>();
+        assert_sync::<MonoSubject>();
+        assert_send::<FluxSubject>();
+        assert_sync::<FluxSubject>();
+        assert_send::<CancelHandle>();
+        assert_sync::<CancelHandle>();
+    }
+
+    #[tokio::test]
+    async fn mono_subject() {
+        let (mut subject, rx) = MonoSubject::new();
+        let payload = Payload::builder().set_data("data").build();
+        subject.on_next(payload.clone()).unwrap();
+        subject.on_complete().unwrap();
+        assert_eq!(rx.await.unwrap().unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn mono_subject_complete_without_next() {
+        let (mut subject, rx) = MonoSubject::new();
+        subject.on_complete().unwrap();
+        assert_eq!(rx.await.unwrap().unwrap(), Payload::default());
+    }
+
+    #[tokio::test]
+    async fn flux_subject() {
+        let (mut subject, rx) = FluxSubject::new();
+        let mut flux = GuardedFlux::new(rx, ());
+        subject.on_next(Payload::builder().set_data("1").build()).unwrap();
+        subject.on_error(Error::new(Kind::Canceled, None::<Error>));
+        assert!(subject.on_next(Payload::default()).is_ok());
+
+        assert_eq!(flux.next().await.unwrap().unwrap().data().unwrap(), "1");
+        assert!(flux.next().await.unwrap().unwrap_err().is_cancel());
+        assert!(flux.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn guarded_flux_consumed() {
+        struct Counter(usize);
+
+        impl FluxGuard for Counter {
+            fn on_consumed(&mut self) {
+                self.0 += 1;
+            }
+        }
+
+        let (mut subject, rx) = FluxSubject::new();
+        let mut flux = GuardedFlux::new(rx, Counter(0));
+        subject.on_next(Payload::default()).unwrap();
+        subject.on_next(Payload::default()).unwrap();
+        subject.on_error(Error::new(Kind::Canceled, None::<Error>));
+        while flux.next().await.is_some() {}
+        assert_eq!(flux.guard.0, 2);
+    }
+
+    #[tokio::test]
+    async fn flux_subject_dropped() {
+        let (mut subject, rx) = FluxSubject::new();
+        drop(rx);
+        assert!(subject.on_next(Payload::default()).unwrap_err().is_cancel());
+    }
+
+    #[tokio::test]
+    async fn cancel_handle() {
+        let (mut handle, rx) = CancelHandle::new();
+        handle.cancel().unwrap();
+        assert!(rx.await.is_ok());
+
+        let (handle, rx) = CancelHandle::new();
+        drop(handle);
+        assert!(rx.await.is_err());
+    }
+}
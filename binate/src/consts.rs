@@ -6,3 +6,26 @@ pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
 /// Default value of the time that a client will allow a server to not respond to
 /// a KEEPALIVE before it is assumed to be dead.
 pub const DEFAULT_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default value of the time that a session is kept for its client to resume after the
+/// connection is lost.
+pub const DEFAULT_RESUME_SESSION_DURATION: Duration = Duration::from_secs(120);
+
+/// Default size (in bytes) of the frames kept for replaying after the session is resumed.
+pub const DEFAULT_RESUME_BUFFER_CAPACITY: usize = 1024 * 1024;
+
+/// Default number of payloads that a requester asks for when it starts to receive a stream.
+pub const DEFAULT_PREFETCH: u32 = 256;
+
+/// Default number of outstanding payloads, under which a requester asks for more.
+pub const DEFAULT_LOW_WATER_MARK: u32 = 64;
+
+/// Default maximum size (in bytes) of the payloads reassembled from fragments.
+pub const DEFAULT_MAX_REASSEMBLED_SIZE: usize = 64 * 1024 * 1024;
+
+/// The smallest MTU (in bytes) that frames can be fragmented by.
+pub const MIN_MTU: usize = 64;
+
+/// The time that an acceptor waits before accepting again, after it fails to accept a
+/// connection.
+pub const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
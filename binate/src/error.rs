@@ -1,5 +1,7 @@
 //! RSocket error and result types.
-use crate::frame::DecodeError;
+use crate::frame::codec::ErrorFrame;
+use crate::frame::{DecodeError, Frame};
+use bytes::Bytes;
 use std::error::Error as StdError;
 use std::fmt;
 use std::io;
@@ -114,6 +116,71 @@ impl Error {
         }
     }
 
+    /// Creates an error with the given code for a frame received when `expected` was.
+    ///
+    /// Only the type of the frame is reported, as the error is sent back to the peer.
+    pub(crate) fn unexpected_frame(
+        code: Code,
+        expected: impl fmt::Display,
+        frame: &Frame,
+    ) -> Error {
+        Error::with_code(
+            code,
+            format!("expected {} but got {:?}", expected, frame.frame_type()),
+        )
+    }
+
+    /// Creates an error with the given protocol error code.
+    ///
+    /// The `source` explains the error, it will be sent to the peer as the error data if this
+    /// error is returned from a responder.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use binate::{Code, Error};
+    ///
+    /// let err = Error::with_code(Code::ApplicationError, "invalid input");
+    /// assert!(err.is_application_error());
+    /// assert_eq!(err.code(), Some(Code::ApplicationError));
+    /// ```
+    pub fn with_code<E>(code: Code, source: E) -> Error
+    where
+        E: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let kind = match code {
+            Code::InvalidSetup => Kind::InvalidSetup,
+            Code::UnsupportedSetup => Kind::UnsupportedSetup,
+            Code::RejectedSetup => Kind::RejectedSetup,
+            Code::RejectedResume => Kind::RejectedResume,
+            Code::ConnectionError => Kind::ConnectionError,
+            Code::ConnectionClose => Kind::ConnectionClose,
+            Code::ApplicationError => Kind::ApplicationError,
+            Code::Rejected => Kind::Rejected,
+            Code::Canceled => Kind::Canceled,
+            Code::Invalid => Kind::Invalid,
+        };
+        Error::new(kind, Some(source))
+    }
+
+    /// Returns the protocol error code of this error, if it is a protocol error.
+    pub fn code(&self) -> Option<Code> {
+        use Kind::*;
+        match self.inner.kind {
+            InvalidSetup => Some(Code::InvalidSetup),
+            UnsupportedSetup => Some(Code::UnsupportedSetup),
+            RejectedSetup => Some(Code::RejectedSetup),
+            RejectedResume => Some(Code::RejectedResume),
+            ConnectionError => Some(Code::ConnectionError),
+            ConnectionClose => Some(Code::ConnectionClose),
+            ApplicationError => Some(Code::ApplicationError),
+            Rejected => Some(Code::Rejected),
+            Canceled => Some(Code::Canceled),
+            Invalid => Some(Code::Invalid),
+            Decode(_) | Io => None,
+        }
+    }
+
     /// Returns true if this error is related to decoding `Bytes`.
     pub fn is_decode(&self) -> bool {
         matches!(self.inner.kind, Kind::Decode(_))
@@ -201,6 +268,31 @@ impl Error {
         matches!(self.inner.kind, Kind::Invalid)
     }
 
+    /// Converts this error into an ERROR frame on the given stream.
+    ///
+    /// Errors that do not pertain to the kind of the given stream (connection or request
+    /// stream) are reported as `CONNECTION_ERROR` or `APPLICATION_ERROR` respectively.
+    pub(crate) fn to_frame(&self, stream_id: u32) -> ErrorFrame {
+        use Kind::*;
+        let code = match (&self.inner.kind, stream_id) {
+            (InvalidSetup, 0) => ErrorFrame::INVALID_SETUP,
+            (UnsupportedSetup, 0) => ErrorFrame::UNSUPPORTED_SETUP,
+            (RejectedSetup, 0) => ErrorFrame::REJECTED_SETUP,
+            (RejectedResume, 0) => ErrorFrame::REJECTED_RESUME,
+            (ConnectionClose, 0) => ErrorFrame::CONNECTION_CLOSE,
+            (_, 0) => ErrorFrame::CONNECTION_ERROR,
+            (Rejected, _) => ErrorFrame::REJECTED,
+            (Canceled, _) => ErrorFrame::CANCELED,
+            (Invalid, _) => ErrorFrame::INVALID,
+            (_, _) => ErrorFrame::APPLICATION_ERROR,
+        };
+        let data = match self.inner.source {
+            Some(ref source) => source.to_string(),
+            None => self.description().to_owned(),
+        };
+        ErrorFrame::new(stream_id, code, Some(Bytes::from(data)))
+    }
+
     fn description(&self) -> &str {
         use Kind::*;
         match &self.inner.kind {
@@ -211,7 +303,7 @@ impl Error {
             ConnectionError => "CONNECTION_ERROR (0x00000101)",
             ConnectionClose => "CONNECTION_CLOSE (0x00000102)",
             ApplicationError => "APPLICATION_ERROR (0x00000201)",
-            Rejected => "CONNECTION_ERROR (0x00000202)",
+            Rejected => "REJECTED (0x00000202)",
             Canceled => "CANCELED (0x00000203)",
             Invalid => "INVALID (0x00000204)",
             Decode(_) => "error decoding frame",
@@ -254,6 +346,26 @@ impl From<DecodeError> for Error {
     }
 }
 
+impl From<ErrorFrame> for Error {
+    fn from(frame: ErrorFrame) -> Error {
+        let kind = match frame.error_code() {
+            ErrorFrame::INVALID_SETUP => Kind::InvalidSetup,
+            ErrorFrame::UNSUPPORTED_SETUP => Kind::UnsupportedSetup,
+            ErrorFrame::REJECTED_SETUP => Kind::RejectedSetup,
+            ErrorFrame::REJECTED_RESUME => Kind::RejectedResume,
+            ErrorFrame::CONNECTION_ERROR => Kind::ConnectionError,
+            ErrorFrame::CONNECTION_CLOSE => Kind::ConnectionClose,
+            ErrorFrame::REJECTED => Kind::Rejected,
+            ErrorFrame::CANCELED => Kind::Canceled,
+            ErrorFrame::INVALID => Kind::Invalid,
+            // Custom application layer error codes are reported as `APPLICATION_ERROR`.
+            _ => Kind::ApplicationError,
+        };
+        let source = frame.data_utf8().map(ToOwned::to_owned);
+        Error::new(kind, source)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
         Error::new(Kind::Io, Some(e))
@@ -287,4 +399,37 @@ mod tests {
         }
         assert!(actual.inner.source.is_some());
     }
+
+    #[test]
+    fn from_error_frame() {
+        let frame = ErrorFrame::new(
+            1,
+            ErrorFrame::REJECTED,
+            Some(Bytes::from("busy")),
+        );
+        let actual: Error = frame.into();
+        assert!(actual.is_rejected());
+        assert_eq!(actual.to_string(), "REJECTED (0x00000202): busy");
+
+        let frame = ErrorFrame::new(1, 0x00000301, None);
+        let actual: Error = frame.into();
+        assert!(actual.is_application_error());
+        assert!(actual.inner.source.is_none());
+    }
+
+    #[test]
+    fn to_error_frame() {
+        let err = Error::new(Kind::Rejected, Some("busy"));
+        let frame = err.to_frame(1);
+        assert_eq!(frame.error_code(), ErrorFrame::REJECTED);
+        assert_eq!(frame.data_utf8(), Some("busy"));
+
+        let frame = err.to_frame(0);
+        assert_eq!(frame.error_code(), ErrorFrame::CONNECTION_ERROR);
+
+        let err = Error::new(Kind::ConnectionClose, None::<Error>);
+        let frame = err.to_frame(1);
+        assert_eq!(frame.error_code(), ErrorFrame::APPLICATION_ERROR);
+        assert_eq!(frame.data_utf8(), Some("CONNECTION_CLOSE (0x00000102)"));
+    }
 }
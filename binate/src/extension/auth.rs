@@ -0,0 +1,255 @@
+use super::invalid;
+use crate::error::Result;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+
+/// The identifier of the well-known `simple` authentication type.
+const SIMPLE_ID: u8 = 0x00;
+/// The identifier of the well-known `bearer` authentication type.
+const BEARER_ID: u8 = 0x01;
+
+/// Authentication metadata (`message/x.rsocket.authentication.v0`), which carries the
+/// credentials of a connection in its SETUP frame, or of a single request.
+///
+/// The `simple` and `bearer` authentication types are well-known, and are encoded as 7-bit
+/// identifiers. Any other type is encoded with its name, and its credentials are opaque.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::Authentication;
+///
+/// let auth = Authentication::Bearer("token".to_owned());
+/// let decoded = Authentication::decode(&auth.to_bytes()).unwrap();
+/// assert_eq!(decoded, auth);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub enum Authentication {
+    /// Authentication with a username and a password.
+    Simple {
+        /// The username, which is at most 65,535 bytes long.
+        username: String,
+        /// The password.
+        password: String,
+    },
+    /// Authentication with a bearer token.
+    Bearer(String),
+    /// Authentication of a custom type.
+    Custom {
+        /// The name of the authentication type, which is 1 to 128 ASCII characters long.
+        auth_type: String,
+        /// The credentials, whose format depends on the authentication type.
+        payload: Bytes,
+    },
+}
+
+impl Authentication {
+    /// Creates a `simple` authentication with the given username and password.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the username is longer than 65,535 bytes.
+    pub fn simple<U, P>(username: U, password: P) -> Authentication
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        let username = username.into();
+        assert!(username.len() <= u16::MAX as usize);
+        Authentication::Simple { username, password: password.into() }
+    }
+
+    /// Creates a `bearer` authentication with the given token.
+    pub fn bearer<T>(token: T) -> Authentication
+    where
+        T: Into<String>,
+    {
+        Authentication::Bearer(token.into())
+    }
+
+    /// Creates an authentication of the given custom type, with the given credentials.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the authentication type is not 1 to 128 ASCII characters long.
+    pub fn custom<T, P>(auth_type: T, payload: P) -> Authentication
+    where
+        T: Into<String>,
+        P: Into<Bytes>,
+    {
+        let auth_type = auth_type.into();
+        assert!(!auth_type.is_empty() && auth_type.len() <= 128);
+        assert!(auth_type.is_ascii());
+        Authentication::Custom { auth_type, payload: payload.into() }
+    }
+
+    /// Returns the name of the authentication type, e.g. `simple` or `bearer`.
+    pub fn auth_type(&self) -> &str {
+        match self {
+            Authentication::Simple { .. } => "simple",
+            Authentication::Bearer(_) => "bearer",
+            Authentication::Custom { auth_type, .. } => auth_type,
+        }
+    }
+
+    /// Decodes the given authentication metadata.
+    ///
+    /// An `INVALID` error is returned if the metadata is malformed, has a reserved identifier
+    /// that is not a well-known authentication type, or if the credentials of a well-known
+    /// type are not UTF-8.
+    pub fn decode(metadata: &Bytes) -> Result<Authentication> {
+        let mut buf = metadata.clone();
+        if !buf.has_remaining() {
+            return Err(invalid("empty authentication metadata"));
+        }
+        match buf.get_u8() {
+            id if id & 0x80 != 0 => match id & 0x7F {
+                SIMPLE_ID => {
+                    if buf.remaining() < 2 {
+                        return Err(invalid(
+                            "incomplete authentication metadata",
+                        ));
+                    }
+                    let len = buf.get_u16() as usize;
+                    if buf.remaining() < len {
+                        return Err(invalid(
+                            "incomplete authentication metadata",
+                        ));
+                    }
+                    let username = utf8(buf.split_to(len))?;
+                    let password = utf8(buf)?;
+                    Ok(Authentication::Simple { username, password })
+                }
+                BEARER_ID => Ok(Authentication::Bearer(utf8(buf)?)),
+                _ => Err(invalid("reserved authentication type identifier")),
+            },
+            len => {
+                let len = len as usize + 1;
+                if buf.remaining() < len {
+                    return Err(invalid("incomplete authentication metadata"));
+                }
+                let auth_type = buf.split_to(len);
+                match std::str::from_utf8(&auth_type) {
+                    Ok(auth_type) if auth_type.is_ascii() => {
+                        Ok(Authentication::Custom {
+                            auth_type: auth_type.to_owned(),
+                            payload: buf,
+                        })
+                    }
+                    _ => Err(invalid("authentication type is not ASCII")),
+                }
+            }
+        }
+    }
+
+    /// Encodes the authentication into authentication metadata.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the username of a `simple` authentication is longer than 65,535
+    /// bytes, or if the type of a custom authentication is not 1 to 128 ASCII characters long.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        match self {
+            Authentication::Simple { username, password } => {
+                assert!(username.len() <= u16::MAX as usize);
+                buf.reserve(3 + username.len() + password.len());
+                buf.put_u8(0x80 | SIMPLE_ID);
+                buf.put_u16(username.len() as u16);
+                buf.put_slice(username.as_bytes());
+                buf.put_slice(password.as_bytes());
+            }
+            Authentication::Bearer(token) => {
+                buf.reserve(1 + token.len());
+                buf.put_u8(0x80 | BEARER_ID);
+                buf.put_slice(token.as_bytes());
+            }
+            Authentication::Custom { auth_type, payload } => {
+                assert!(!auth_type.is_empty() && auth_type.len() <= 128);
+                assert!(auth_type.is_ascii());
+                buf.reserve(1 + auth_type.len() + payload.len());
+                buf.put_u8(auth_type.len() as u8 - 1);
+                buf.put_slice(auth_type.as_bytes());
+                buf.put_slice(payload);
+            }
+        }
+        buf.freeze()
+    }
+}
+
+fn utf8(bytes: Bytes) -> Result<String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| invalid("credentials are not UTF-8"))
+}
+
+impl fmt::Debug for Authentication {
+    // The credentials are never printed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Authentication::Simple { username, .. } => f
+                .debug_struct("Simple")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Authentication::Bearer(_) => f.write_str("Bearer(..)"),
+            Authentication::Custom { auth_type, .. } => f
+                .debug_struct("Custom")
+                .field("auth_type", auth_type)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple() {
+        let auth = Authentication::simple("user", "pass");
+        let bytes = auth.to_bytes();
+        assert_eq!(bytes, Bytes::from_static(b"\x80\x00\x04userpass"));
+        assert_eq!(Authentication::decode(&bytes).unwrap(), auth);
+        assert_eq!(auth.auth_type(), "simple");
+        assert_eq!(format!("{:?}", auth), "Simple { username: \"user\", .. }");
+    }
+
+    #[test]
+    fn bearer() {
+        let auth = Authentication::bearer("token");
+        let bytes = auth.to_bytes();
+        assert_eq!(bytes, Bytes::from_static(b"\x81token"));
+        assert_eq!(Authentication::decode(&bytes).unwrap(), auth);
+        assert_eq!(auth.auth_type(), "bearer");
+    }
+
+    #[test]
+    fn custom() {
+        let auth = Authentication::custom("x-key", "\x00\x01");
+        let bytes = auth.to_bytes();
+        assert_eq!(bytes, Bytes::from_static(b"\x04x-key\x00\x01"));
+        assert_eq!(Authentication::decode(&bytes).unwrap(), auth);
+        assert_eq!(auth.auth_type(), "x-key");
+    }
+
+    #[test]
+    fn decode_malformed() {
+        for bytes in [
+            &b""[..],
+            &b"\x80\x00"[..],
+            &b"\x80\x00\x05user"[..],
+            &b"\x81\xFF"[..],
+            &b"\x82token"[..],
+            &b"\x04x-ke"[..],
+        ] {
+            let err = Authentication::decode(&Bytes::from(bytes)).unwrap_err();
+            assert!(err.is_invalid());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_auth_type() {
+        Authentication::custom("", "");
+    }
+}
@@ -0,0 +1,315 @@
+use super::{
+    check_mime_type, decode_mime_type, encode_mime_type, invalid,
+    mime_type_len,
+};
+use crate::error::Result;
+use crate::mimetype::MimeType;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::iter::FromIterator;
+use std::slice;
+
+/// The largest length of the metadata of an entry, which is a 24-bit unsigned integer.
+const MAX_CONTENT_LEN: usize = 0xFF_FFFF;
+
+/// Composite metadata (`message/x.rsocket.composite-metadata.v0`), which carries several
+/// metadata entries of different MIME types in the metadata of a single payload.
+///
+/// Each entry is prefixed with its MIME type, which is encoded as a 7-bit identifier if it is
+/// well-known, or as a string otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::CompositeMetadata;
+/// use binate::mimetype::WellKnownMimeType;
+///
+/// let mut metadata = CompositeMetadata::new();
+/// metadata.push(WellKnownMimeType::MESSAGE_X_RSOCKET_ROUTING_V0, "\x04echo");
+/// metadata.push("application/x-custom", "custom");
+///
+/// let decoded = CompositeMetadata::decode(&metadata.to_bytes()).unwrap();
+/// assert_eq!(decoded, metadata);
+/// assert_eq!(decoded.get("application/x-custom").unwrap(), "custom");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompositeMetadata {
+    entries: Vec<MetadataEntry>,
+}
+
+/// An entry of [`CompositeMetadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataEntry {
+    mime_type: MimeType,
+    content: Bytes,
+}
+
+impl CompositeMetadata {
+    /// Creates an empty `CompositeMetadata`.
+    pub fn new() -> CompositeMetadata {
+        CompositeMetadata::default()
+    }
+
+    /// Appends an entry with the given MIME type and content.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the MIME type is neither well-known nor a custom string of 1 to
+    /// 128 ASCII characters, or if the content is longer than 16,777,215 bytes.
+    pub fn push<M, C>(&mut self, mime_type: M, content: C)
+    where
+        M: Into<MimeType>,
+        C: Into<Bytes>,
+    {
+        self.entries.push(MetadataEntry::new(mime_type, content));
+    }
+
+    /// Returns the content of the first entry of the given MIME type, if any.
+    pub fn get<M>(&self, mime_type: M) -> Option<&Bytes>
+    where
+        M: Into<MimeType>,
+    {
+        let mime_type = mime_type.into();
+        self.iter()
+            .find(|entry| entry.mime_type == mime_type)
+            .map(|entry| &entry.content)
+    }
+
+    /// Returns an iterator over the entries, in the order that they are encoded.
+    pub fn iter(&self) -> slice::Iter<'_, MetadataEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Decodes the given composite metadata.
+    ///
+    /// The contents of the entries share the given bytes rather than being copied. An `INVALID`
+    /// error is returned if the metadata is malformed. Entries whose identifier is not a
+    /// well-known MIME type are kept as [`MimeType::Reserved`] entries.
+    pub fn decode(metadata: &Bytes) -> Result<CompositeMetadata> {
+        let mut buf = metadata.clone();
+        let mut entries = Vec::new();
+        while buf.has_remaining() {
+            let mime_type = decode_mime_type(&mut buf)?;
+            if buf.remaining() < 3 {
+                return Err(invalid("incomplete composite metadata"));
+            }
+            let len = buf.get_uint(3) as usize;
+            if buf.remaining() < len {
+                return Err(invalid("incomplete composite metadata"));
+            }
+            let content = buf.split_to(len);
+            entries.push(MetadataEntry { mime_type, content });
+        }
+        Ok(CompositeMetadata { entries })
+    }
+
+    /// Encodes the entries into composite metadata.
+    pub fn to_bytes(&self) -> Bytes {
+        let len = self.iter().map(MetadataEntry::encoded_len).sum();
+        let mut buf = BytesMut::with_capacity(len);
+        for entry in self.iter() {
+            entry.encode(&mut buf);
+        }
+        buf.freeze()
+    }
+}
+
+impl<'a> IntoIterator for &'a CompositeMetadata {
+    type Item = &'a MetadataEntry;
+    type IntoIter = slice::Iter<'a, MetadataEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for CompositeMetadata {
+    type Item = MetadataEntry;
+    type IntoIter = std::vec::IntoIter<MetadataEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<MetadataEntry> for CompositeMetadata {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = MetadataEntry>,
+    {
+        CompositeMetadata { entries: iter.into_iter().collect() }
+    }
+}
+
+impl MetadataEntry {
+    /// Creates a new entry with the given MIME type and content.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the MIME type is neither well-known nor a custom string of 1 to
+    /// 128 ASCII characters, or if the content is longer than 16,777,215 bytes.
+    pub fn new<M, C>(mime_type: M, content: C) -> MetadataEntry
+    where
+        M: Into<MimeType>,
+        C: Into<Bytes>,
+    {
+        let mime_type = mime_type.into();
+        check_mime_type(&mime_type);
+        let content = content.into();
+        assert!(content.len() <= MAX_CONTENT_LEN);
+        MetadataEntry { mime_type, content }
+    }
+
+    /// Returns the MIME type of this entry.
+    pub fn mime_type(&self) -> &MimeType {
+        &self.mime_type
+    }
+
+    /// Returns the content of this entry.
+    pub fn content(&self) -> &Bytes {
+        &self.content
+    }
+
+    fn encoded_len(&self) -> usize {
+        mime_type_len(&self.mime_type) + 3 + self.content.len()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        encode_mime_type(&self.mime_type, buf);
+        buf.put_uint(self.content.len() as u64, 3);
+        buf.put_slice(&self.content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::metadata_entry;
+    use crate::mimetype::WellKnownMimeType::*;
+
+    #[test]
+    fn encode() {
+        let mut metadata = CompositeMetadata::new();
+        metadata.push(MESSAGE_X_RSOCKET_ROUTING_V0, "\x04echo");
+        metadata.push("text/x-a", "b");
+        assert_eq!(
+            metadata.to_bytes(),
+            Bytes::from_static(
+                b"\xFE\x00\x00\x05\x04echo\x07text/x-a\x00\x00\x01b"
+            )
+        );
+    }
+
+    #[test]
+    fn decode() {
+        // A well-known `application/json` entry, followed by a custom one.
+        let bytes = Bytes::from_static(
+            b"\x85\x00\x00\x02{}\x0Btext/x-empty\x00\x00\x00",
+        );
+        let metadata = CompositeMetadata::decode(&bytes).unwrap();
+        assert_eq!(metadata.len(), 2);
+        let mut entries = metadata.iter();
+        let entry = entries.next().unwrap();
+        assert_eq!(entry.mime_type(), &MimeType::WellKnown(APPLICATION_JSON));
+        assert_eq!(entry.content(), "{}");
+        let entry = entries.next().unwrap();
+        assert_eq!(
+            entry.mime_type(),
+            &MimeType::Custom("text/x-empty".to_owned())
+        );
+        assert!(entry.content().is_empty());
+        assert!(entries.next().is_none());
+
+        assert_eq!(metadata.to_bytes(), bytes);
+        assert!(CompositeMetadata::decode(&Bytes::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_well_known_string() {
+        // A routing entry whose MIME type is sent as a string rather than an identifier.
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(MESSAGE_X_RSOCKET_ROUTING_V0.as_str().len() as u8 - 1);
+        bytes.put_slice(MESSAGE_X_RSOCKET_ROUTING_V0.as_str().as_bytes());
+        bytes.put_slice(b"\x00\x00\x05\x04echo");
+        let bytes = bytes.freeze();
+        let metadata = CompositeMetadata::decode(&bytes).unwrap();
+        let entry = metadata.iter().next().unwrap();
+        assert_eq!(
+            entry.mime_type(),
+            &MimeType::WellKnown(MESSAGE_X_RSOCKET_ROUTING_V0)
+        );
+        assert_eq!(
+            metadata.get(MESSAGE_X_RSOCKET_ROUTING_V0).unwrap(),
+            "\x04echo"
+        );
+        let entry = metadata_entry(
+            MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0,
+            Some(&bytes),
+            MESSAGE_X_RSOCKET_ROUTING_V0,
+        );
+        assert_eq!(entry.unwrap().unwrap(), "\x04echo");
+    }
+
+    #[test]
+    fn decode_reserved() {
+        // An entry with an identifier that is not a well-known MIME type.
+        let bytes = Bytes::from_static(b"\xD0\x00\x00\x01a\x85\x00\x00\x02{}");
+        let metadata = CompositeMetadata::decode(&bytes).unwrap();
+        assert_eq!(metadata.len(), 2);
+        let entry = metadata.iter().next().unwrap();
+        assert_eq!(entry.mime_type(), &MimeType::Reserved(0x50));
+        assert_eq!(entry.mime_type().as_str(), "");
+        assert_eq!(entry.content(), "a");
+        assert_eq!(metadata.get(APPLICATION_JSON).unwrap(), "{}");
+        assert_eq!(metadata.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn decode_malformed() {
+        for bytes in [
+            &b"\x85\x00\x00"[..],
+            &b"\x85\x00\x00\x02{"[..],
+            &b"\x05text"[..],
+            &b"\x00\xFF\x00\x00\x00"[..],
+        ] {
+            let err =
+                CompositeMetadata::decode(&Bytes::from(bytes)).unwrap_err();
+            assert!(err.is_invalid());
+        }
+    }
+
+    #[test]
+    fn get() {
+        let metadata: CompositeMetadata = vec![
+            MetadataEntry::new(APPLICATION_JSON, "1"),
+            MetadataEntry::new(APPLICATION_JSON, "2"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(metadata.get(APPLICATION_JSON).unwrap(), "1");
+        assert_eq!(metadata.get("application/json").unwrap(), "1");
+        assert!(metadata.get(APPLICATION_CBOR).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unparseable_mime_type() {
+        MetadataEntry::new(UNPARSEABLE, "");
+    }
+
+    #[test]
+    #[should_panic]
+    fn mime_type_too_long() {
+        MetadataEntry::new("a".repeat(129).as_str(), "");
+    }
+}
@@ -0,0 +1,421 @@
+use super::{
+    check_mime_type, decode_mime_type, encode_mime_type, invalid,
+    metadata_entry, mime_type_len,
+};
+use crate::error::Result;
+use crate::mimetype::{MimeType, WellKnownMimeType};
+use crate::payload::Payload;
+use crate::SetupFrame;
+
+use bytes::{Buf, Bytes, BytesMut};
+use std::iter::FromIterator;
+use std::slice;
+
+/// Per-stream data MIME type metadata (`message/x.rsocket.mime-type.v0`), which overrides the
+/// data MIME type of the connection for the payloads of a single stream.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::MimeTypeMetadata;
+/// use binate::mimetype::WellKnownMimeType;
+///
+/// let metadata = MimeTypeMetadata::new(WellKnownMimeType::APPLICATION_CBOR);
+/// let decoded = MimeTypeMetadata::decode(&metadata.to_bytes()).unwrap();
+/// assert_eq!(decoded, metadata);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimeTypeMetadata {
+    mime_type: MimeType,
+}
+
+impl MimeTypeMetadata {
+    /// Creates a new `MimeTypeMetadata` with the given MIME type.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the MIME type is neither well-known nor a custom string of 1 to
+    /// 128 ASCII characters.
+    pub fn new<M>(mime_type: M) -> MimeTypeMetadata
+    where
+        M: Into<MimeType>,
+    {
+        let mime_type = mime_type.into();
+        check_mime_type(&mime_type);
+        MimeTypeMetadata { mime_type }
+    }
+
+    /// Returns the MIME type.
+    pub fn mime_type(&self) -> &MimeType {
+        &self.mime_type
+    }
+
+    /// Decodes the given MIME type metadata.
+    ///
+    /// An `INVALID` error is returned if the metadata is malformed. Identifiers that are not
+    /// well-known MIME types are kept as [`MimeType::Reserved`].
+    pub fn decode(metadata: &Bytes) -> Result<MimeTypeMetadata> {
+        let mut buf = metadata.clone();
+        let mime_type = decode_mime_type(&mut buf)?;
+        if buf.has_remaining() {
+            return Err(invalid("trailing bytes after MIME type"));
+        }
+        Ok(MimeTypeMetadata { mime_type })
+    }
+
+    /// Encodes the MIME type into MIME type metadata.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(mime_type_len(&self.mime_type));
+        encode_mime_type(&self.mime_type, &mut buf);
+        buf.freeze()
+    }
+}
+
+/// Accepted MIME types metadata (`message/x.rsocket.accept-mime-types.v0`), which lists the
+/// data MIME types that a requester accepts in the responses to a request, most preferred
+/// first.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::AcceptMimeTypes;
+/// use binate::mimetype::WellKnownMimeType;
+///
+/// let mut metadata = AcceptMimeTypes::new();
+/// metadata.push(WellKnownMimeType::APPLICATION_CBOR);
+/// metadata.push("application/x-custom");
+///
+/// let decoded = AcceptMimeTypes::decode(&metadata.to_bytes()).unwrap();
+/// assert!(decoded.contains(WellKnownMimeType::APPLICATION_CBOR));
+/// assert!(!decoded.contains(WellKnownMimeType::APPLICATION_JSON));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AcceptMimeTypes {
+    mime_types: Vec<MimeType>,
+}
+
+impl AcceptMimeTypes {
+    /// Creates an empty `AcceptMimeTypes`.
+    pub fn new() -> AcceptMimeTypes {
+        AcceptMimeTypes::default()
+    }
+
+    /// Appends an accepted MIME type.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the MIME type is neither well-known nor a custom string of 1 to
+    /// 128 ASCII characters.
+    pub fn push<M>(&mut self, mime_type: M)
+    where
+        M: Into<MimeType>,
+    {
+        let mime_type = mime_type.into();
+        check_mime_type(&mime_type);
+        self.mime_types.push(mime_type);
+    }
+
+    /// Returns true if the given MIME type is accepted.
+    pub fn contains<M>(&self, mime_type: M) -> bool
+    where
+        M: Into<MimeType>,
+    {
+        self.mime_types.contains(&mime_type.into())
+    }
+
+    /// Returns an iterator over the accepted MIME types, most preferred first.
+    pub fn iter(&self) -> slice::Iter<'_, MimeType> {
+        self.mime_types.iter()
+    }
+
+    /// Returns the number of accepted MIME types.
+    pub fn len(&self) -> usize {
+        self.mime_types.len()
+    }
+
+    /// Returns true if there are no accepted MIME types.
+    pub fn is_empty(&self) -> bool {
+        self.mime_types.is_empty()
+    }
+
+    /// Decodes the given accepted MIME types metadata.
+    ///
+    /// An `INVALID` error is returned if the metadata is malformed. Identifiers that are not
+    /// well-known MIME types are kept as [`MimeType::Reserved`].
+    pub fn decode(metadata: &Bytes) -> Result<AcceptMimeTypes> {
+        let mut buf = metadata.clone();
+        let mut mime_types = Vec::new();
+        while buf.has_remaining() {
+            mime_types.push(decode_mime_type(&mut buf)?);
+        }
+        Ok(AcceptMimeTypes { mime_types })
+    }
+
+    /// Encodes the accepted MIME types into accepted MIME types metadata.
+    pub fn to_bytes(&self) -> Bytes {
+        let len = self.iter().map(mime_type_len).sum();
+        let mut buf = BytesMut::with_capacity(len);
+        for mime_type in self.iter() {
+            encode_mime_type(mime_type, &mut buf);
+        }
+        buf.freeze()
+    }
+}
+
+impl<M: Into<MimeType>> FromIterator<M> for AcceptMimeTypes {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+    {
+        let mut metadata = AcceptMimeTypes::new();
+        for mime_type in iter {
+            metadata.push(mime_type);
+        }
+        metadata
+    }
+}
+
+impl<'a> IntoIterator for &'a AcceptMimeTypes {
+    type Item = &'a MimeType;
+    type IntoIter = slice::Iter<'a, MimeType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The MIME types of a connection, as set up by its SETUP frame, which resolves the MIME types
+/// of each request.
+///
+/// Responders that handle several data MIME types on one connection keep the
+/// `ConnectionMimeTypes` of the SETUP frame given to the
+/// [`SocketAcceptor`](crate::SocketAcceptor), and look up the MIME types of each request with
+/// it. The per-stream MIME types are only found in requests with composite metadata, or with
+/// metadata of their own MIME type.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::{CompositeMetadata, ConnectionMimeTypes, MimeTypeMetadata};
+/// use binate::mimetype::{MimeType, WellKnownMimeType::*};
+/// use binate::{Payload, SetupFrame};
+///
+/// let setup = SetupFrame::builder()
+///     .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0")
+///     .set_data_mimetype("application/json")
+///     .build();
+/// let mime_types = ConnectionMimeTypes::from_setup(&setup);
+/// assert_eq!(
+///     mime_types.metadata_mime_type(),
+///     &MimeType::WellKnown(MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0),
+/// );
+///
+/// let mut metadata = CompositeMetadata::new();
+/// metadata.push(
+///     MESSAGE_X_RSOCKET_MIME_TYPE_V0,
+///     MimeTypeMetadata::new(APPLICATION_CBOR).to_bytes(),
+/// );
+/// let request = Payload::builder().set_metadata(metadata.to_bytes()).build();
+/// assert_eq!(
+///     mime_types.data_mime_type(&request).unwrap(),
+///     MimeType::WellKnown(APPLICATION_CBOR),
+/// );
+///
+/// let request = Payload::builder().build();
+/// assert_eq!(
+///     mime_types.data_mime_type(&request).unwrap(),
+///     MimeType::WellKnown(APPLICATION_JSON),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionMimeTypes {
+    metadata_mime_type: MimeType,
+    data_mime_type: MimeType,
+}
+
+impl ConnectionMimeTypes {
+    /// Creates a new `ConnectionMimeTypes` from the MIME types of the given SETUP frame.
+    pub fn from_setup(setup: &SetupFrame) -> ConnectionMimeTypes {
+        let mime_type = |mime_type: Option<&str>| {
+            MimeType::from(mime_type.unwrap_or_default())
+        };
+        ConnectionMimeTypes {
+            metadata_mime_type: mime_type(setup.metadata_mimetype()),
+            data_mime_type: mime_type(setup.data_mimetype()),
+        }
+    }
+
+    /// Returns the metadata MIME type of the connection.
+    pub fn metadata_mime_type(&self) -> &MimeType {
+        &self.metadata_mime_type
+    }
+
+    /// Returns the data MIME type of the connection.
+    pub fn connection_data_mime_type(&self) -> &MimeType {
+        &self.data_mime_type
+    }
+
+    /// Returns the data MIME type of the given request, which is the per-stream MIME type in its
+    /// metadata if any, and the data MIME type of the connection otherwise.
+    ///
+    /// An `INVALID` error is returned if the metadata of the request is malformed.
+    pub fn data_mime_type(&self, request: &Payload) -> Result<MimeType> {
+        let metadata = self.entry(
+            request,
+            WellKnownMimeType::MESSAGE_X_RSOCKET_MIME_TYPE_V0,
+        )?;
+        match metadata {
+            Some(metadata) => {
+                Ok(MimeTypeMetadata::decode(&metadata)?.mime_type)
+            }
+            None => Ok(self.data_mime_type.clone()),
+        }
+    }
+
+    /// Returns the data MIME types accepted in the responses to the given request, which are
+    /// the accepted MIME types in its metadata if any, and the data MIME type of the connection
+    /// otherwise.
+    ///
+    /// An `INVALID` error is returned if the metadata of the request is malformed.
+    pub fn accept_mime_types(
+        &self,
+        request: &Payload,
+    ) -> Result<AcceptMimeTypes> {
+        let metadata = self.entry(
+            request,
+            WellKnownMimeType::MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0,
+        )?;
+        match metadata {
+            Some(metadata) => AcceptMimeTypes::decode(&metadata),
+            None => Ok(AcceptMimeTypes {
+                mime_types: vec![self.data_mime_type.clone()],
+            }),
+        }
+    }
+
+    fn entry(
+        &self,
+        request: &Payload,
+        mime_type: WellKnownMimeType,
+    ) -> Result<Option<Bytes>> {
+        let metadata_mime_type = match self.metadata_mime_type {
+            MimeType::WellKnown(mime_type) => mime_type,
+            MimeType::Custom(_) | MimeType::Reserved(_) => return Ok(None),
+        };
+        metadata_entry(metadata_mime_type, request.metadata(), mime_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::CompositeMetadata;
+    use WellKnownMimeType::*;
+
+    use bytes::BufMut;
+
+    #[test]
+    fn mime_type() {
+        let metadata = MimeTypeMetadata::new(APPLICATION_CBOR);
+        assert_eq!(metadata.to_bytes(), Bytes::from_static(b"\x81"));
+        let decoded = MimeTypeMetadata::decode(&metadata.to_bytes()).unwrap();
+        assert_eq!(
+            decoded.mime_type(),
+            &MimeType::WellKnown(APPLICATION_CBOR)
+        );
+
+        let metadata = MimeTypeMetadata::new("text/x-a");
+        assert_eq!(metadata.to_bytes(), Bytes::from_static(b"\x07text/x-a"));
+        let decoded = MimeTypeMetadata::decode(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded, metadata);
+
+        for bytes in [&b""[..], &b"\x81\x82"[..], &b"\x07text"[..]] {
+            let err =
+                MimeTypeMetadata::decode(&Bytes::from(bytes)).unwrap_err();
+            assert!(err.is_invalid());
+        }
+    }
+
+    #[test]
+    fn accept_mime_types() {
+        let metadata: AcceptMimeTypes =
+            vec![MimeType::from(APPLICATION_CBOR), MimeType::from("text/x-a")]
+                .into_iter()
+                .collect();
+        let bytes = metadata.to_bytes();
+        assert_eq!(bytes, Bytes::from_static(b"\x81\x07text/x-a"));
+        let decoded = AcceptMimeTypes::decode(&bytes).unwrap();
+        assert_eq!(decoded, metadata);
+        assert!(decoded.contains("text/x-a"));
+        assert!(!decoded.contains(APPLICATION_JSON));
+        assert!(AcceptMimeTypes::decode(&Bytes::new()).unwrap().is_empty());
+
+        let bytes = Bytes::from_static(b"\x81\xD0");
+        let decoded = AcceptMimeTypes::decode(&bytes).unwrap();
+        assert!(decoded.contains(APPLICATION_CBOR));
+        assert!(decoded.contains(MimeType::Reserved(0x50)));
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn connection_mime_types() {
+        let setup = SetupFrame::builder()
+            .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0")
+            .set_data_mimetype("application/json")
+            .build();
+        let mime_types = ConnectionMimeTypes::from_setup(&setup);
+        assert_eq!(
+            mime_types.metadata_mime_type(),
+            &MimeType::WellKnown(MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0)
+        );
+        assert_eq!(
+            mime_types.connection_data_mime_type(),
+            &MimeType::WellKnown(APPLICATION_JSON)
+        );
+
+        let mut metadata = CompositeMetadata::new();
+        metadata.push(
+            MESSAGE_X_RSOCKET_MIME_TYPE_V0,
+            MimeTypeMetadata::new(APPLICATION_CBOR).to_bytes(),
+        );
+        let accept: AcceptMimeTypes =
+            vec![APPLICATION_CBOR].into_iter().collect();
+        metadata
+            .push(MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0, accept.to_bytes());
+        let request =
+            Payload::builder().set_metadata(metadata.to_bytes()).build();
+        assert_eq!(
+            mime_types.data_mime_type(&request).unwrap(),
+            MimeType::WellKnown(APPLICATION_CBOR)
+        );
+        assert_eq!(mime_types.accept_mime_types(&request).unwrap(), accept);
+
+        // The MIME types of the connection apply to requests without their own.
+        let request = Payload::builder().set_data("{}").build();
+        assert_eq!(
+            mime_types.data_mime_type(&request).unwrap(),
+            MimeType::WellKnown(APPLICATION_JSON)
+        );
+        let accept = mime_types.accept_mime_types(&request).unwrap();
+        assert!(accept.contains(APPLICATION_JSON));
+        assert_eq!(accept.len(), 1);
+
+        // An entry may name its MIME type rather than use its identifier.
+        let mut metadata = BytesMut::new();
+        let name = "message/x.rsocket.mime-type.v0";
+        metadata.put_u8(name.len() as u8 - 1);
+        metadata.put_slice(name.as_bytes());
+        metadata.put_slice(b"\x00\x00\x01\x81");
+        let request =
+            Payload::builder().set_metadata(metadata.freeze()).build();
+        assert_eq!(
+            mime_types.data_mime_type(&request).unwrap(),
+            MimeType::WellKnown(APPLICATION_CBOR)
+        );
+
+        let request = Payload::builder()
+            .set_metadata(Bytes::from_static(b"\x81"))
+            .build();
+        assert!(mime_types.data_mime_type(&request).unwrap_err().is_invalid());
+    }
+}
@@ -0,0 +1,112 @@
+//! Codecs for the metadata extensions of the RSocket protocol.
+//!
+//! See the [extensions] of the RSocket protocol for the formats of the metadata.
+//!
+//! [extensions]: https://github.com/rsocket/rsocket/tree/master/Extensions
+mod auth;
+mod composite;
+mod mime;
+mod routing;
+mod zipkin;
+
+pub use self::auth::Authentication;
+pub use self::composite::{CompositeMetadata, MetadataEntry};
+pub use self::mime::{AcceptMimeTypes, ConnectionMimeTypes, MimeTypeMetadata};
+pub use self::routing::RoutingMetadata;
+pub use self::zipkin::{Sampling, TraceId, ZipkinMetadata};
+
+use crate::error::{Code, Error, Result};
+use crate::mimetype::{MimeType, WellKnownMimeType};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Returns the error for metadata that cannot be decoded.
+fn invalid(reason: &'static str) -> Error {
+    Error::with_code(Code::Invalid, reason)
+}
+
+/// Returns the metadata of the given MIME type in the metadata of a frame, if any.
+///
+/// The metadata of the frame is either composite metadata, where the first entry of the given
+/// MIME type is returned, or metadata of the given MIME type itself.
+pub(crate) fn metadata_entry(
+    metadata_mime_type: WellKnownMimeType,
+    metadata: Option<&Bytes>,
+    mime_type: WellKnownMimeType,
+) -> Result<Option<Bytes>> {
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    match metadata_mime_type {
+        WellKnownMimeType::MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0 => {
+            let composite = CompositeMetadata::decode(metadata)?;
+            Ok(composite.get(mime_type).cloned())
+        }
+        _ if metadata_mime_type == mime_type => Ok(Some(metadata.clone())),
+        _ => Ok(None),
+    }
+}
+
+/// Asserts that the given MIME type can be encoded by [`encode_mime_type`].
+fn check_mime_type(mime_type: &MimeType) {
+    match mime_type {
+        MimeType::WellKnown(mime_type) => assert!(mime_type.id().is_some()),
+        MimeType::Custom(mime_type) => {
+            assert!(!mime_type.is_empty() && mime_type.len() <= 128);
+            assert!(mime_type.is_ascii());
+        }
+        MimeType::Reserved(id) => assert!(*id < 0x80),
+    }
+}
+
+/// Returns the length of the given MIME type, once encoded by [`encode_mime_type`].
+fn mime_type_len(mime_type: &MimeType) -> usize {
+    match mime_type {
+        MimeType::WellKnown(_) | MimeType::Reserved(_) => 1,
+        MimeType::Custom(mime_type) => 1 + mime_type.len(),
+    }
+}
+
+/// Encodes the given MIME type as its 7-bit identifier if it is well-known or reserved, or as
+/// its length (minus 1) followed by the string otherwise.
+fn encode_mime_type(mime_type: &MimeType, buf: &mut BytesMut) {
+    match mime_type {
+        MimeType::WellKnown(mime_type) => {
+            buf.put_u8(0x80 | mime_type.id().unwrap());
+        }
+        MimeType::Reserved(id) => buf.put_u8(0x80 | id),
+        MimeType::Custom(mime_type) => {
+            buf.put_u8(mime_type.len() as u8 - 1);
+            buf.put_slice(mime_type.as_bytes());
+        }
+    }
+}
+
+/// Decodes a MIME type encoded by [`encode_mime_type`].
+///
+/// An identifier that is not a well-known MIME type is returned as a reserved MIME type rather
+/// than an error, so that metadata from a peer that knows more MIME types can still be decoded.
+/// A well-known MIME type sent as a string is returned as the well-known MIME type.
+fn decode_mime_type(buf: &mut Bytes) -> Result<MimeType> {
+    if !buf.has_remaining() {
+        return Err(invalid("incomplete MIME type"));
+    }
+    match buf.get_u8() {
+        id if id & 0x80 != 0 => match WellKnownMimeType::from_id(id & 0x7F) {
+            Some(mime_type) => Ok(MimeType::WellKnown(mime_type)),
+            None => Ok(MimeType::Reserved(id & 0x7F)),
+        },
+        len => {
+            let len = len as usize + 1;
+            if buf.remaining() < len {
+                return Err(invalid("incomplete MIME type"));
+            }
+            let mime_type = buf.split_to(len);
+            match std::str::from_utf8(&mime_type) {
+                Ok(mime_type) if mime_type.is_ascii() => Ok(mime_type.into()),
+                _ => Err(invalid("MIME type is not ASCII")),
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::invalid;
+use crate::error::Result;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::iter::FromIterator;
+use std::slice;
+
+/// Routing metadata (`message/x.rsocket.routing.v0`), which carries the tags that a request is
+/// routed by, most significant first.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::RoutingMetadata;
+///
+/// let metadata = RoutingMetadata::from("users.find");
+/// let decoded = RoutingMetadata::decode(&metadata.to_bytes()).unwrap();
+/// assert_eq!(decoded.route(), Some("users.find"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingMetadata {
+    tags: Vec<String>,
+}
+
+impl RoutingMetadata {
+    /// Creates an empty `RoutingMetadata`.
+    pub fn new() -> RoutingMetadata {
+        RoutingMetadata::default()
+    }
+
+    /// Appends a tag.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the tag is empty, or longer than 255 bytes.
+    pub fn push<T>(&mut self, tag: T)
+    where
+        T: Into<String>,
+    {
+        let tag = tag.into();
+        assert!(!tag.is_empty() && tag.len() <= 255);
+        self.tags.push(tag);
+    }
+
+    /// Returns the first tag, which is the route of the request by convention.
+    pub fn route(&self) -> Option<&str> {
+        self.tags.first().map(String::as_str)
+    }
+
+    /// Returns an iterator over the tags.
+    pub fn iter(&self) -> slice::Iter<'_, String> {
+        self.tags.iter()
+    }
+
+    /// Returns the number of tags.
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    /// Returns true if there are no tags.
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Decodes the given routing metadata.
+    ///
+    /// An `INVALID` error is returned if the metadata is malformed, or a tag is not UTF-8.
+    pub fn decode(metadata: &Bytes) -> Result<RoutingMetadata> {
+        let mut buf = metadata.clone();
+        let mut tags = Vec::new();
+        while buf.has_remaining() {
+            let len = buf.get_u8() as usize;
+            if len == 0 || buf.remaining() < len {
+                return Err(invalid("malformed routing metadata"));
+            }
+            let tag = buf.split_to(len);
+            match std::str::from_utf8(&tag) {
+                Ok(tag) => tags.push(tag.to_owned()),
+                Err(_) => return Err(invalid("routing tag is not UTF-8")),
+            }
+        }
+        Ok(RoutingMetadata { tags })
+    }
+
+    /// Encodes the tags into routing metadata.
+    pub fn to_bytes(&self) -> Bytes {
+        let len = self.iter().map(|tag| 1 + tag.len()).sum();
+        let mut buf = BytesMut::with_capacity(len);
+        for tag in self.iter() {
+            buf.put_u8(tag.len() as u8);
+            buf.put_slice(tag.as_bytes());
+        }
+        buf.freeze()
+    }
+}
+
+impl From<&str> for RoutingMetadata {
+    /// Creates a `RoutingMetadata` with the given route as its only tag.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the route is empty, or longer than 255 bytes.
+    fn from(route: &str) -> RoutingMetadata {
+        let mut metadata = RoutingMetadata::new();
+        metadata.push(route);
+        metadata
+    }
+}
+
+impl<T: Into<String>> FromIterator<T> for RoutingMetadata {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut metadata = RoutingMetadata::new();
+        for tag in iter {
+            metadata.push(tag);
+        }
+        metadata
+    }
+}
+
+impl<'a> IntoIterator for &'a RoutingMetadata {
+    type Item = &'a String;
+    type IntoIter = slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let metadata: RoutingMetadata =
+            vec!["echo", "v1"].into_iter().collect();
+        let bytes = metadata.to_bytes();
+        assert_eq!(bytes, Bytes::from_static(b"\x04echo\x02v1"));
+        let decoded = RoutingMetadata::decode(&bytes).unwrap();
+        assert_eq!(decoded, metadata);
+        assert_eq!(decoded.route(), Some("echo"));
+        assert!(RoutingMetadata::decode(&Bytes::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_malformed() {
+        for bytes in [&b"\x05echo"[..], &b"\x00"[..], &b"\x01\xFF"[..]] {
+            let err =
+                RoutingMetadata::decode(&Bytes::from(bytes)).unwrap_err();
+            assert!(err.is_invalid());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn tag_too_long() {
+        RoutingMetadata::new().push("a".repeat(256));
+    }
+}
@@ -0,0 +1,253 @@
+use super::invalid;
+use crate::error::Result;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt;
+
+const FLAG_EXTENDED_TRACE_ID: u8 = 0b0000_1000;
+const FLAG_INCLUDE_PARENT_ID: u8 = 0b0000_0100;
+const FLAG_NOT_SAMPLED: u8 = 0b0001_0000;
+const FLAG_SAMPLED: u8 = 0b0010_0000;
+const FLAG_DEBUG: u8 = 0b0100_0000;
+const FLAG_IDS_SET: u8 = 0b1000_0000;
+
+/// Zipkin tracing metadata (`message/x.rsocket.tracing-zipkin.v0`), which propagates the B3
+/// context of a trace from a requester to a responder.
+///
+/// The metadata carries either the identifiers of a span along with its sampling decision, or
+/// only a sampling decision, which lets the responder start a trace of its own.
+///
+/// # Examples
+///
+/// ```
+/// use binate::extension::{Sampling, TraceId, ZipkinMetadata};
+///
+/// let metadata = ZipkinMetadata::new(TraceId::Bits128(0x1234), 0x5678)
+///     .set_parent_id(0x9ABC)
+///     .set_sampling(Sampling::Sampled);
+/// let decoded = ZipkinMetadata::decode(&metadata.to_bytes()).unwrap();
+/// assert_eq!(decoded, metadata);
+/// assert_eq!(decoded.span_id(), Some(0x5678));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZipkinMetadata {
+    ids: Option<SpanIds>,
+    sampling: Sampling,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpanIds {
+    trace_id: TraceId,
+    span_id: u64,
+    parent_id: Option<u64>,
+}
+
+/// The identifier of a trace, which is either 64 or 128 bits long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraceId {
+    /// A 64-bit trace identifier.
+    Bits64(u64),
+    /// A 128-bit trace identifier.
+    Bits128(u128),
+}
+
+/// The sampling decision of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sampling {
+    /// The sampling decision is deferred to the responder.
+    Unspecified,
+    /// The trace is reported.
+    Sampled,
+    /// The trace is not reported.
+    NotSampled,
+    /// The trace is reported, bypassing any sampling rate.
+    Debug,
+}
+
+impl ZipkinMetadata {
+    /// Creates a new `ZipkinMetadata` with the identifiers of a span, and an unspecified
+    /// sampling decision.
+    pub fn new(trace_id: TraceId, span_id: u64) -> ZipkinMetadata {
+        ZipkinMetadata {
+            ids: Some(SpanIds { trace_id, span_id, parent_id: None }),
+            sampling: Sampling::Unspecified,
+        }
+    }
+
+    /// Creates a new `ZipkinMetadata` that only carries a sampling decision.
+    pub fn sampling_only(sampling: Sampling) -> ZipkinMetadata {
+        ZipkinMetadata { ids: None, sampling }
+    }
+
+    /// Sets the identifier of the parent of the span.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the metadata does not carry the identifiers of a span.
+    pub fn set_parent_id(mut self, parent_id: u64) -> Self {
+        let ids = self.ids.as_mut().expect("no span identifiers");
+        ids.parent_id = Some(parent_id);
+        self
+    }
+
+    /// Sets the sampling decision.
+    pub fn set_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Returns the identifier of the trace, if any.
+    pub fn trace_id(&self) -> Option<TraceId> {
+        self.ids.map(|ids| ids.trace_id)
+    }
+
+    /// Returns the identifier of the span, if any.
+    pub fn span_id(&self) -> Option<u64> {
+        self.ids.map(|ids| ids.span_id)
+    }
+
+    /// Returns the identifier of the parent of the span, if any.
+    pub fn parent_id(&self) -> Option<u64> {
+        self.ids.and_then(|ids| ids.parent_id)
+    }
+
+    /// Returns the sampling decision.
+    pub fn sampling(&self) -> Sampling {
+        self.sampling
+    }
+
+    /// Decodes the given Zipkin tracing metadata.
+    ///
+    /// An `INVALID` error is returned if the metadata is malformed.
+    pub fn decode(metadata: &Bytes) -> Result<ZipkinMetadata> {
+        let mut buf = metadata.clone();
+        if !buf.has_remaining() {
+            return Err(invalid("empty tracing metadata"));
+        }
+        let flags = buf.get_u8();
+        let sampling = if flags & FLAG_DEBUG != 0 {
+            Sampling::Debug
+        } else if flags & FLAG_SAMPLED != 0 {
+            Sampling::Sampled
+        } else if flags & FLAG_NOT_SAMPLED != 0 {
+            Sampling::NotSampled
+        } else {
+            Sampling::Unspecified
+        };
+        if flags & FLAG_IDS_SET == 0 {
+            return Ok(ZipkinMetadata { ids: None, sampling });
+        }
+
+        let extended = flags & FLAG_EXTENDED_TRACE_ID != 0;
+        let has_parent = flags & FLAG_INCLUDE_PARENT_ID != 0;
+        let len = 16 + 8 * (extended as usize + has_parent as usize);
+        if buf.remaining() != len {
+            return Err(invalid("malformed tracing metadata"));
+        }
+        let trace_id = if extended {
+            TraceId::Bits128(buf.get_u128())
+        } else {
+            TraceId::Bits64(buf.get_u64())
+        };
+        let span_id = buf.get_u64();
+        let parent_id = if has_parent { Some(buf.get_u64()) } else { None };
+        Ok(ZipkinMetadata {
+            ids: Some(SpanIds { trace_id, span_id, parent_id }),
+            sampling,
+        })
+    }
+
+    /// Encodes the context of the trace into Zipkin tracing metadata.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut flags = match self.sampling {
+            Sampling::Unspecified => 0,
+            Sampling::Sampled => FLAG_SAMPLED,
+            Sampling::NotSampled => FLAG_NOT_SAMPLED,
+            Sampling::Debug => FLAG_DEBUG,
+        };
+        let ids = match &self.ids {
+            Some(ids) => ids,
+            None => return Bytes::copy_from_slice(&[flags]),
+        };
+        flags |= FLAG_IDS_SET;
+        if let TraceId::Bits128(_) = ids.trace_id {
+            flags |= FLAG_EXTENDED_TRACE_ID;
+        }
+        if ids.parent_id.is_some() {
+            flags |= FLAG_INCLUDE_PARENT_ID;
+        }
+        let mut buf = BytesMut::with_capacity(33);
+        buf.put_u8(flags);
+        match ids.trace_id {
+            TraceId::Bits64(trace_id) => buf.put_u64(trace_id),
+            TraceId::Bits128(trace_id) => buf.put_u128(trace_id),
+        }
+        buf.put_u64(ids.span_id);
+        if let Some(parent_id) = ids.parent_id {
+            buf.put_u64(parent_id);
+        }
+        buf.freeze()
+    }
+}
+
+impl fmt::Display for TraceId {
+    /// Formats the trace identifier as lower-hex, with 16 or 32 digits as in B3 headers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceId::Bits64(trace_id) => write!(f, "{:016x}", trace_id),
+            TraceId::Bits128(trace_id) => write!(f, "{:032x}", trace_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let metadata = ZipkinMetadata::new(TraceId::Bits64(1), 2)
+            .set_sampling(Sampling::Sampled);
+        let bytes = metadata.to_bytes();
+        assert_eq!(
+            bytes,
+            Bytes::from_static(b"\xA0\0\0\0\0\0\0\0\x01\0\0\0\0\0\0\0\x02")
+        );
+        assert_eq!(ZipkinMetadata::decode(&bytes).unwrap(), metadata);
+
+        let metadata = ZipkinMetadata::new(TraceId::Bits128(1 << 64), 2)
+            .set_parent_id(3)
+            .set_sampling(Sampling::Debug);
+        let bytes = metadata.to_bytes();
+        assert_eq!(bytes.len(), 33);
+        assert_eq!(bytes[0], 0b1100_1100);
+        let decoded = ZipkinMetadata::decode(&bytes).unwrap();
+        assert_eq!(decoded, metadata);
+        assert_eq!(decoded.trace_id(), Some(TraceId::Bits128(1 << 64)));
+        assert_eq!(decoded.parent_id(), Some(3));
+    }
+
+    #[test]
+    fn sampling_only() {
+        let metadata = ZipkinMetadata::sampling_only(Sampling::NotSampled);
+        assert_eq!(metadata.to_bytes(), Bytes::from_static(b"\x10"));
+        let decoded = ZipkinMetadata::decode(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded.sampling(), Sampling::NotSampled);
+        assert!(decoded.trace_id().is_none());
+        assert!(decoded.span_id().is_none());
+    }
+
+    #[test]
+    fn decode_malformed() {
+        for bytes in [&b""[..], &b"\x80\0\0\0\0"[..], &[0x84; 17][..]] {
+            let err = ZipkinMetadata::decode(&Bytes::from(bytes)).unwrap_err();
+            assert!(err.is_invalid());
+        }
+    }
+
+    #[test]
+    fn trace_id_display() {
+        assert_eq!(TraceId::Bits64(0xAB).to_string(), "00000000000000ab");
+        assert_eq!(TraceId::Bits128(1).to_string().len(), 32);
+    }
+}
@@ -138,7 +138,7 @@ impl ErrorFrame {
     /// Returns the error data in this error frame in UTF-8 format. If the error data is not valid
     /// UTF-8, this will return `None`.
     pub fn data_utf8(&self) -> Option<&str> {
-        self.data.as_ref().map(|data| std::str::from_utf8(data).ok()).flatten()
+        self.data.as_ref().and_then(|data| std::str::from_utf8(data).ok())
     }
 }
 
@@ -192,24 +192,24 @@ fn validate_stream_id(stream_id: u32, code: u32) -> Result<()> {
         | ErrorFrame::REJECTED_SETUP
         | ErrorFrame::REJECTED_RESUME
         | ErrorFrame::CONNECTION_ERROR
-        | ErrorFrame::CONNECTION_CLOSE => {
-            if stream_id != 0 {
-                return Err(DecodeError::InvalidStreamId {
-                    expected: "0",
-                    found: stream_id,
-                });
-            }
+        | ErrorFrame::CONNECTION_CLOSE
+            if stream_id != 0 =>
+        {
+            return Err(DecodeError::InvalidStreamId {
+                expected: "0",
+                found: stream_id,
+            });
         }
         ErrorFrame::APPLICATION_ERROR
         | ErrorFrame::REJECTED
         | ErrorFrame::CANCELED
-        | ErrorFrame::INVALID => {
-            if stream_id == 0 {
-                return Err(DecodeError::InvalidStreamId {
-                    expected: "> 0",
-                    found: stream_id,
-                });
-            }
+        | ErrorFrame::INVALID
+            if stream_id == 0 =>
+        {
+            return Err(DecodeError::InvalidStreamId {
+                expected: "> 0",
+                found: stream_id,
+            });
         }
         _ => (),
     }
@@ -48,4 +48,5 @@ pub use self::request_response::RequestResponseFrame;
 pub use self::request_stream::RequestStreamFrame;
 pub use self::resume::ResumeFrame;
 pub use self::resume_ok::ResumeOkFrame;
+#[allow(unused_imports)]
 pub use self::setup::{SetupFrame, SetupFrameBuilder};
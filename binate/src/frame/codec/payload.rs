@@ -33,9 +33,9 @@ impl PayloadFrame {
     /// - `stream_id` MUST be <= [`MAX_U31`].
     /// - flag `follows` means more fragments follow this fragment.
     /// - flag `complete` indicates stream completion. If set, `on_complete()` will be invoked on
-    /// Subscriber/Observer.
+    ///   Subscriber/Observer.
     /// - flag `next` indicates Next (Payload Data and/or Metadata present). If set,
-    /// `on_next(Payload)` will be invoked on Subscriber/Observer.
+    ///   `on_next(Payload)` will be invoked on Subscriber/Observer.
     ///
     /// A PAYLOAD MUST NOT have both (C)complete and (N)ext empty (false). See [`Payload Frame`]
     /// section in the spec for more details.
@@ -91,20 +91,14 @@ impl Encode for PayloadFrame {
     fn encode(&self, buf: &mut BytesMut) {
         buf.put_u32(self.stream_id);
         buf.put_u16(FrameType::PAYLOAD.bits() | self.flags.bits());
-        let u24 = U24::from_usize(
-            self.payload.metadata().map(|v| v.len()).unwrap_or_default(),
-        );
-        buf.put_u8(u24.0);
-        buf.put_u16(u24.1);
-        self.payload.encode(buf);
+        encode_payload(&self.payload, buf);
     }
 
     fn len(&self) -> usize {
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(payload)
-        9 + self.payload.len()
+        6 + payload_len(&self.payload)
     }
 }
 
@@ -116,7 +110,7 @@ impl Decode for PayloadFrame {
         stream_id: u32,
         flags: Flags,
     ) -> Result<Self::Value> {
-        let payload = eat_payload(buf, true)?;
+        let payload = eat_payload(buf, flags)?;
         Ok(PayloadFrame { stream_id, flags, payload })
     }
 }
@@ -142,7 +136,6 @@ mod tests {
 
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(metadata): 8
         // len(data): 4
         let buf_len = buf.len();
@@ -161,4 +154,33 @@ mod tests {
         assert_eq!(lease.len(), buf_len);
         assert_eq!(decoded.len(), buf_len);
     }
+
+    #[test]
+    fn test_codec_without_metadata() {
+        let frame = PayloadFrame::new(
+            1,
+            Flags::NEXT,
+            Payload::builder().set_data(Bytes::from("data")).build(),
+        );
+
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf);
+        let mut buf = buf.freeze();
+
+        // len(stream_id): 4
+        // len(flags): 2
+        // len(data): 4
+        let buf_len = buf.len();
+        assert_eq!(buf_len, 4 + 2 + 4);
+
+        let stream_id = eat_stream_id(&mut buf).unwrap();
+        let (_, flags) = eat_flags(&mut buf).unwrap();
+        assert_eq!(flags, Flags::NEXT);
+
+        let decoded =
+            PayloadFrame::decode(&mut buf, stream_id, flags).unwrap();
+
+        assert_eq!(decoded, frame);
+        assert_eq!(frame.len(), buf_len);
+    }
 }
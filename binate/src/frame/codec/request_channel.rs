@@ -36,7 +36,7 @@ impl RequestChannelFrame {
     /// - `stream_id` MUST be <= [`MAX_U31`].
     /// - flag `follows` means more fragments follow this fragment.
     /// - flag `complete` indicates stream completion. If set `on_complete()` or equivalent will be
-    /// invoked on Subscriber/Observer.
+    ///   invoked on Subscriber/Observer.
     /// - `initial_request_n` MUST be > 0 and <= [`MAX_U31`].
     pub fn new(
         stream_id: u32,
@@ -103,21 +103,15 @@ impl Encode for RequestChannelFrame {
         buf.put_u32(self.stream_id);
         buf.put_u16(FrameType::REQUEST_CHANNEL.bits() | self.flags.bits());
         buf.put_u32(self.initial_request_n);
-        let u24 = U24::from_usize(
-            self.payload.metadata().map(|v| v.len()).unwrap_or_default(),
-        );
-        buf.put_u8(u24.0);
-        buf.put_u16(u24.1);
-        self.payload.encode(buf);
+        encode_payload(&self.payload, buf);
     }
 
     fn len(&self) -> usize {
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(initial_request_n): 4
         // len(payload)
-        13 + self.payload.len()
+        10 + payload_len(&self.payload)
     }
 }
 
@@ -130,7 +124,7 @@ impl Decode for RequestChannelFrame {
         flags: Flags,
     ) -> Result<Self::Value> {
         let initial_request_n = eat_u31(buf)?;
-        let payload = eat_payload(buf, true)?;
+        let payload = eat_payload(buf, flags)?;
         Ok(RequestChannelFrame {
             stream_id,
             flags,
@@ -164,7 +158,6 @@ mod tests {
         // len(stream_id): 4
         // len(flags): 2
         // len(initial_request_n): 4
-        // len(metadata_len): 3
         // len(metadata): 8
         // len(data): 4
         let buf_len = buf.len();
@@ -75,20 +75,14 @@ impl Encode for RequestFnfFrame {
     fn encode(&self, buf: &mut BytesMut) {
         buf.put_u32(self.stream_id);
         buf.put_u16(FrameType::REQUEST_FNF.bits() | self.flags.bits());
-        let u24 = U24::from_usize(
-            self.payload.metadata().map(|v| v.len()).unwrap_or_default(),
-        );
-        buf.put_u8(u24.0);
-        buf.put_u16(u24.1);
-        self.payload.encode(buf);
+        encode_payload(&self.payload, buf);
     }
 
     fn len(&self) -> usize {
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(payload)
-        9 + self.payload.len()
+        6 + payload_len(&self.payload)
     }
 }
 
@@ -100,7 +94,7 @@ impl Decode for RequestFnfFrame {
         stream_id: u32,
         flags: Flags,
     ) -> Result<Self::Value> {
-        let payload = eat_payload(buf, true)?;
+        let payload = eat_payload(buf, flags)?;
         Ok(RequestFnfFrame { stream_id, flags, payload })
     }
 }
@@ -126,7 +120,6 @@ mod tests {
 
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(metadata): 8
         // len(data): 4
         let buf_len = buf.len();
@@ -32,7 +32,7 @@ impl RequestNFrame {
     ///
     /// - `stream_id` MUST be <= [`MAX_U31`].
     /// - `request_n` represents the number of items to request. Value MUST be > 0 and
-    /// <= [`MAX_U31`].
+    ///   <= [`MAX_U31`].
     pub fn new(stream_id: u32, request_n: u32) -> Self {
         debug_assert_max_u31!(stream_id, request_n);
         debug_assert_non_zero!(request_n);
@@ -92,21 +92,15 @@ impl Encode for RequestStreamFrame {
         buf.put_u32(self.stream_id);
         buf.put_u16(FrameType::REQUEST_STREAM.bits() | self.flags.bits());
         buf.put_u32(self.initial_request_n);
-        let u24 = U24::from_usize(
-            self.payload.metadata().map(|v| v.len()).unwrap_or_default(),
-        );
-        buf.put_u8(u24.0);
-        buf.put_u16(u24.1);
-        self.payload.encode(buf);
+        encode_payload(&self.payload, buf);
     }
 
     fn len(&self) -> usize {
         // len(stream_id): 4
         // len(flags): 2
-        // len(metadata_len): 3
         // len(initial_request_n): 4
         // len(payload)
-        13 + self.payload.len()
+        10 + payload_len(&self.payload)
     }
 }
 
@@ -119,7 +113,7 @@ impl Decode for RequestStreamFrame {
         flags: Flags,
     ) -> Result<Self::Value> {
         let initial_request_n = eat_u31(buf)?;
-        let payload = eat_payload(buf, true)?;
+        let payload = eat_payload(buf, flags)?;
         Ok(RequestStreamFrame { stream_id, flags, initial_request_n, payload })
     }
 }
@@ -147,7 +141,6 @@ mod tests {
         // len(stream_id): 4
         // len(flags): 2
         // len(initial_request_n): 4
-        // len(metadata_len): 3
         // len(metadata): 8
         // len(data): 4
         let buf_len = buf.len();
@@ -44,7 +44,7 @@ impl ResumeFrame {
     ///
     /// - The length of `resume_token` MUST be <= `65,535` bytes long.
     /// - Both `last_received_server_position` and `first_available_client_position` MUST be <=
-    /// [`MAX_U63`].
+    ///   [`MAX_U63`].
     pub fn new(
         version: Version,
         resume_token: Bytes,
@@ -32,7 +32,7 @@ impl ResumeOkFrame {
     /// Create a new `ResumeOk` frame.
     ///
     /// - `last_received_client_position` and `first_available_client_position` MUST be <=
-    /// [`MAX_U63`].
+    ///   [`MAX_U63`].
     pub fn new(mut last_received_client_position: u64) -> Self {
         debug_assert_max_u63!(last_received_client_position);
         last_received_client_position &= MAX_U63;
@@ -153,12 +153,7 @@ impl Encode for SetupFrame {
         buf.put_slice(&self.metadata_mimetype);
         buf.put_u8(self.data_mimetype.len() as u8);
         buf.put_slice(&self.data_mimetype);
-        let u24 = U24::from_usize(
-            self.payload.metadata().map(|v| v.len()).unwrap_or_default(),
-        );
-        buf.put_u8(u24.0);
-        buf.put_u16(u24.1);
-        self.payload.encode(buf);
+        encode_payload(&self.payload, buf);
     }
 
     fn len(&self) -> usize {
@@ -167,12 +162,12 @@ impl Encode for SetupFrame {
         // len(version): 4
         // len(keepalive): 4
         // len(lifetime): 4
-        // len(token_length): 2
-        let mut len = 20;
+        let mut len = 18;
 
+        // len(token_length): 2
         // len(resume_token)
         if let Some(resume_token) = &self.resume_token {
-            len += resume_token.len();
+            len += 2 + resume_token.len();
         }
 
         // len(mime_metadata_length): 1
@@ -181,9 +176,8 @@ impl Encode for SetupFrame {
         // len(mime_data)
         len += 1 + self.metadata_mimetype.len() + 1 + self.data_mimetype.len();
 
-        // len(metadata_length): 3
         // len(payload)
-        len += 3 + self.payload.len();
+        len += payload_len(&self.payload);
 
         len
     }
@@ -206,7 +200,7 @@ impl Decode for SetupFrame {
             eat_bytes(buf, metadata_mimetype_len as usize)?;
         let data_mimetype_len = eat_u8(buf)?;
         let data_mimetype = eat_bytes(buf, data_mimetype_len as usize)?;
-        let payload = eat_payload(buf, true)?;
+        let payload = eat_payload(buf, flags)?;
         Ok(SetupFrame {
             flags,
             version,
@@ -271,10 +265,10 @@ impl SetupFrameBuilder {
     /// This value MUST be > `0` and <= [`MAX_U31`].
     ///
     /// - For server-to-server connections, a reasonable time interval between client KEEPALIVE
-    /// frames is 500ms.
+    ///   frames is 500ms.
     ///
     /// - For mobile-to-server connections, the time interval between client KEEPALIVE frames is
-    /// often > 30,000ms.
+    ///   often > 30,000ms.
     pub fn set_keepalive_interval(mut self, interval: u32) -> Self {
         debug_assert_max_u31!(interval);
         self.keepalive_interval = interval & MAX_U31;
@@ -308,7 +302,7 @@ impl SetupFrameBuilder {
     ///
     /// # Panics
     ///
-    /// This function panics if the length of the given `mimetype` is greater than `256` bytes.
+    /// This function panics if the length of the given `mimetype` is greater than `255` bytes.
     ///
     /// [`Internet media type`]: https://en.wikipedia.org/wiki/Internet_media_type
     /// [`RFC 2045`]: https://datatracker.ietf.org/doc/html/rfc2045
@@ -317,7 +311,7 @@ impl SetupFrameBuilder {
         T: Into<String>,
     {
         let mimetype: String = mimetype.into();
-        assert!(mimetype.len() <= 256);
+        assert!(mimetype.len() <= 255);
         self.metadata_mimetype = Bytes::from(mimetype);
         self
     }
@@ -329,7 +323,7 @@ impl SetupFrameBuilder {
     ///
     /// # Panics
     ///
-    /// This function panics if the length of the given `mimetype` is greater than `256` bytes.
+    /// This function panics if the length of the given `mimetype` is greater than `255` bytes.
     ///
     /// [`Internet media type`]: https://en.wikipedia.org/wiki/Internet_media_type
     /// [`RFC 2045`]: https://datatracker.ietf.org/doc/html/rfc2045
@@ -338,7 +332,7 @@ impl SetupFrameBuilder {
         T: Into<String>,
     {
         let mimetype: String = mimetype.into();
-        assert!(mimetype.len() <= 256);
+        assert!(mimetype.len() <= 255);
         self.data_mimetype = Bytes::from(mimetype);
         self
     }
@@ -432,4 +426,27 @@ mod tests {
         assert_eq!(setup.len(), buf_len);
         assert_eq!(decoded.len(), buf_len);
     }
+
+    #[test]
+    fn test_max_mimetype_len() {
+        let mimetype = "a".repeat(255);
+        let setup = SetupFrame::builder()
+            .set_metadata_mimetype(mimetype.as_str())
+            .set_data_mimetype(mimetype.as_str())
+            .build();
+
+        let mut buf = BytesMut::new();
+        setup.encode(&mut buf);
+        let mut buf = buf.freeze();
+        let stream_id = eat_stream_id(&mut buf).unwrap();
+        let (_, flags) = eat_flags(&mut buf).unwrap();
+        let decoded = SetupFrame::decode(&mut buf, stream_id, flags).unwrap();
+        assert_eq!(decoded, setup);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mimetype_too_long() {
+        SetupFrame::builder().set_data_mimetype("a".repeat(256));
+    }
 }
@@ -31,6 +31,13 @@ pub enum DecodeError {
         /// found stream ID
         found: u32,
     },
+    /// The decoded frame is an extension frame, whose extended type is not supported.
+    UnsupportedExtension {
+        /// extended type of the frame
+        extended_type: u32,
+        /// whether the frame has the IGNORE flag set, so that it can be skipped
+        ignore: bool,
+    },
 }
 
 impl fmt::Display for DecodeError {
@@ -46,6 +53,11 @@ impl fmt::Display for DecodeError {
                 "invalid stream ID (expected {}, found {})",
                 expected, found
             ),
+            UnsupportedExtension { extended_type, .. } => write!(
+                f,
+                "unsupported extended frame type {0:#x}",
+                extended_type
+            ),
         }
     }
 }
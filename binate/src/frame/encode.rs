@@ -1,3 +1,5 @@
+use super::U24;
+use crate::payload::Payload;
 use bytes::{Bytes, BytesMut};
 
 /// A trait for encoding a frame into bytes.
@@ -23,3 +25,22 @@ pub trait Encode {
         buf.freeze()
     }
 }
+
+/// Encodes the given payload, prefixing its metadata with a 24-bit length field.
+///
+/// The metadata length field is only present if the payload has metadata (i.e. the METADATA
+/// flag is set on the frame).
+pub(super) fn encode_payload(payload: &Payload, buf: &mut BytesMut) {
+    if let Some(metadata) = payload.metadata() {
+        U24::from_usize(metadata.len()).encode(buf);
+    }
+    payload.encode(buf);
+}
+
+/// Returns the length of the given payload once encoded by [`encode_payload`].
+pub(super) fn payload_len(payload: &Payload) -> usize {
+    match payload.metadata() {
+        Some(_) => 3 + payload.len(),
+        None => payload.len(),
+    }
+}
@@ -18,6 +18,7 @@ pub use self::version::Version;
 use crate::payload::Payload;
 use bytes::{Buf, BytesMut};
 use codec::*;
+use encode::{encode_payload, payload_len};
 use visit::*;
 
 /// The maximum value 31-bit unsigned integer can hold.
@@ -116,13 +117,13 @@ impl Frame {
             | FrameType::KEEPALIVE
             | FrameType::METADATA_PUSH
             | FrameType::RESUME
-            | FrameType::RESUME_OK => {
-                if stream_id != 0 {
-                    return Err(DecodeError::InvalidStreamId {
-                        expected: "0",
-                        found: stream_id,
-                    });
-                }
+            | FrameType::RESUME_OK
+                if stream_id != 0 =>
+            {
+                return Err(DecodeError::InvalidStreamId {
+                    expected: "0",
+                    found: stream_id,
+                });
             }
             _ => (),
         }
@@ -171,16 +172,62 @@ impl Frame {
                 Frame::ResumeOk(ResumeOkFrame::decode(buf, stream_id, flags)?)
             }
             FrameType::EXT => {
-                unimplemented!()
+                let extended_type = eat_u32(buf)?;
+                buf.advance(buf.remaining());
+                return Err(DecodeError::UnsupportedExtension {
+                    extended_type,
+                    ignore: flags.is_ignore(),
+                });
             }
         })
     }
+
+    /// Decodes the given bytes into a frame, or returns `None` if the frame is not understood
+    /// but has the IGNORE flag set, in which case it must be skipped.
+    pub(crate) fn decode_or_skip<B: Buf>(buf: &mut B) -> Result<Option<Self>> {
+        match Frame::decode(buf) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(DecodeError::UnsupportedExtension { ignore: true, .. }) => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the type of this frame.
+    pub(crate) fn frame_type(&self) -> FrameType {
+        match self {
+            Frame::Setup(_) => FrameType::SETUP,
+            Frame::Error(_) => FrameType::ERROR,
+            Frame::Lease(_) => FrameType::LEASE,
+            Frame::Keepalive(_) => FrameType::KEEPALIVE,
+            Frame::RequestResponse(_) => FrameType::REQUEST_RESPONSE,
+            Frame::RequestFnf(_) => FrameType::REQUEST_FNF,
+            Frame::RequestStream(_) => FrameType::REQUEST_STREAM,
+            Frame::RequestChannel(_) => FrameType::REQUEST_CHANNEL,
+            Frame::RequestN(_) => FrameType::REQUEST_N,
+            Frame::Cancel(_) => FrameType::CANCEL,
+            Frame::Payload(_) => FrameType::PAYLOAD,
+            Frame::MetadataPush(_) => FrameType::METADATA_PUSH,
+            Frame::Resume(_) => FrameType::RESUME,
+            Frame::ResumeOk(_) => FrameType::RESUME_OK,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytes::Bytes;
+    use bytes::{BufMut, Bytes};
+
+    fn ext_frame(flags: u16) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_u32(1);
+        buf.put_u16(FrameType::EXT.bits() | flags);
+        buf.put_u32(0xABCD);
+        buf.put_slice(b"extension");
+        buf.freeze()
+    }
 
     #[test]
     fn test_max_u31() {
@@ -192,6 +239,23 @@ mod tests {
         assert_eq!(MAX_U63, u64::MAX >> 1);
     }
 
+    #[test]
+    fn test_ext_frame_decode() {
+        let mut buf = ext_frame(0);
+        assert_eq!(
+            Frame::decode(&mut buf),
+            Err(DecodeError::UnsupportedExtension {
+                extended_type: 0xABCD,
+                ignore: false,
+            })
+        );
+        assert!(Frame::decode_or_skip(&mut ext_frame(0)).is_err());
+
+        let mut buf = ext_frame(Flags::IGNORE.bits());
+        assert_eq!(Frame::decode_or_skip(&mut buf), Ok(None));
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn test_frame_decode() {
         let f = RequestFnfFrame::new(
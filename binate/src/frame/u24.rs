@@ -90,7 +90,7 @@ mod tests {
     #[test]
     #[should_panic]
     fn test_from_invalid_u32() {
-        U24::from_u32(U24::MAX as u32 + 1);
+        U24::from_u32(U24::MAX + 1);
     }
 
     #[test]
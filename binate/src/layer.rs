@@ -0,0 +1,529 @@
+//! Middleware for responders.
+//!
+//! An [`RSocketLayer`] wraps a responder in another one, which sees every request of all five
+//! interaction models before the wrapped responder does, along with the responses it returns.
+//! Layers are added to a connection with [`ServerBuilder::add_layer`] or
+//! [`ClientBuilder::add_layer`], so that cross-cutting concerns are handled in one place rather
+//! than in every responder.
+//!
+//! This module provides layers for logging ([`LoggingLayer`]), timeouts ([`TimeoutLayer`]) and
+//! concurrency limits ([`ConcurrencyLimitLayer`]).
+//!
+//! [`ServerBuilder::add_layer`]: crate::ServerBuilder::add_layer
+//! [`ClientBuilder::add_layer`]: crate::ClientBuilder::add_layer
+use crate::error::{Code, Error, Result};
+use crate::payload::{Metadata, Payload};
+use crate::{Flux, Mono, RSocket};
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt};
+use tracing::debug;
+
+/// Wraps a responder in another one, such as a middleware.
+///
+/// `RSocketLayer` is implemented for closures, so that simple layers can be written inline.
+///
+/// # Examples
+///
+/// ```
+/// use binate::layer::{LoggingLayer, TimeoutLayer};
+/// use binate::{RSocket, Server};
+/// use std::time::Duration;
+///
+/// let builder = Server::builder()
+///     .add_layer(LoggingLayer::new())
+///     .add_layer(TimeoutLayer::new(Duration::from_secs(5)))
+///     .add_layer(|responder: Box<dyn RSocket>| responder);
+/// ```
+pub trait RSocketLayer: Send + Sync {
+    /// Wraps the given responder.
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket>;
+}
+
+impl<F> RSocketLayer for F
+where
+    F: Fn(Box<dyn RSocket>) -> Box<dyn RSocket> + Send + Sync,
+{
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        self(responder)
+    }
+}
+
+/// Wraps the given responder in the given layers, where the first layer is the outermost one.
+pub(crate) fn layered(
+    layers: &[Arc<dyn RSocketLayer>],
+    responder: Box<dyn RSocket>,
+) -> Box<dyn RSocket> {
+    layers
+        .iter()
+        .rev()
+        .fold(responder, |responder, layer| layer.layer(responder))
+}
+
+/// A layer that logs every request and its outcome with `tracing`, at the `DEBUG` level.
+#[derive(Debug, Clone, Default)]
+pub struct LoggingLayer {
+    _priv: (),
+}
+
+impl LoggingLayer {
+    /// Creates a new `LoggingLayer`.
+    pub fn new() -> LoggingLayer {
+        LoggingLayer { _priv: () }
+    }
+}
+
+impl RSocketLayer for LoggingLayer {
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        Box::new(Logging { inner: responder })
+    }
+}
+
+struct Logging {
+    inner: Box<dyn RSocket>,
+}
+
+impl Logging {
+    fn mono<T>(
+        interaction: &'static str,
+        response: Mono<Result<T>>,
+    ) -> Mono<Result<T>>
+    where
+        T: Send + 'static,
+    {
+        let started = Instant::now();
+        Box::pin(async move {
+            let result = response.await;
+            let elapsed = started.elapsed();
+            match &result {
+                Ok(_) => debug!(interaction, ?elapsed, "request completed"),
+                Err(err) => {
+                    debug!(interaction, ?elapsed, error = %err, "request failed")
+                }
+            }
+            result
+        })
+    }
+}
+
+impl RSocket for Logging {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        debug!(interaction = "request_response", "request received");
+        Logging::mono("request_response", self.inner.request_response(payload))
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        debug!(interaction = "request_stream", "request received");
+        let payloads = self.inner.request_stream(payload);
+        Box::pin(LoggedFlux::new("request_stream", payloads))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        debug!(interaction = "request_channel", "request received");
+        let payloads = self.inner.request_channel(payloads);
+        Box::pin(LoggedFlux::new("request_channel", payloads))
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        let result = self.inner.fire_and_forget(payload);
+        match &result {
+            Ok(_) => {
+                debug!(interaction = "fire_and_forget", "request received")
+            }
+            Err(err) => debug!(
+                interaction = "fire_and_forget",
+                error = %err,
+                "request failed"
+            ),
+        }
+        result
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        debug!(interaction = "metadata_push", "request received");
+        Logging::mono("metadata_push", self.inner.metadata_push(metadata))
+    }
+}
+
+/// Logs the outcome of a stream of payloads once it terminates, or is cancelled.
+struct LoggedFlux {
+    interaction: &'static str,
+    inner: Flux<Result<Payload>>,
+    started: Instant,
+    payloads: usize,
+    terminated: bool,
+}
+
+impl LoggedFlux {
+    fn new(
+        interaction: &'static str,
+        inner: Flux<Result<Payload>>,
+    ) -> LoggedFlux {
+        LoggedFlux {
+            interaction,
+            inner,
+            started: Instant::now(),
+            payloads: 0,
+            terminated: false,
+        }
+    }
+}
+
+impl Stream for LoggedFlux {
+    type Item = Result<Payload>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let item = match self.inner.as_mut().poll_next(cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => return Poll::Pending,
+        };
+        let (interaction, payloads) = (self.interaction, self.payloads);
+        let elapsed = self.started.elapsed();
+        match &item {
+            Some(Ok(_)) => self.payloads += 1,
+            Some(Err(err)) => {
+                self.terminated = true;
+                debug!(
+                    interaction,
+                    ?elapsed,
+                    payloads,
+                    error = %err,
+                    "request failed"
+                );
+            }
+            None => {
+                self.terminated = true;
+                debug!(interaction, ?elapsed, payloads, "request completed");
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for LoggedFlux {
+    fn drop(&mut self) {
+        if !self.terminated {
+            let (interaction, payloads) = (self.interaction, self.payloads);
+            let elapsed = self.started.elapsed();
+            debug!(interaction, ?elapsed, payloads, "request cancelled");
+        }
+    }
+}
+
+/// A layer that fails the requests that are not responded to within a timeout.
+///
+/// Request-response and metadata-push requests fail with a `CANCELED` error if they do not
+/// complete in time, and streams and channels are terminated with a `CANCELED` error if they
+/// do not complete in time since they are requested. Fire-and-forget requests are handled
+/// synchronously, and are not subject to the timeout.
+#[derive(Debug, Clone)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    /// Creates a new `TimeoutLayer` that fails the requests not responded to within `timeout`.
+    pub fn new(timeout: Duration) -> TimeoutLayer {
+        TimeoutLayer { timeout }
+    }
+}
+
+impl RSocketLayer for TimeoutLayer {
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        Box::new(Timeout { inner: responder, timeout: self.timeout })
+    }
+}
+
+struct Timeout {
+    inner: Box<dyn RSocket>,
+    timeout: Duration,
+}
+
+impl Timeout {
+    fn elapsed() -> Error {
+        Error::with_code(Code::Canceled, "request timed out")
+    }
+
+    fn mono<T>(&self, response: Mono<Result<T>>) -> Mono<Result<T>>
+    where
+        T: Send + 'static,
+    {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(result) => result,
+                Err(_) => Err(Timeout::elapsed()),
+            }
+        })
+    }
+
+    fn flux(&self, payloads: Flux<Result<Payload>>) -> Flux<Result<Payload>> {
+        let deadline = Instant::now() + self.timeout;
+        Box::pin(futures_util::stream::unfold(
+            Some(payloads),
+            move |payloads| async move {
+                let mut payloads = payloads?;
+                match tokio::time::timeout_at(deadline, payloads.next()).await
+                {
+                    Ok(Some(item)) => Some((item, Some(payloads))),
+                    Ok(None) => None,
+                    Err(_) => Some((Err(Timeout::elapsed()), None)),
+                }
+            },
+        ))
+    }
+}
+
+impl RSocket for Timeout {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        self.mono(self.inner.request_response(payload))
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        self.flux(self.inner.request_stream(payload))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        self.flux(self.inner.request_channel(payloads))
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        self.inner.fire_and_forget(payload)
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        self.mono(self.inner.metadata_push(metadata))
+    }
+}
+
+/// A layer that limits the number of requests handled at a time on each connection.
+///
+/// A request counts towards the limit until its response completes (or the stream terminates,
+/// or is cancelled). Requests beyond the limit are rejected with a `REJECTED` error, which
+/// guarantees the requester that they are not processed.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitLayer {
+    max: usize,
+}
+
+impl ConcurrencyLimitLayer {
+    /// Creates a new `ConcurrencyLimitLayer` that handles at most `max` requests at a time on
+    /// each connection.
+    pub fn new(max: usize) -> ConcurrencyLimitLayer {
+        ConcurrencyLimitLayer { max }
+    }
+}
+
+impl RSocketLayer for ConcurrencyLimitLayer {
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        Box::new(ConcurrencyLimit {
+            inner: responder,
+            semaphore: Arc::new(Semaphore::new(self.max)),
+        })
+    }
+}
+
+struct ConcurrencyLimit {
+    inner: Box<dyn RSocket>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimit {
+    fn rejected() -> Error {
+        Error::with_code(Code::Rejected, "too many concurrent requests")
+    }
+
+    fn flux<F>(&self, request: F) -> Flux<Result<Payload>>
+    where
+        F: FnOnce() -> Flux<Result<Payload>>,
+    {
+        match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Box::pin(request().map(move |item| {
+                // The permit is released once the stream is dropped.
+                let _permit = &permit;
+                item
+            })),
+            Err(_) => Box::pin(tokio_stream::once(Err(Self::rejected()))),
+        }
+    }
+
+    fn mono<T, F>(&self, request: F) -> Mono<Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Mono<Result<T>>,
+    {
+        match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => {
+                let response = request();
+                Box::pin(async move {
+                    let _permit = permit;
+                    response.await
+                })
+            }
+            Err(_) => Box::pin(async { Err(Self::rejected()) }),
+        }
+    }
+}
+
+impl RSocket for ConcurrencyLimit {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        self.mono(|| self.inner.request_response(payload))
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        self.flux(|| self.inner.request_stream(payload))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        self.flux(|| self.inner.request_channel(payloads))
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        let _permit =
+            self.semaphore.try_acquire().map_err(|_| Self::rejected())?;
+        self.inner.fire_and_forget(payload)
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        self.mono(|| self.inner.metadata_push(metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    /// Responds to every request after `delay`.
+    struct Delayed {
+        delay: Duration,
+    }
+
+    impl RSocket for Delayed {
+        fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+            let delay = self.delay;
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                Ok(payload)
+            })
+        }
+
+        fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+            let delay = self.delay;
+            Box::pin(futures_util::stream::unfold(0, move |n| {
+                let payload = payload.clone();
+                async move {
+                    if n == 2 {
+                        return None;
+                    }
+                    tokio::time::sleep(delay).await;
+                    Some((Ok(payload), n + 1))
+                }
+            }))
+        }
+
+        fn request_channel(
+            &self,
+            payloads: Flux<Result<Payload>>,
+        ) -> Flux<Result<Payload>> {
+            payloads
+        }
+
+        fn fire_and_forget(&self, _payload: Payload) -> Result<()> {
+            Ok(())
+        }
+
+        fn metadata_push(&self, _metadata: Metadata) -> Mono<Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn delayed(millis: u64) -> Box<dyn RSocket> {
+        Box::new(Delayed { delay: Duration::from_millis(millis) })
+    }
+
+    fn payload(data: &'static str) -> Payload {
+        Payload::builder().set_data(data).build()
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Arc<dyn RSocketLayer>>();
+        assert_sync::<Arc<dyn RSocketLayer>>();
+    }
+
+    #[test]
+    fn layered_in_order() {
+        let wrapped = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let layer = |name: &'static str| {
+            let wrapped = wrapped.clone();
+            Arc::new(move |responder: Box<dyn RSocket>| {
+                wrapped.lock().unwrap().push(name);
+                responder
+            }) as Arc<dyn RSocketLayer>
+        };
+        layered(&[layer("outer"), layer("inner")], delayed(0));
+        assert_eq!(*wrapped.lock().unwrap(), vec!["inner", "outer"]);
+    }
+
+    #[tokio::test]
+    async fn logging() {
+        let responder = LoggingLayer::new().layer(delayed(0));
+        let response = responder.request_response(payload("ping")).await;
+        assert_eq!(response.unwrap(), payload("ping"));
+        let payloads = responder.request_stream(payload("ping"));
+        assert_eq!(payloads.collect::<Vec<_>>().await.len(), 2);
+        assert!(responder.fire_and_forget(payload("ping")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        tokio::time::pause();
+        let layer = TimeoutLayer::new(Duration::from_millis(30));
+        let responder = layer.layer(delayed(0));
+        let response = responder.request_response(payload("ping")).await;
+        assert_eq!(response.unwrap(), payload("ping"));
+
+        let responder = layer.layer(delayed(20));
+        let mut payloads = responder.request_stream(payload("ping"));
+        assert!(payloads.next().await.unwrap().is_ok());
+        assert!(payloads.next().await.unwrap().unwrap_err().is_cancel());
+        assert!(payloads.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit() {
+        tokio::time::pause();
+        let responder = ConcurrencyLimitLayer::new(1).layer(delayed(10));
+        let first = responder.request_response(payload("1"));
+        let err = responder.request_response(payload("2")).await;
+        assert!(err.unwrap_err().is_rejected());
+        let payloads = responder.request_stream(payload("3"));
+        assert!(payloads.collect::<Vec<_>>().await[0].is_err());
+        assert!(responder.fire_and_forget(payload("4")).is_err());
+
+        // The permit is released once the first request completes.
+        assert_eq!(first.await.unwrap(), payload("1"));
+        let payloads = responder.request_stream(payload("5"));
+        assert!(responder.request_response(payload("6")).await.is_err());
+        drop(payloads);
+        assert!(responder.request_response(payload("7")).await.is_ok());
+    }
+}
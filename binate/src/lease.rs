@@ -0,0 +1,161 @@
+//! Lease-based admission control.
+//!
+//! When leases are enabled on a connection (see [`ClientBuilder::set_lease`]), a requester may
+//! only send requests while it holds a valid lease from the responder of its peer, which allows
+//! a limited number of requests within a period of time. Requests made without a valid lease are
+//! refused locally with a `REJECTED` error, so that a saturated responder can shed load by
+//! issuing fewer leases.
+//!
+//! Responders issue leases from a [`LeasePolicy`].
+//!
+//! [`ClientBuilder::set_lease`]: crate::ClientBuilder::set_lease
+use crate::frame::MAX_U31;
+use crate::Flux;
+
+use bytes::Bytes;
+use std::fmt;
+use std::time::Duration;
+
+/// A lease that allows the peer to send a number of requests within a period of time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    ttl: Duration,
+    number_of_requests: u32,
+    metadata: Option<Bytes>,
+}
+
+impl Lease {
+    /// Creates a new `Lease` that allows `number_of_requests` requests to be sent within `ttl`
+    /// from the time it is received.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `ttl` (in milliseconds) or `number_of_requests` is greater than
+    /// `MAX_U31` (2,147,483,647).
+    pub fn new(ttl: Duration, number_of_requests: u32) -> Lease {
+        assert!(ttl.as_millis() <= MAX_U31 as u128);
+        assert!(number_of_requests <= MAX_U31);
+        Lease { ttl, number_of_requests, metadata: None }
+    }
+
+    /// Sets the metadata sent along with the lease.
+    pub fn set_metadata(mut self, metadata: Bytes) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Returns the time that the lease is valid for.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Returns the number of requests that the lease allows.
+    pub fn number_of_requests(&self) -> u32 {
+        self.number_of_requests
+    }
+
+    /// Returns the metadata sent along with the lease, if any.
+    pub fn metadata(&self) -> Option<&Bytes> {
+        self.metadata.as_ref()
+    }
+}
+
+/// Decides the leases that a responder issues to the requester of its peer.
+///
+/// Each lease yielded by [`leases`](LeasePolicy::leases) is sent to the peer as soon as it is
+/// yielded, and replaces the lease issued before it. No more leases are issued once the stream
+/// ends.
+///
+/// # Examples
+///
+/// ```
+/// use binate::lease::{Lease, LeasePolicy};
+/// use binate::Flux;
+/// use std::time::Duration;
+///
+/// /// Issues a single lease that never expires.
+/// struct Unlimited;
+///
+/// impl LeasePolicy for Unlimited {
+///     fn leases(&self) -> Flux<Lease> {
+///         let lease = Lease::new(Duration::from_millis(0x7FFF_FFFF), 0x7FFF_FFFF);
+///         Box::pin(tokio_stream::once(lease))
+///     }
+/// }
+/// ```
+pub trait LeasePolicy: Send + Sync {
+    /// Returns the leases to issue over a connection.
+    ///
+    /// This is called once for each connection that leases are enabled on.
+    fn leases(&self) -> Flux<Lease>;
+}
+
+/// A [`LeasePolicy`] that issues the same lease every time the previous one expires.
+#[derive(Clone)]
+pub struct FixedLeasePolicy {
+    lease: Lease,
+}
+
+impl FixedLeasePolicy {
+    /// Creates a new `FixedLeasePolicy` that allows `number_of_requests` requests to be sent
+    /// every `ttl`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `ttl` is less than 1 millisecond, or if `ttl` (in milliseconds) or
+    /// `number_of_requests` is greater than `MAX_U31` (2,147,483,647).
+    pub fn new(ttl: Duration, number_of_requests: u32) -> FixedLeasePolicy {
+        assert!(ttl.as_millis() > 0);
+        FixedLeasePolicy { lease: Lease::new(ttl, number_of_requests) }
+    }
+}
+
+impl LeasePolicy for FixedLeasePolicy {
+    fn leases(&self) -> Flux<Lease> {
+        let lease = self.lease.clone();
+        Box::pin(futures_util::stream::unfold(true, move |first| {
+            let lease = lease.clone();
+            async move {
+                if !first {
+                    tokio::time::sleep(lease.ttl()).await;
+                }
+                Some((lease, false))
+            }
+        }))
+    }
+}
+
+impl fmt::Debug for FixedLeasePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FixedLeasePolicy")
+            .field("ttl", &self.lease.ttl)
+            .field("number_of_requests", &self.lease.number_of_requests)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    #[should_panic]
+    fn lease_exceeds_u31() {
+        Lease::new(Duration::from_secs(1), MAX_U31 + 1);
+    }
+
+    #[tokio::test]
+    async fn fixed_lease_policy() {
+        tokio::time::pause();
+        let ttl = Duration::from_millis(20);
+        let policy = FixedLeasePolicy::new(ttl, 3);
+        let mut leases = policy.leases();
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(leases.next().await, Some(Lease::new(ttl, 3)));
+        assert!(start.elapsed() < ttl);
+        assert_eq!(leases.next().await, Some(Lease::new(ttl, 3)));
+        assert!(start.elapsed() >= ttl);
+    }
+}
@@ -16,16 +16,32 @@ extern crate bitflags;
 pub(crate) mod macros;
 pub(crate) mod test_helpers;
 
+mod client;
 mod consts;
 mod error;
 mod payload;
 mod rsocket;
 mod runtime;
+mod server;
 mod types;
 
+pub mod auth;
 pub mod connection;
+pub mod extension;
+pub mod layer;
+pub mod lease;
 pub mod mimetype;
 pub mod prelude;
+pub mod resume;
+pub mod router;
+pub mod service;
+pub mod transport;
+pub mod zipkin;
+
+cfg_doc! {
+    #[any(feature = "json", feature = "cbor", feature = "msgpack")]
+    pub mod codec;
+}
 
 cfg_doc! {
     #[feature = "frame"]
@@ -37,6 +53,9 @@ cfg_not! {
     mod frame;
 }
 
+pub use self::client::{Client, ClientBuilder};
 pub use self::error::{Code, Error, Result};
+pub use self::frame::codec::SetupFrame;
 pub use self::payload::{Data, Metadata, Payload, PayloadBuilder};
 pub use self::rsocket::{Flux, Mono, RSocket};
+pub use self::server::{Server, ServerBuilder, SocketAcceptor};
@@ -1,128 +1,201 @@
-//! A list of wellknown MIME types.
+//! The registry of well-known MIME types.
+//!
+//! The well-known MIME types are listed by the RSocket [extensions], along with the 7-bit
+//! identifiers that stand for them in composite metadata. Any other MIME type is a
+//! [custom](MimeType::Custom) one, which is written out in full.
+//!
+//! [extensions]: https://github.com/rsocket/rsocket/blob/master/Extensions/WellKnownMimeTypes.md
+use std::fmt;
 
 /// Default mimetype for encoding metadata and data.
 pub const DEFAULT_MIMETYPE: &str = "application/binary";
 
-/// Well-known MIME types.
-#[rustfmt::skip]
-#[allow(missing_docs)]
-#[allow(non_camel_case_types)]
-#[non_exhaustive]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum WellKnownMimeType {
-    UNPARSEABLE,
-    APPLICATION_AVRO,
-    APPLICATION_CBOR,
-    APPLICATION_GRAPHQL,
-    APPLICATION_GZIP,
-    APPLICATION_JAVASCRIPT,
-    APPLICATION_JSON,
-    APPLICATION_OCTET_STREAM,
-    APPLICATION_PDF,
-    APPLICATION_VND_APACHE_THRIFT_BINARY,
-    APPLICATION_VND_GOOGLE_PROTOBUF,
-    APPLICATION_XML,
-    APPLICATION_ZIP,
-    AUDIO_AAC,
-    AUDIO_MP3,
-    AUDIO_MP4,
-    AUDIO_MPEG3,
-    AUDIO_MPEG,
-    AUDIO_OGG,
-    AUDIO_OPUS,
-    AUDIO_VORBIS,
-    IMAGE_BMP,
-    IMAGE_GIF,
-    IMAGE_HEIC_SEQUENCE,
-    IMAGE_HEIC,
-    IMAGE_HEIF_SEQUENCE,
-    IMAGE_HEIF,
-    IMAGE_JPEG,
-    IMAGE_PNG,
-    IMAGE_TIFF,
-    MULTIPART_MIXED,
-    TEXT_CSS,
-    TEXT_CSV,
-    TEXT_HTML,
-    TEXT_PLAIN,
-    TEXT_XML,
-    VIDEO_H264,
-    VIDEO_H265,
-    VIDEO_VP8,
-    APPLICATION_X_HESSIAN,
-    APPLICATION_X_JAVA_OBJECT,
-    APPLICATION_CLOUDEVENTS_JSON,
-    MESSAGE_X_RSOCKET_MIME_TYPE_V0,
-    MESSAGE_X_RSOCKET_ACCEPT_TIME_TYPES_V0,
-    MESSAGE_X_RSOCKET_AUTHENTICATION_V0,
-    MESSAGE_X_RSOCKET_TRACING_ZIPKIN_V0,
-    MESSAGE_X_RSOCKET_ROUTING_V0,
-    MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0,
+/// Defines the well-known MIME types, along with their identifiers and names.
+macro_rules! well_known_mime_types {
+    ($($name:ident = ($id:literal, $string:literal),)*) => {
+        /// Well-known MIME types.
+        #[allow(non_camel_case_types)]
+        #[non_exhaustive]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum WellKnownMimeType {
+            /// A MIME type that is not well-known.
+            UNPARSEABLE,
+            $(
+                #[doc = concat!("`", $string, "`")]
+                $name,
+            )*
+        }
+
+        impl WellKnownMimeType {
+            /// Returns the 7-bit identifier of this MIME type, which stands for it in composite
+            /// metadata, or `None` if it is `UNPARSEABLE`.
+            pub fn id(self) -> Option<u8> {
+                match self {
+                    $(WellKnownMimeType::$name => Some($id),)*
+                    WellKnownMimeType::UNPARSEABLE => None,
+                }
+            }
+
+            /// Returns the MIME type that the given 7-bit identifier stands for, or `None` if
+            /// the identifier is reserved.
+            pub fn from_id(id: u8) -> Option<WellKnownMimeType> {
+                match id {
+                    $($id => Some(WellKnownMimeType::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns the name of this MIME type, or an empty string if it is `UNPARSEABLE`.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(WellKnownMimeType::$name => $string,)*
+                    WellKnownMimeType::UNPARSEABLE => "",
+                }
+            }
+        }
+
+        impl From<&str> for WellKnownMimeType {
+            /// Returns the well-known MIME type of the given name, or `UNPARSEABLE` if it is not
+            /// well-known.
+            fn from(v: &str) -> Self {
+                match v {
+                    $($string => WellKnownMimeType::$name,)*
+                    _ => WellKnownMimeType::UNPARSEABLE,
+                }
+            }
+        }
+    };
 }
 
 #[rustfmt::skip]
-impl From<&str> for WellKnownMimeType {
-    fn from(v: &str) -> Self {
-        use WellKnownMimeType::*;
-        match v {
-            "application/avro" => APPLICATION_AVRO,
-            "application/cbor" => APPLICATION_CBOR,
-            "application/graphql" => APPLICATION_GRAPHQL,
-            "application/gzip" => APPLICATION_GZIP,
-            "application/javascript" => APPLICATION_JAVASCRIPT,
-            "application/json" => APPLICATION_JSON,
-            "application/octet-stream" => APPLICATION_OCTET_STREAM,
-            "application/pdf" => APPLICATION_PDF,
-            "application/vnd.apache.thrift.binary" => APPLICATION_VND_APACHE_THRIFT_BINARY,
-            "application/vnd.google.protobuf" => APPLICATION_VND_GOOGLE_PROTOBUF,
-            "application/xml" => APPLICATION_XML,
-            "application/zip" => APPLICATION_ZIP,
-            "audio/aac" => AUDIO_AAC,
-            "audio/mp3" => AUDIO_MP3,
-            "audio/mp4" => AUDIO_MP4,
-            "audio/mpeg3" => AUDIO_MPEG3,
-            "audio/mpeg" => AUDIO_MPEG,
-            "audio/ogg" => AUDIO_OGG,
-            "audio/opus" => AUDIO_OPUS,
-            "audio/vorbis" => AUDIO_VORBIS,
-            "image/bmp" => IMAGE_BMP,
-            "image/gif" => IMAGE_GIF,
-            "image/heic-sequence" => IMAGE_HEIC_SEQUENCE,
-            "image/heic" => IMAGE_HEIC,
-            "image/heif-sequence" => IMAGE_HEIF_SEQUENCE,
-            "image/heif" => IMAGE_HEIF,
-            "image/jpeg" => IMAGE_JPEG,
-            "image/png" => IMAGE_PNG,
-            "image/tiff" => IMAGE_TIFF,
-            "multipart/mixed" => MULTIPART_MIXED,
-            "text/css" => TEXT_CSS,
-            "text/csv" => TEXT_CSV,
-            "text/html" => TEXT_HTML,
-            "text/plain" => TEXT_PLAIN,
-            "text/xml" => TEXT_XML,
-            "video/H264" => VIDEO_H264,
-            "video/H265" => VIDEO_H265,
-            "video/VP8" => VIDEO_VP8,
-            "application/x-hessian" => APPLICATION_X_HESSIAN,
-            "application/x-java-object" => APPLICATION_X_JAVA_OBJECT,
-            "application/cloudevents+json" => APPLICATION_CLOUDEVENTS_JSON,
-            "message/x.rsocket.mime.type.v0" => MESSAGE_X_RSOCKET_MIME_TYPE_V0,
-            "message/x.rsocket.accept.time.types.v0" => MESSAGE_X_RSOCKET_ACCEPT_TIME_TYPES_V0,
-            "message/x.rsocket.authentication.v0" => MESSAGE_X_RSOCKET_AUTHENTICATION_V0,
-            "message/x.rsocket.tracing.zipkin.v0" => MESSAGE_X_RSOCKET_TRACING_ZIPKIN_V0,
-            "message/x.rsocket.routing.v0" => MESSAGE_X_RSOCKET_ROUTING_V0,
-            "message/x.rsocket.composite.metadata.v0" => MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0,
-            _ => UNPARSEABLE,
-        }
+well_known_mime_types! {
+    APPLICATION_AVRO                        = (0x00, "application/avro"),
+    APPLICATION_CBOR                        = (0x01, "application/cbor"),
+    APPLICATION_GRAPHQL                     = (0x02, "application/graphql"),
+    APPLICATION_GZIP                        = (0x03, "application/gzip"),
+    APPLICATION_JAVASCRIPT                  = (0x04, "application/javascript"),
+    APPLICATION_JSON                        = (0x05, "application/json"),
+    APPLICATION_OCTET_STREAM                = (0x06, "application/octet-stream"),
+    APPLICATION_PDF                         = (0x07, "application/pdf"),
+    APPLICATION_VND_APACHE_THRIFT_BINARY    = (0x08, "application/vnd.apache.thrift.binary"),
+    APPLICATION_VND_GOOGLE_PROTOBUF         = (0x09, "application/vnd.google.protobuf"),
+    APPLICATION_XML                         = (0x0A, "application/xml"),
+    APPLICATION_ZIP                         = (0x0B, "application/zip"),
+    AUDIO_AAC                               = (0x0C, "audio/aac"),
+    AUDIO_MP3                               = (0x0D, "audio/mp3"),
+    AUDIO_MP4                               = (0x0E, "audio/mp4"),
+    AUDIO_MPEG3                             = (0x0F, "audio/mpeg3"),
+    AUDIO_MPEG                              = (0x10, "audio/mpeg"),
+    AUDIO_OGG                               = (0x11, "audio/ogg"),
+    AUDIO_OPUS                              = (0x12, "audio/opus"),
+    AUDIO_VORBIS                            = (0x13, "audio/vorbis"),
+    IMAGE_BMP                               = (0x14, "image/bmp"),
+    IMAGE_GIF                               = (0x15, "image/gif"),
+    IMAGE_HEIC_SEQUENCE                     = (0x16, "image/heic-sequence"),
+    IMAGE_HEIC                              = (0x17, "image/heic"),
+    IMAGE_HEIF_SEQUENCE                     = (0x18, "image/heif-sequence"),
+    IMAGE_HEIF                              = (0x19, "image/heif"),
+    IMAGE_JPEG                              = (0x1A, "image/jpeg"),
+    IMAGE_PNG                               = (0x1B, "image/png"),
+    IMAGE_TIFF                              = (0x1C, "image/tiff"),
+    MULTIPART_MIXED                         = (0x1D, "multipart/mixed"),
+    TEXT_CSS                                = (0x1E, "text/css"),
+    TEXT_CSV                                = (0x1F, "text/csv"),
+    TEXT_HTML                               = (0x20, "text/html"),
+    TEXT_PLAIN                              = (0x21, "text/plain"),
+    TEXT_XML                                = (0x22, "text/xml"),
+    VIDEO_H264                              = (0x23, "video/H264"),
+    VIDEO_H265                              = (0x24, "video/H265"),
+    VIDEO_VP8                               = (0x25, "video/VP8"),
+    APPLICATION_X_HESSIAN                   = (0x26, "application/x-hessian"),
+    APPLICATION_X_JAVA_OBJECT               = (0x27, "application/x-java-object"),
+    APPLICATION_CLOUDEVENTS_JSON            = (0x28, "application/cloudevents+json"),
+    APPLICATION_X_CAPNP                     = (0x29, "application/x-capnp"),
+    APPLICATION_X_FLATBUFFERS               = (0x2A, "application/x-flatbuffers"),
+    MESSAGE_X_RSOCKET_MIME_TYPE_V0          = (0x7A, "message/x.rsocket.mime-type.v0"),
+    MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0  = (0x7B, "message/x.rsocket.accept-mime-types.v0"),
+    MESSAGE_X_RSOCKET_AUTHENTICATION_V0     = (0x7C, "message/x.rsocket.authentication.v0"),
+    MESSAGE_X_RSOCKET_TRACING_ZIPKIN_V0     = (0x7D, "message/x.rsocket.tracing-zipkin.v0"),
+    MESSAGE_X_RSOCKET_ROUTING_V0            = (0x7E, "message/x.rsocket.routing.v0"),
+    MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0 = (0x7F, "message/x.rsocket.composite-metadata.v0"),
+}
+
+impl WellKnownMimeType {
+    /// `message/x.rsocket.accept-mime-types.v0`, under the name it had before it was fixed.
+    #[deprecated(
+        note = "use `MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0` instead"
+    )]
+    pub const MESSAGE_X_RSOCKET_ACCEPT_TIME_TYPES_V0: WellKnownMimeType =
+        WellKnownMimeType::MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0;
+}
+
+impl fmt::Display for WellKnownMimeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 impl From<WellKnownMimeType> for &'static str {
     fn from(t: WellKnownMimeType) -> &'static str {
-        if t == WellKnownMimeType::UNPARSEABLE {
-            ""
-        } else {
-            t.into()
+        t.as_str()
+    }
+}
+
+/// A MIME type, which is either well-known, a custom string, or a reserved identifier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MimeType {
+    /// A well-known MIME type, which has a compact identifier.
+    WellKnown(WellKnownMimeType),
+    /// A custom MIME type.
+    Custom(String),
+    /// A compact identifier that is not a well-known MIME type, such as one added by a later
+    /// version of the protocol. It has no name, and is kept as is.
+    Reserved(u8),
+}
+
+impl MimeType {
+    /// Returns the name of this MIME type, or an empty string if it is reserved.
+    pub fn as_str(&self) -> &str {
+        match self {
+            MimeType::WellKnown(mime_type) => mime_type.as_str(),
+            MimeType::Custom(mime_type) => mime_type,
+            MimeType::Reserved(_) => "",
+        }
+    }
+}
+
+impl fmt::Display for MimeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<WellKnownMimeType> for MimeType {
+    fn from(mime_type: WellKnownMimeType) -> MimeType {
+        MimeType::WellKnown(mime_type)
+    }
+}
+
+impl From<&str> for MimeType {
+    /// Returns the well-known MIME type of the given string, or a custom one if it is not
+    /// well-known.
+    fn from(mime_type: &str) -> MimeType {
+        match WellKnownMimeType::from(mime_type) {
+            WellKnownMimeType::UNPARSEABLE => {
+                MimeType::Custom(mime_type.to_owned())
+            }
+            mime_type => MimeType::WellKnown(mime_type),
+        }
+    }
+}
+
+impl From<String> for MimeType {
+    /// Returns the well-known MIME type of the given string, or a custom one if it is not
+    /// well-known.
+    fn from(mime_type: String) -> MimeType {
+        match WellKnownMimeType::from(mime_type.as_str()) {
+            WellKnownMimeType::UNPARSEABLE => MimeType::Custom(mime_type),
+            mime_type => MimeType::WellKnown(mime_type),
         }
     }
 }
@@ -138,4 +211,75 @@ mod tests {
         assert_eq!(mime, WellKnownMimeType::UNPARSEABLE);
         assert_eq!(string, "");
     }
+
+    #[test]
+    fn ids() {
+        use WellKnownMimeType::*;
+        assert_eq!(APPLICATION_AVRO.id(), Some(0x00));
+        assert_eq!(APPLICATION_CLOUDEVENTS_JSON.id(), Some(0x28));
+        assert_eq!(APPLICATION_X_FLATBUFFERS.id(), Some(0x2A));
+        assert_eq!(MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0.id(), Some(0x7F));
+        assert_eq!(UNPARSEABLE.id(), None);
+        let mut count = 0;
+        for id in 0..0x80 {
+            if let Some(mime_type) = WellKnownMimeType::from_id(id) {
+                assert_eq!(mime_type.id(), Some(id));
+                count += 1;
+            }
+        }
+        assert_eq!(count, 49);
+        assert_eq!(WellKnownMimeType::from_id(0x2B), None);
+        assert_eq!(WellKnownMimeType::from_id(0x80), None);
+    }
+
+    #[test]
+    fn names() {
+        use WellKnownMimeType::*;
+        for id in 0..0x80 {
+            if let Some(mime_type) = WellKnownMimeType::from_id(id) {
+                assert_eq!(
+                    WellKnownMimeType::from(mime_type.as_str()),
+                    mime_type
+                );
+            }
+        }
+        // The extension MIME types, by identifier, as named by the RSocket extensions.
+        let extensions = [
+            (0x7A, "message/x.rsocket.mime-type.v0"),
+            (0x7B, "message/x.rsocket.accept-mime-types.v0"),
+            (0x7C, "message/x.rsocket.authentication.v0"),
+            (0x7D, "message/x.rsocket.tracing-zipkin.v0"),
+            (0x7E, "message/x.rsocket.routing.v0"),
+            (0x7F, "message/x.rsocket.composite-metadata.v0"),
+        ];
+        for &(id, name) in extensions.iter() {
+            let mime_type = WellKnownMimeType::from_id(id).unwrap();
+            assert_eq!(mime_type.as_str(), name);
+            assert_eq!(WellKnownMimeType::from(name).id(), Some(id));
+        }
+        #[allow(deprecated)]
+        let old = WellKnownMimeType::MESSAGE_X_RSOCKET_ACCEPT_TIME_TYPES_V0;
+        assert_eq!(old, MESSAGE_X_RSOCKET_ACCEPT_MIME_TYPES_V0);
+        let string: &'static str = APPLICATION_JSON.into();
+        assert_eq!(string, "application/json");
+        assert_eq!(TEXT_PLAIN.to_string(), "text/plain");
+    }
+
+    #[test]
+    fn mime_type() {
+        assert_eq!(
+            MimeType::from("application/json"),
+            MimeType::WellKnown(WellKnownMimeType::APPLICATION_JSON)
+        );
+        let custom = MimeType::from("application/x-custom".to_owned());
+        assert_eq!(
+            custom,
+            MimeType::Custom("application/x-custom".to_owned())
+        );
+        assert_eq!(custom.as_str(), "application/x-custom");
+        assert_eq!(
+            MimeType::from(WellKnownMimeType::APPLICATION_CBOR).to_string(),
+            "application/cbor"
+        );
+    }
 }
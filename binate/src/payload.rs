@@ -107,6 +107,10 @@ impl Payload {
     /// If `mtu` does not divide the the `metadata` and `data` of the payload, then the last chunk
     /// will not have length `mtu`.
     ///
+    /// The chunks do not account for the frame headers, so frames built from them may still
+    /// exceed `mtu`. Connections fragment the payloads sent by their MTU themselves (see
+    /// [`ClientBuilder::set_mtu`](crate::ClientBuilder::set_mtu)).
+    ///
     /// # Panics
     ///
     /// This function panics if the given `chunk_size` is `0`.
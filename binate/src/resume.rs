@@ -0,0 +1,1104 @@
+//! Storage of the frames kept for session resumption.
+//!
+//! When a session is resumable, every frame sent on a stream is saved in a
+//! [`ResumableFramesStore`] along with its implied position, until the peer acknowledges it.
+//! After the session is resumed over a new connection, the frames that the peer has not
+//! received are loaded from the store and sent again.
+//!
+//! A [`FileFramesStore`] keeps the sessions of a server on disk, so that they can also be
+//! resumed after the server process restarts.
+use crate::consts::DEFAULT_RESUME_BUFFER_CAPACITY;
+use crate::error::{Code, Error, Result};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use tracing::warn;
+
+/// Stores the encoded frames sent on resumable sessions, keyed by their resume tokens.
+///
+/// The frames of a session are saved in the order they are sent, each with its implied
+/// position, i.e. the total length of the frames saved before it. Implementations are
+/// expected to limit the size of the frames they keep, by evicting the oldest frames first.
+pub trait ResumableFramesStore: Send + Sync {
+    /// Saves a frame sent at the given position on the session identified by `token`.
+    fn save(&self, token: &Bytes, position: u64, frame: Bytes) -> Result<()>;
+
+    /// Drops the frames that end at or before the given position, which the peer has received.
+    fn release(&self, token: &Bytes, position: u64) -> Result<()>;
+
+    /// Returns the earliest position that the frames of the session can be loaded from.
+    fn first_available_position(&self, token: &Bytes) -> u64;
+
+    /// Loads the frames starting at or after the given position, in the order they are saved.
+    ///
+    /// An error with the code [`Code::RejectedResume`] is returned if the frames at the given
+    /// position have been evicted.
+    fn load(&self, token: &Bytes, position: u64) -> Result<Vec<Bytes>>;
+
+    /// Removes all the frames of the session identified by `token`.
+    fn remove(&self, token: &Bytes) -> Result<()>;
+
+    /// Saves the encoded SETUP frame of the session identified by `token`, so that the session
+    /// can be set up again when it is resumed after the process restarts.
+    ///
+    /// This does nothing by default, for stores that do not outlive the process.
+    fn save_setup(&self, _token: &Bytes, _setup: Bytes) -> Result<()> {
+        Ok(())
+    }
+
+    /// Saves the implied position of the frames received on the session identified by `token`.
+    ///
+    /// This does nothing by default, for stores that do not outlive the process.
+    fn save_received_position(
+        &self,
+        _token: &Bytes,
+        _position: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the sessions saved by a previous process that can be resumed, i.e. the ones whose
+    /// SETUP frames are saved.
+    ///
+    /// This returns no sessions by default, for stores that do not outlive the process.
+    fn saved_sessions(&self) -> Vec<SavedSession> {
+        Vec::new()
+    }
+}
+
+/// A session saved in a [`ResumableFramesStore`] by a previous process, which can be resumed
+/// after the process restarts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedSession {
+    token: Bytes,
+    setup: Bytes,
+    sent_position: u64,
+    received_position: u64,
+}
+
+impl SavedSession {
+    /// Creates a new `SavedSession` identified by `token`, which is set up with the given
+    /// encoded SETUP frame.
+    pub fn new(
+        token: Bytes,
+        setup: Bytes,
+        sent_position: u64,
+        received_position: u64,
+    ) -> SavedSession {
+        SavedSession { token, setup, sent_position, received_position }
+    }
+
+    /// Returns the resume token that identifies the session.
+    pub fn token(&self) -> &Bytes {
+        &self.token
+    }
+
+    /// Returns the encoded SETUP frame that the session is set up with.
+    pub fn setup(&self) -> &Bytes {
+        &self.setup
+    }
+
+    /// Returns the implied position of the frames sent, i.e. the end of the last frame saved.
+    pub fn sent_position(&self) -> u64 {
+        self.sent_position
+    }
+
+    /// Returns the implied position of the frames received.
+    pub fn received_position(&self) -> u64 {
+        self.received_position
+    }
+}
+
+/// A [`ResumableFramesStore`] that keeps the frames in memory.
+///
+/// The frames of each session are kept in a ring buffer, where the oldest frames are evicted
+/// once the total size of the frames exceeds the capacity.
+///
+/// # Examples
+///
+/// ```
+/// use binate::resume::{InMemoryFramesStore, ResumableFramesStore};
+/// use bytes::Bytes;
+///
+/// let store = InMemoryFramesStore::new(8);
+/// let token = Bytes::from("token");
+/// store.save(&token, 0, Bytes::from("12345")).unwrap();
+/// store.save(&token, 5, Bytes::from("67890")).unwrap();
+///
+/// // The first frame is evicted, as the frames exceed 8 bytes.
+/// assert_eq!(store.first_available_position(&token), 5);
+/// assert!(store.load(&token, 0).is_err());
+/// assert_eq!(store.load(&token, 5).unwrap(), vec![Bytes::from("67890")]);
+/// ```
+pub struct InMemoryFramesStore {
+    capacity: usize,
+    sessions: Mutex<HashMap<Bytes, FramesLog<Bytes>>>,
+}
+
+impl InMemoryFramesStore {
+    /// Creates a new `InMemoryFramesStore`, which keeps at most `capacity` bytes of frames for
+    /// each session.
+    pub fn new(capacity: usize) -> InMemoryFramesStore {
+        InMemoryFramesStore { capacity, sessions: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl Default for InMemoryFramesStore {
+    /// Creates a new `InMemoryFramesStore` with the default capacity (1 MiB).
+    fn default() -> Self {
+        InMemoryFramesStore::new(DEFAULT_RESUME_BUFFER_CAPACITY)
+    }
+}
+
+impl ResumableFramesStore for InMemoryFramesStore {
+    fn save(&self, token: &Bytes, position: u64, frame: Bytes) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let log = sessions.entry(token.clone()).or_insert_with(FramesLog::new);
+        let len = frame.len();
+        log.push(position, len, frame);
+        while log.size > self.capacity {
+            log.pop();
+        }
+        Ok(())
+    }
+
+    fn release(&self, token: &Bytes, position: u64) -> Result<()> {
+        if let Some(log) = self.sessions.lock().unwrap().get_mut(token) {
+            log.release(position);
+        }
+        Ok(())
+    }
+
+    fn first_available_position(&self, token: &Bytes) -> u64 {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .map_or(0, |log| log.first_available)
+    }
+
+    fn load(&self, token: &Bytes, position: u64) -> Result<Vec<Bytes>> {
+        let sessions = self.sessions.lock().unwrap();
+        let log = match sessions.get(token) {
+            Some(log) => log,
+            None => return Ok(Vec::new()),
+        };
+        log.check_available(position)?;
+        Ok(log.from(position).map(|(_, _, frame)| frame.clone()).collect())
+    }
+
+    fn remove(&self, token: &Bytes) -> Result<()> {
+        self.sessions.lock().unwrap().remove(token);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for InMemoryFramesStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryFramesStore")
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// A [`ResumableFramesStore`] that keeps the frames in files, one for each session.
+///
+/// The frames are kept on disk rather than in memory, so that sessions can keep large amounts
+/// of frames for replaying. Same as [`InMemoryFramesStore`], the oldest frames of a session are
+/// evicted once the total size of the frames exceeds the capacity.
+///
+/// The SETUP frames of the sessions that a server sets up, and the positions of the frames it
+/// receives, are kept in the files as well. When the store is opened again after the process
+/// restarts, these sessions are loaded, so that the server can set them up again and resume
+/// them (see [`ServerBuilder::set_resume_store`](crate::ServerBuilder::set_resume_store)). The
+/// state of the streams is lost with the process though: the frames saved are replayed to the
+/// client, but the requests in flight are not handled further.
+///
+/// Frames are written to the files on a dedicated thread, so that sending a frame does not wait
+/// for the disk. Loading the frames of a session, which is only done when it is resumed, waits
+/// for the frames saved before to be written and reads them back.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::resume::FileFramesStore;
+/// use binate::Server;
+///
+/// # fn main() -> binate::Result<()> {
+/// let store = FileFramesStore::open("/var/lib/rsocket/resume", 16 * 1024 * 1024)?;
+/// let server = Server::builder().set_resume().set_resume_store(store);
+/// # Ok(())
+/// # }
+/// ```
+pub struct FileFramesStore {
+    dir: PathBuf,
+    capacity: usize,
+    // The positions and lengths of the frames of each session, which are kept in the files.
+    sessions: Mutex<HashMap<Bytes, FramesLog<()>>>,
+    saved: Vec<SavedSession>,
+    writer: mpsc::Sender<Command>,
+}
+
+/// The file extension of the session files.
+const EXTENSION: &str = "frames";
+
+/// The file extension of the session files being compacted.
+const TMP_EXTENSION: &str = "tmp";
+
+/// The length of the header of a session file, which is the earliest available position, the
+/// position of the frames received and the length of the SETUP frame, followed by the SETUP
+/// frame itself.
+const HEADER_LEN: u64 = 20;
+
+/// The offset of the position of the frames received in a session file.
+const RECEIVED_POSITION_OFFSET: u64 = 8;
+
+/// The length of the header of a frame record, which is the position and the length of the
+/// frame.
+const RECORD_HEADER_LEN: u64 = 12;
+
+impl FileFramesStore {
+    /// Opens a `FileFramesStore` in the given directory, which keeps at most `capacity` bytes of
+    /// frames for each session.
+    ///
+    /// The directory is created if it does not exist, and the sessions saved in it with their
+    /// SETUP frames are loaded. Other session files, e.g. the ones of clients, which cannot be
+    /// resumed after the process restarts, are removed.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        capacity: usize,
+    ) -> Result<FileFramesStore> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+
+        let mut logs = HashMap::new();
+        let mut sessions = HashMap::new();
+        let mut saved = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let token = match session_token(&path) {
+                Some(token) => token,
+                None => continue,
+            };
+            if path.extension().is_some_and(|ext| ext == TMP_EXTENSION) {
+                // The session file itself is left as is when a compaction does not complete.
+                fs::remove_file(path)?;
+                continue;
+            }
+            match FileLog::load(path.clone()) {
+                Ok(log) if !log.setup.is_empty() => {
+                    saved.push(SavedSession::new(
+                        token.clone(),
+                        log.setup.clone(),
+                        log.frames.end(),
+                        log.received_position,
+                    ));
+                    sessions.insert(token.clone(), log.frames.positions());
+                    logs.insert(token, Some(log));
+                }
+                Ok(_) => fs::remove_file(path)?,
+                Err(err) => {
+                    warn!(
+                        "failed to load session file {}: {}",
+                        path.display(),
+                        err
+                    );
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
+        let (writer, commands) = mpsc::channel();
+        let files = SessionFiles { dir: dir.clone(), capacity, logs };
+        thread::Builder::new()
+            .name("binate-frames-store".into())
+            .spawn(move || files.run(commands))?;
+        Ok(FileFramesStore {
+            dir,
+            capacity,
+            sessions: Mutex::new(sessions),
+            saved,
+            writer,
+        })
+    }
+
+    /// Returns the directory that the session files are kept in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the resume tokens of the sessions in this store.
+    pub fn tokens(&self) -> Vec<Bytes> {
+        self.sessions.lock().unwrap().keys().cloned().collect()
+    }
+
+    #[cfg(test)]
+    fn path(&self, token: &Bytes) -> PathBuf {
+        session_path(&self.dir, token)
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.writer.send(command).map_err(|_| {
+            io::Error::other("the frames store writer has stopped").into()
+        })
+    }
+}
+
+impl ResumableFramesStore for FileFramesStore {
+    fn save(&self, token: &Bytes, position: u64, frame: Bytes) -> Result<()> {
+        // The commands are sent with the sessions locked, so that they are in the same order
+        // as the frames are saved.
+        let mut sessions = self.sessions.lock().unwrap();
+        let log = sessions.entry(token.clone()).or_insert_with(FramesLog::new);
+        log.push(position, frame.len(), ());
+        while log.size > self.capacity {
+            log.pop();
+        }
+        self.send(Command::Save { token: token.clone(), position, frame })
+    }
+
+    fn release(&self, token: &Bytes, position: u64) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(token) {
+            Some(log) => {
+                log.release(position);
+                self.send(Command::Release { token: token.clone(), position })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn first_available_position(&self, token: &Bytes) -> u64 {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .map_or(0, |log| log.first_available)
+    }
+
+    fn load(&self, token: &Bytes, position: u64) -> Result<Vec<Bytes>> {
+        let (reply, result) = mpsc::sync_channel(1);
+        {
+            let sessions = self.sessions.lock().unwrap();
+            if let Some(log) = sessions.get(token) {
+                log.check_available(position)?;
+            }
+            self.send(Command::Load {
+                token: token.clone(),
+                position,
+                reply,
+            })?;
+        }
+        result.recv().map_err(|_| {
+            io::Error::other("the frames store writer has stopped")
+        })?
+    }
+
+    fn remove(&self, token: &Bytes) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.remove(token).is_some() {
+            self.send(Command::Remove { token: token.clone() })?;
+        }
+        Ok(())
+    }
+
+    fn save_setup(&self, token: &Bytes, setup: Bytes) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.entry(token.clone()).or_insert_with(FramesLog::new);
+        self.send(Command::SaveSetup { token: token.clone(), setup })
+    }
+
+    fn save_received_position(
+        &self,
+        token: &Bytes,
+        position: u64,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.entry(token.clone()).or_insert_with(FramesLog::new);
+        self.send(Command::SaveReceivedPosition {
+            token: token.clone(),
+            position,
+        })
+    }
+
+    fn saved_sessions(&self) -> Vec<SavedSession> {
+        self.saved.clone()
+    }
+}
+
+impl fmt::Debug for FileFramesStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileFramesStore")
+            .field("dir", &self.dir)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// A change to the session files, or a read of them, done on the writer thread.
+enum Command {
+    Save {
+        token: Bytes,
+        position: u64,
+        frame: Bytes,
+    },
+    Release {
+        token: Bytes,
+        position: u64,
+    },
+    Load {
+        token: Bytes,
+        position: u64,
+        reply: mpsc::SyncSender<Result<Vec<Bytes>>>,
+    },
+    Remove {
+        token: Bytes,
+    },
+    SaveSetup {
+        token: Bytes,
+        setup: Bytes,
+    },
+    SaveReceivedPosition {
+        token: Bytes,
+        position: u64,
+    },
+}
+
+/// The session files of a [`FileFramesStore`], owned by its writer thread.
+struct SessionFiles {
+    dir: PathBuf,
+    capacity: usize,
+    // `None` if the frames of the session are lost, after failing to write them.
+    logs: HashMap<Bytes, Option<FileLog>>,
+}
+
+impl SessionFiles {
+    /// Runs the commands until the store is dropped.
+    fn run(mut self, commands: mpsc::Receiver<Command>) {
+        for command in commands {
+            match command {
+                Command::Save { token, position, frame } => {
+                    if let Err(err) = self.save(&token, position, &frame) {
+                        self.fail(&token, err);
+                    }
+                }
+                Command::Release { token, position } => {
+                    if let Err(err) = self.release(&token, position) {
+                        self.fail(&token, err);
+                    }
+                }
+                Command::Load { token, position, reply } => {
+                    let _ = reply.send(self.load(&token, position));
+                }
+                Command::Remove { token } => self.remove(&token),
+                Command::SaveSetup { token, setup } => {
+                    let result = self.log(&token).and_then(|log| match log {
+                        Some(log) => log.set_setup(setup),
+                        None => Ok(()),
+                    });
+                    if let Err(err) = result {
+                        self.fail(&token, err);
+                    }
+                }
+                Command::SaveReceivedPosition { token, position } => {
+                    let result = self.log(&token).and_then(|log| match log {
+                        Some(log) => Ok(log.set_received_position(position)?),
+                        None => Ok(()),
+                    });
+                    if let Err(err) = result {
+                        self.fail(&token, err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn save(
+        &mut self,
+        token: &Bytes,
+        position: u64,
+        frame: &Bytes,
+    ) -> Result<()> {
+        let capacity = self.capacity;
+        let log = match self.log(token)? {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        log.append(position, frame)?;
+        while log.frames.size > capacity {
+            log.frames.pop();
+        }
+        log.commit()
+    }
+
+    /// Returns the file of the session, which is created if there is none yet, or `None` if the
+    /// frames of the session are lost.
+    fn log(&mut self, token: &Bytes) -> Result<Option<&mut FileLog>> {
+        if !self.logs.contains_key(token) {
+            let path = session_path(&self.dir, token);
+            let log = FileLog::create(path, 0, 0, Bytes::new())?;
+            self.logs.insert(token.clone(), Some(log));
+        }
+        Ok(self.logs.get_mut(token).and_then(Option::as_mut))
+    }
+
+    fn release(&mut self, token: &Bytes, position: u64) -> Result<()> {
+        match self.logs.get_mut(token) {
+            Some(Some(log)) => {
+                log.frames.release(position);
+                log.commit()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn load(&mut self, token: &Bytes, position: u64) -> Result<Vec<Bytes>> {
+        match self.logs.get_mut(token) {
+            Some(Some(log)) => log.read(position),
+            Some(None) => Err(Error::with_code(
+                Code::RejectedResume,
+                "frames of the session have been lost",
+            )),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn remove(&mut self, token: &Bytes) {
+        if let Some(Some(log)) = self.logs.remove(token) {
+            if let Err(err) = fs::remove_file(&log.path) {
+                warn!("failed to remove session file: {}", err);
+            }
+        }
+    }
+
+    /// Drops the frames of a session that cannot be written, so that it is not resumed with
+    /// frames missing.
+    fn fail(&mut self, token: &Bytes, err: Error) {
+        warn!("failed to write resumable frames: {}", err);
+        self.logs.insert(token.clone(), None);
+        let _ = fs::remove_file(session_path(&self.dir, token));
+    }
+}
+
+/// The frames of a session, from the earliest available position.
+///
+/// `T` is either the frame itself, or the offset of the frame in a file.
+struct FramesLog<T> {
+    frames: VecDeque<(u64, usize, T)>,
+    first_available: u64,
+    size: usize,
+}
+
+impl<T> FramesLog<T> {
+    fn new() -> FramesLog<T> {
+        FramesLog { frames: VecDeque::new(), first_available: 0, size: 0 }
+    }
+
+    fn push(&mut self, position: u64, len: usize, frame: T) {
+        if self.frames.is_empty() {
+            self.first_available = position;
+        }
+        self.size += len;
+        self.frames.push_back((position, len, frame));
+    }
+
+    fn pop(&mut self) {
+        if let Some((position, len, _)) = self.frames.pop_front() {
+            self.size -= len;
+            self.first_available = position + len as u64;
+        }
+    }
+
+    fn release(&mut self, position: u64) {
+        while let Some((start, len, _)) = self.frames.front() {
+            if start + *len as u64 > position {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn check_available(&self, position: u64) -> Result<()> {
+        if position < self.first_available {
+            return Err(Error::with_code(
+                Code::RejectedResume,
+                format!(
+                    "frames from position {} have been evicted, the earliest available \
+                     position is {}",
+                    position, self.first_available
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn from(&self, position: u64) -> impl Iterator<Item = &(u64, usize, T)> {
+        self.frames.iter().skip_while(move |(start, _, _)| *start < position)
+    }
+
+    /// Returns the position that the frames end at.
+    fn end(&self) -> u64 {
+        self.frames.back().map_or(self.first_available, |(start, len, _)| {
+            start + *len as u64
+        })
+    }
+
+    /// Returns the positions and lengths of the frames, without the frames.
+    fn positions(&self) -> FramesLog<()> {
+        FramesLog {
+            frames: self
+                .frames
+                .iter()
+                .map(|(p, len, _)| (*p, *len, ()))
+                .collect(),
+            first_available: self.first_available,
+            size: self.size,
+        }
+    }
+}
+
+/// A session file, which starts with the header, followed by the frame records.
+///
+/// Evicted and released frames are not removed from the file until they take up more space
+/// than the frames available.
+struct FileLog {
+    path: PathBuf,
+    file: File,
+    frames: FramesLog<u64>,
+    // The earliest available position written in the file.
+    header: u64,
+    received_position: u64,
+    setup: Bytes,
+    len: u64,
+}
+
+impl FileLog {
+    fn create(
+        path: PathBuf,
+        position: u64,
+        received_position: u64,
+        setup: Bytes,
+    ) -> io::Result<FileLog> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        let mut header =
+            BytesMut::with_capacity(HEADER_LEN as usize + setup.len());
+        header.put_u64(position);
+        header.put_u64(received_position);
+        header.put_u32(setup.len() as u32);
+        header.put_slice(&setup);
+        file.write_all(&header)?;
+        let mut frames = FramesLog::new();
+        frames.first_available = position;
+        Ok(FileLog {
+            path,
+            file,
+            frames,
+            header: position,
+            received_position,
+            setup,
+            len: header.len() as u64,
+        })
+    }
+
+    fn load(path: PathBuf) -> io::Result<FileLog> {
+        let mut file =
+            OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut buf = &buf[..];
+
+        if buf.len() < HEADER_LEN as usize {
+            return Err(invalid_header());
+        }
+        let header = buf.get_u64();
+        let received_position = buf.get_u64();
+        let setup_len = buf.get_u32() as usize;
+        if buf.len() < setup_len {
+            return Err(invalid_header());
+        }
+        let setup = Bytes::copy_from_slice(&buf[..setup_len]);
+        buf.advance(setup_len);
+
+        let mut frames = FramesLog::new();
+        frames.first_available = header;
+        let mut offset = HEADER_LEN + setup_len as u64;
+        while buf.len() >= RECORD_HEADER_LEN as usize {
+            let position = buf.get_u64();
+            let len = buf.get_u32() as usize;
+            if buf.len() < len {
+                break;
+            }
+            buf.advance(len);
+            // Skips the frames dropped before the file is compacted.
+            if position >= header {
+                frames.push(position, len, offset + RECORD_HEADER_LEN);
+            }
+            offset += RECORD_HEADER_LEN + len as u64;
+        }
+        // Drops the partial record written when the process exits, if any.
+        file.set_len(offset)?;
+        Ok(FileLog {
+            path,
+            file,
+            frames,
+            header,
+            received_position,
+            setup,
+            len: offset,
+        })
+    }
+
+    fn append(&mut self, position: u64, frame: &Bytes) -> io::Result<()> {
+        let mut record = BytesMut::with_capacity(12 + frame.len());
+        record.put_u64(position);
+        record.put_u32(frame.len() as u32);
+        record.put_slice(frame);
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&record)?;
+
+        self.frames.push(position, frame.len(), self.len + RECORD_HEADER_LEN);
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    fn read(&mut self, position: u64) -> Result<Vec<Bytes>> {
+        self.frames.check_available(position)?;
+        let mut frames = Vec::new();
+        for (_, len, offset) in self.frames.from(position) {
+            let mut frame = vec![0; *len];
+            self.file.seek(SeekFrom::Start(*offset))?;
+            self.file.read_exact(&mut frame)?;
+            frames.push(Bytes::from(frame));
+        }
+        Ok(frames)
+    }
+
+    fn set_received_position(&mut self, position: u64) -> io::Result<()> {
+        if self.received_position != position {
+            self.file.seek(SeekFrom::Start(RECEIVED_POSITION_OFFSET))?;
+            self.file.write_all(&position.to_be_bytes())?;
+            self.received_position = position;
+        }
+        Ok(())
+    }
+
+    /// Saves the SETUP frame in the header, which is rewritten along with the frames.
+    fn set_setup(&mut self, setup: Bytes) -> Result<()> {
+        self.setup = setup;
+        self.rewrite()
+    }
+
+    /// Writes the earliest available position to the file, after frames are dropped.
+    fn commit(&mut self) -> Result<()> {
+        if self.header != self.frames.first_available {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(&self.frames.first_available.to_be_bytes())?;
+            self.header = self.frames.first_available;
+        }
+        self.compact()
+    }
+
+    /// Rewrites the file with the frames available, if the frames dropped take up more space.
+    fn compact(&mut self) -> Result<()> {
+        let live = self.frames.size as u64
+            + self.frames.frames.len() as u64 * RECORD_HEADER_LEN;
+        let records = self.len - HEADER_LEN - self.setup.len() as u64;
+        if records <= 2 * live {
+            return Ok(());
+        }
+        self.rewrite()
+    }
+
+    /// Rewrites the file with the header and the frames available.
+    fn rewrite(&mut self) -> Result<()> {
+        let mut frames = Vec::with_capacity(self.frames.frames.len());
+        for (position, len, offset) in self.frames.frames.iter() {
+            let mut frame = vec![0; *len];
+            self.file.seek(SeekFrom::Start(*offset))?;
+            self.file.read_exact(&mut frame)?;
+            frames.push((*position, Bytes::from(frame)));
+        }
+
+        // Writes to a temporary file first, so that the frames are not lost if the process
+        // exits in the middle.
+        let tmp = self.path.with_extension(TMP_EXTENSION);
+        let mut log = FileLog::create(
+            tmp,
+            self.frames.first_available,
+            self.received_position,
+            self.setup.clone(),
+        )?;
+        for (position, frame) in frames {
+            log.append(position, &frame)?;
+        }
+        log.file.sync_data()?;
+        fs::rename(&log.path, &self.path)?;
+        log.path = self.path.clone();
+        *self = log;
+        Ok(())
+    }
+}
+
+fn invalid_header() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated session file header")
+}
+
+fn session_path(dir: &Path, token: &Bytes) -> PathBuf {
+    dir.join(format!("{}.{}", encode_hex(token), EXTENSION))
+}
+
+/// Returns the resume token of the given session file, or session file being compacted.
+fn session_token(path: &Path) -> Option<Bytes> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if !matches!(extension, Some(EXTENSION) | Some(TMP_EXTENSION)) {
+        return None;
+    }
+    path.file_stem().and_then(|stem| stem.to_str()).and_then(decode_hex)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Bytes> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .map(Bytes::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "binate-resume-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+
+    /// Saves 4 frames of 10 bytes each.
+    fn save_frames(store: &dyn ResumableFramesStore, token: &Bytes) {
+        for (i, frame) in ["0", "1", "2", "3"].iter().enumerate() {
+            let frame = Bytes::from(frame.repeat(10));
+            store.save(token, i as u64 * 10, frame).unwrap();
+        }
+    }
+
+    fn frame(data: &str) -> Bytes {
+        Bytes::from(data.repeat(10))
+    }
+
+    /// Waits for the frames saved so far to be written to the files.
+    fn flush(store: &FileFramesStore) {
+        store.load(&Bytes::new(), 0).unwrap();
+    }
+
+    fn check_store(store: &dyn ResumableFramesStore) {
+        let token = Bytes::from("token");
+        save_frames(store, &token);
+
+        // Frames beyond the capacity are evicted.
+        assert_eq!(store.first_available_position(&token), 10);
+        let err = store.load(&token, 0).unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+        assert_eq!(
+            store.load(&token, 10).unwrap(),
+            vec![frame("1"), frame("2"), frame("3")]
+        );
+        assert_eq!(store.load(&token, 30).unwrap(), vec![frame("3")]);
+        assert_eq!(store.load(&token, 40).unwrap(), Vec::<Bytes>::new());
+
+        // Frames that end after the position are kept.
+        store.release(&token, 25).unwrap();
+        assert_eq!(store.first_available_position(&token), 20);
+        assert_eq!(
+            store.load(&token, 20).unwrap(),
+            vec![frame("2"), frame("3")]
+        );
+
+        // Sessions are independent of each other.
+        let other = Bytes::from("other");
+        assert_eq!(store.first_available_position(&other), 0);
+        assert!(store.load(&other, 0).unwrap().is_empty());
+
+        store.remove(&token).unwrap();
+        assert_eq!(store.first_available_position(&token), 0);
+        assert!(store.load(&token, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<InMemoryFramesStore>();
+        assert_sync::<InMemoryFramesStore>();
+        assert_send::<FileFramesStore>();
+        assert_sync::<FileFramesStore>();
+    }
+
+    #[test]
+    fn in_memory() {
+        check_store(&InMemoryFramesStore::new(30));
+    }
+
+    #[test]
+    fn file() {
+        let dir = temp_dir();
+        check_store(&FileFramesStore::open(&dir, 30).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_reopen() {
+        let dir = temp_dir();
+        let token = Bytes::from(vec![0, 1, 0xfe, 0xff]);
+        let client = Bytes::from("client");
+        let setup = Bytes::from("setup");
+        let (path, client_path, tmp) = {
+            let store = FileFramesStore::open(&dir, 30).unwrap();
+            save_frames(&store, &token);
+            store.release(&token, 20).unwrap();
+            // Rewrites the file with the SETUP frame in its header.
+            store.save_setup(&token, setup.clone()).unwrap();
+            store.save_received_position(&token, 7).unwrap();
+            save_frames(&store, &client);
+            flush(&store);
+            (
+                store.path(&token),
+                store.path(&client),
+                store.path(&token).with_extension(TMP_EXTENSION),
+            )
+        };
+        assert!(path.exists() && client_path.exists());
+        let other = dir.join("other.frames");
+        fs::write(&other, b"not a session").unwrap();
+        fs::write(&tmp, b"partial compaction").unwrap();
+
+        // Only the sessions with their SETUP frames saved can be set up again.
+        let store = FileFramesStore::open(&dir, 30).unwrap();
+        assert_eq!(store.tokens(), vec![token.clone()]);
+        assert_eq!(
+            store.saved_sessions(),
+            vec![SavedSession::new(token.clone(), setup, 40, 7)]
+        );
+        assert!(!client_path.exists());
+        assert!(!tmp.exists());
+        assert!(other.exists());
+
+        // The frames are replayed from the saved position.
+        assert_eq!(store.first_available_position(&token), 20);
+        assert_eq!(
+            store.load(&token, 20).unwrap(),
+            vec![frame("2"), frame("3")]
+        );
+        let err = store.load(&token, 10).unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+
+        store.save(&token, 40, frame("4")).unwrap();
+        assert_eq!(
+            store.load(&token, 20).unwrap(),
+            vec![frame("2"), frame("3"), frame("4")]
+        );
+        store.remove(&token).unwrap();
+        flush(&store);
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_drops_partial_record() {
+        let dir = temp_dir();
+        let token = Bytes::from("token");
+        let path = {
+            let store = FileFramesStore::open(&dir, 100).unwrap();
+            store.save_setup(&token, Bytes::from("setup")).unwrap();
+            save_frames(&store, &token);
+            flush(&store);
+            store.path(&token)
+        };
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 5)
+            .unwrap();
+
+        let store = FileFramesStore::open(&dir, 100).unwrap();
+        assert_eq!(store.saved_sessions()[0].sent_position(), 30);
+        assert_eq!(
+            store.load(&token, 0).unwrap(),
+            vec![frame("0"), frame("1"), frame("2")]
+        );
+        store.save(&token, 30, frame("3")).unwrap();
+        assert_eq!(store.load(&token, 30).unwrap(), vec![frame("3")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_compaction() {
+        let dir = temp_dir();
+        let token = Bytes::from("token");
+        let store = FileFramesStore::open(&dir, 100).unwrap();
+        save_frames(&store, &token);
+        flush(&store);
+        let path = store.path(&token);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 20 + 4 * 22);
+
+        // Dropped frames are kept until they take up more space than the frames available.
+        store.release(&token, 10).unwrap();
+        flush(&store);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 20 + 4 * 22);
+        store.release(&token, 30).unwrap();
+        flush(&store);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 20 + 22);
+        assert_eq!(store.load(&token, 30).unwrap(), vec![frame("3")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_write_error() {
+        let dir = temp_dir();
+        let token = Bytes::from("token");
+        let store = FileFramesStore::open(&dir, 100).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The session is not resumed with the frames that are not written.
+        store.save(&token, 0, frame("0")).unwrap();
+        let err = store.load(&token, 0).unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+
+        store.remove(&token).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        store.save(&token, 0, frame("0")).unwrap();
+        assert_eq!(store.load(&token, 0).unwrap(), vec![frame("0")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(encode_hex(&[0, 1, 0xab, 0xff]), "0001abff");
+        assert_eq!(decode_hex("0001abff").unwrap(), &[0, 1, 0xab, 0xff][..]);
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
@@ -0,0 +1,548 @@
+//! Routing of requests to handlers by their routes.
+//!
+//! A [`Router`] is a responder that dispatches each request to the handler registered for the
+//! route in its [routing metadata](crate::extension::RoutingMetadata), which lets a single
+//! connection expose many endpoints, like the `@MessageMapping` methods of Spring.
+//!
+//! # Examples
+//!
+//! ```
+//! use binate::router::Router;
+//! use binate::Payload;
+//!
+//! let router = Router::new()
+//!     .add_request_response("echo", |payload: Payload, _| {
+//!         Box::pin(async move { Ok(payload) })
+//!     })
+//!     .add_request_stream("users.{id}.events", |_, params| {
+//!         let id = params.get("id").unwrap().to_owned();
+//!         let event = Payload::builder().set_data(id).build();
+//!         Box::pin(tokio_stream::once(Ok(event)))
+//!     });
+//! ```
+use crate::error::{Code, Error, Result};
+use crate::extension::{metadata_entry, RoutingMetadata};
+use crate::mimetype::WellKnownMimeType;
+use crate::payload::{Metadata, Payload};
+use crate::rsocket::with_first_payload;
+use crate::{Flux, Mono, RSocket};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+type RequestResponseHandler =
+    Arc<dyn Fn(Payload, Params) -> Mono<Result<Payload>> + Send + Sync>;
+type RequestStreamHandler =
+    Arc<dyn Fn(Payload, Params) -> Flux<Result<Payload>> + Send + Sync>;
+type RequestChannelHandler = Arc<
+    dyn Fn(Flux<Result<Payload>>, Params) -> Flux<Result<Payload>>
+        + Send
+        + Sync,
+>;
+type FireAndForgetHandler =
+    Arc<dyn Fn(Payload, Params) -> Result<()> + Send + Sync>;
+
+/// A responder that dispatches requests to handlers by their routes.
+///
+/// The route of a request is the first tag of the routing metadata in its (first) payload. By
+/// default the metadata is expected to be [composite](crate::extension::CompositeMetadata),
+/// with a routing entry, as sent by Spring. See
+/// [`set_metadata_mime_type`](Router::set_metadata_mime_type) for routing metadata on its own.
+///
+/// Routes may have path variables, such as `users.{id}`, where `{id}` matches any part of the
+/// route up to the next `.` or `/`. The values of the path variables are passed to the handlers
+/// as [`Params`]. Routes without path variables take precedence, and otherwise routes are
+/// matched in the order that they are added.
+///
+/// Requests without a route are failed with an `INVALID` error, and requests without a handler
+/// for their route with an `APPLICATION_ERROR`. Metadata-push requests are rejected, as they
+/// carry no routing metadata.
+#[derive(Clone)]
+pub struct Router {
+    metadata_mime_type: WellKnownMimeType,
+    request_response: Routes<RequestResponseHandler>,
+    request_stream: Routes<RequestStreamHandler>,
+    request_channel: Arc<Routes<RequestChannelHandler>>,
+    fire_and_forget: Routes<FireAndForgetHandler>,
+}
+
+/// The values of the path variables of a route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Router {
+    /// Creates a new `Router` without any routes.
+    pub fn new() -> Router {
+        Router {
+            metadata_mime_type:
+                WellKnownMimeType::MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0,
+            request_response: Routes::new(),
+            request_stream: Routes::new(),
+            request_channel: Arc::new(Routes::new()),
+            fire_and_forget: Routes::new(),
+        }
+    }
+
+    /// Sets the MIME type of the metadata of the requests, which is either
+    /// `MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0` (the default) or `MESSAGE_X_RSOCKET_ROUTING_V0`.
+    ///
+    /// This should be the metadata MIME type in the SETUP frame of the connection.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given MIME type is neither composite nor routing metadata.
+    pub fn set_metadata_mime_type(
+        mut self,
+        mime_type: WellKnownMimeType,
+    ) -> Self {
+        assert!(matches!(
+            mime_type,
+            WellKnownMimeType::MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0
+                | WellKnownMimeType::MESSAGE_X_RSOCKET_ROUTING_V0
+        ));
+        self.metadata_mime_type = mime_type;
+        self
+    }
+
+    /// Adds a handler of the request-response requests to the given route.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the route has a path variable that is unterminated or unnamed.
+    pub fn add_request_response<F>(mut self, route: &str, handler: F) -> Self
+    where
+        F: Fn(Payload, Params) -> Mono<Result<Payload>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.request_response.insert(route, Arc::new(handler));
+        self
+    }
+
+    /// Adds a handler of the request-stream requests to the given route.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the route has a path variable that is unterminated or unnamed.
+    pub fn add_request_stream<F>(mut self, route: &str, handler: F) -> Self
+    where
+        F: Fn(Payload, Params) -> Flux<Result<Payload>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.request_stream.insert(route, Arc::new(handler));
+        self
+    }
+
+    /// Adds a handler of the request-channel requests to the given route.
+    ///
+    /// The handler receives all the payloads of the channel, including the first one that
+    /// carries the route.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the route has a path variable that is unterminated or unnamed.
+    pub fn add_request_channel<F>(mut self, route: &str, handler: F) -> Self
+    where
+        F: Fn(Flux<Result<Payload>>, Params) -> Flux<Result<Payload>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Arc::make_mut(&mut self.request_channel)
+            .insert(route, Arc::new(handler));
+        self
+    }
+
+    /// Adds a handler of the fire-and-forget requests to the given route.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the route has a path variable that is unterminated or unnamed.
+    pub fn add_fire_and_forget<F>(mut self, route: &str, handler: F) -> Self
+    where
+        F: Fn(Payload, Params) -> Result<()> + Send + Sync + 'static,
+    {
+        self.fire_and_forget.insert(route, Arc::new(handler));
+        self
+    }
+}
+
+/// Returns the route in the metadata of the given payload.
+fn route(mime_type: WellKnownMimeType, payload: &Payload) -> Result<String> {
+    let no_route = || Error::with_code(Code::Invalid, "no route in metadata");
+    let routing = metadata_entry(
+        mime_type,
+        payload.metadata(),
+        WellKnownMimeType::MESSAGE_X_RSOCKET_ROUTING_V0,
+    )?
+    .ok_or_else(no_route)?;
+    let routing = RoutingMetadata::decode(&routing)?;
+    routing.route().map(str::to_owned).ok_or_else(no_route)
+}
+
+fn no_handler(route: &str) -> Error {
+    Error::with_code(
+        Code::ApplicationError,
+        format!("no handler for route '{}'", route),
+    )
+}
+
+impl RSocket for Router {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        let result =
+            route(self.metadata_mime_type, &payload).and_then(|route| {
+                self.request_response
+                    .find(&route)
+                    .ok_or_else(|| no_handler(&route))
+            });
+        match result {
+            Ok((handler, params)) => handler(payload, params),
+            Err(err) => Box::pin(async move { Err(err) }),
+        }
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        let result =
+            route(self.metadata_mime_type, &payload).and_then(|route| {
+                self.request_stream
+                    .find(&route)
+                    .ok_or_else(|| no_handler(&route))
+            });
+        match result {
+            Ok((handler, params)) => handler(payload, params),
+            Err(err) => Box::pin(tokio_stream::once(Err(err))),
+        }
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        let mime_type = self.metadata_mime_type;
+        let routes = self.request_channel.clone();
+        // The route is in the first payload.
+        with_first_payload(payloads, move |first, payloads| {
+            let result = route(mime_type, first).and_then(|route| {
+                routes.find(&route).ok_or_else(|| no_handler(&route))
+            });
+            match result {
+                Ok((handler, params)) => handler(payloads, params),
+                Err(err) => Box::pin(tokio_stream::once(Err(err))),
+            }
+        })
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        let route = route(self.metadata_mime_type, &payload)?;
+        let (handler, params) = self
+            .fire_and_forget
+            .find(&route)
+            .ok_or_else(|| no_handler(&route))?;
+        handler(payload, params)
+    }
+
+    fn metadata_push(&self, _metadata: Metadata) -> Mono<Result<()>> {
+        Box::pin(async {
+            Err(Error::with_code(
+                Code::Rejected,
+                "metadata-push is not routed",
+            ))
+        })
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("metadata_mime_type", &self.metadata_mime_type)
+            .field("request_response", &self.request_response)
+            .field("request_stream", &self.request_stream)
+            .field("request_channel", &self.request_channel)
+            .field("fire_and_forget", &self.fire_and_forget)
+            .finish()
+    }
+}
+
+impl Params {
+    /// Returns the value of the given path variable, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns an iterator over the names and values of the path variables.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns the number of path variables.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns true if there are no path variables.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+/// The handlers of an interaction model, by their routes.
+#[derive(Clone)]
+struct Routes<H> {
+    exact: HashMap<String, H>,
+    patterns: Vec<(Pattern, H)>,
+}
+
+/// A route with path variables.
+#[derive(Debug, Clone)]
+struct Pattern {
+    route: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+impl<H: Clone> Routes<H> {
+    fn new() -> Routes<H> {
+        Routes { exact: HashMap::new(), patterns: Vec::new() }
+    }
+
+    fn insert(&mut self, route: &str, handler: H) {
+        let pattern = Pattern::parse(route);
+        match pattern.segments.as_slice() {
+            [] | [Segment::Literal(_)] => {
+                self.exact.insert(route.to_owned(), handler);
+            }
+            _ => self.patterns.push((pattern, handler)),
+        }
+    }
+
+    fn find(&self, route: &str) -> Option<(H, Params)> {
+        if let Some(handler) = self.exact.get(route) {
+            return Some((handler.clone(), Params::default()));
+        }
+        self.patterns.iter().find_map(|(pattern, handler)| {
+            pattern.matches(route).map(|params| (handler.clone(), params))
+        })
+    }
+}
+
+impl<H> fmt::Debug for Routes<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let patterns = self.patterns.iter().map(|(pattern, _)| &pattern.route);
+        f.debug_list().entries(self.exact.keys()).entries(patterns).finish()
+    }
+}
+
+/// Returns true if the given character separates the parts of a route.
+fn is_separator(c: char) -> bool {
+    c == '.' || c == '/'
+}
+
+impl Pattern {
+    fn parse(route: &str) -> Pattern {
+        let mut segments = Vec::new();
+        let mut rest = route;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end =
+                        rest.find('}').expect("unterminated path variable");
+                    let name = &rest[1..end];
+                    assert!(!name.is_empty(), "unnamed path variable");
+                    segments.push(Segment::Variable(name.to_owned()));
+                    rest = &rest[end + 1..];
+                }
+                Some(start) => {
+                    segments.push(Segment::Literal(rest[..start].to_owned()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    segments.push(Segment::Literal(rest.to_owned()));
+                    rest = "";
+                }
+            }
+        }
+        Pattern { route: route.to_owned(), segments }
+    }
+
+    fn matches(&self, route: &str) -> Option<Params> {
+        let mut params = Vec::new();
+        let mut rest = route;
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    rest = rest.strip_prefix(literal.as_str())?;
+                }
+                Segment::Variable(name) => {
+                    let end = rest.find(is_separator).unwrap_or(rest.len());
+                    if end == 0 {
+                        return None;
+                    }
+                    params.push((name.clone(), rest[..end].to_owned()));
+                    rest = &rest[end..];
+                }
+            }
+        }
+        if rest.is_empty() {
+            Some(Params { params })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::CompositeMetadata;
+    use crate::test_helpers::*;
+    use bytes::Bytes;
+    use tokio_stream::StreamExt;
+
+    fn request(route: &str, data: &'static str) -> Payload {
+        let mut metadata = CompositeMetadata::new();
+        metadata.push(
+            WellKnownMimeType::MESSAGE_X_RSOCKET_ROUTING_V0,
+            RoutingMetadata::from(route).to_bytes(),
+        );
+        Payload::builder()
+            .set_metadata(metadata.to_bytes())
+            .set_data(data)
+            .build()
+    }
+
+    fn data(payload: Payload) -> Bytes {
+        payload.data().unwrap().clone()
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .add_request_response("echo", |payload, _| {
+                Box::pin(async move { Ok(payload) })
+            })
+            .add_request_response("users.{id}", |_, params| {
+                let id = params.get("id").unwrap().to_owned();
+                Box::pin(
+                    async move { Ok(Payload::builder().set_data(id).build()) },
+                )
+            })
+            .add_request_response("users.me", |_, _| {
+                Box::pin(async {
+                    Ok(Payload::builder().set_data("me").build())
+                })
+            })
+            .add_request_stream("repeat", |payload, _| {
+                let payloads = vec![Ok(payload.clone()), Ok(payload)];
+                Box::pin(tokio_stream::iter(payloads))
+            })
+            .add_request_channel("echo", |payloads, _| payloads)
+            .add_fire_and_forget("drop", |_, _| Ok(()))
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<Router>();
+        assert_sync::<Router>();
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = Pattern::parse("users.{id}/posts.{post}");
+        let params = pattern.matches("users.42/posts.7").unwrap();
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("post"), Some("7"));
+        assert_eq!(params.len(), 2);
+        assert!(pattern.matches("users.42/posts.").is_none());
+        assert!(pattern.matches("users.42.43/posts.7").is_none());
+        assert!(pattern.matches("users.42/posts.7.8").is_none());
+
+        let pattern = Pattern::parse("files-{name}");
+        let params = pattern.matches("files-a").unwrap();
+        assert_eq!(params.iter().collect::<Vec<_>>(), vec![("name", "a")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unterminated_variable() {
+        Router::new().add_fire_and_forget("users.{id", |_, _| Ok(()));
+    }
+
+    #[tokio::test]
+    async fn request_response() {
+        let router = router();
+        let response = router.request_response(request("echo", "ping")).await;
+        assert_eq!(data(response.unwrap()), "ping");
+        let response = router.request_response(request("users.42", "")).await;
+        assert_eq!(data(response.unwrap()), "42");
+        // Exact routes take precedence over the patterns.
+        let response = router.request_response(request("users.me", "")).await;
+        assert_eq!(data(response.unwrap()), "me");
+
+        let err = router.request_response(request("unknown", "")).await;
+        assert!(err.unwrap_err().is_application_error());
+        let payload = Payload::builder().set_data("ping").build();
+        let err = router.request_response(payload).await;
+        assert!(err.unwrap_err().is_invalid());
+    }
+
+    #[tokio::test]
+    async fn request_stream_and_channel() {
+        let router = router();
+        let payloads = router.request_stream(request("repeat", "ping"));
+        assert_eq!(payloads.collect::<Vec<_>>().await.len(), 2);
+
+        let payloads = vec![
+            Ok(request("echo", "1")),
+            Ok(Payload::builder().set_data("2").build()),
+        ];
+        let mut payloads =
+            router.request_channel(Box::pin(tokio_stream::iter(payloads)));
+        assert_eq!(data(payloads.next().await.unwrap().unwrap()), "1");
+        assert_eq!(data(payloads.next().await.unwrap().unwrap()), "2");
+        assert!(payloads.next().await.is_none());
+
+        let payloads = Box::pin(tokio_stream::once(Ok(request("none", ""))));
+        let mut payloads = router.request_channel(payloads);
+        let err = payloads.next().await.unwrap().unwrap_err();
+        assert!(err.is_application_error());
+    }
+
+    #[test]
+    fn fire_and_forget() {
+        let router = router();
+        assert!(router.fire_and_forget(request("drop", "")).is_ok());
+        assert!(router.fire_and_forget(request("echo", "")).is_err());
+    }
+
+    #[tokio::test]
+    async fn routing_metadata() {
+        let router = router().set_metadata_mime_type(
+            WellKnownMimeType::MESSAGE_X_RSOCKET_ROUTING_V0,
+        );
+        let payload = Payload::builder()
+            .set_metadata(RoutingMetadata::from("echo").to_bytes())
+            .set_data("ping")
+            .build();
+        let response = router.request_response(payload).await;
+        assert_eq!(data(response.unwrap()), "ping");
+    }
+}
@@ -1,10 +1,10 @@
 use crate::payload::Payload;
-use crate::Result;
+use crate::{Code, Error, Result};
 
 use bytes::Bytes;
 use std::future::Future;
 use std::pin::Pin;
-use tokio_stream::Stream;
+use tokio_stream::{Stream, StreamExt};
 
 /// A stream that emits a value exactly once.
 pub type Mono<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
@@ -33,35 +33,105 @@ pub trait RSocket: Send + Sync {
     fn metadata_push(&self, metadata: Bytes) -> Mono<Result<()>>;
 }
 
+/// The default responder of a connection, which rejects every request.
 #[derive(Clone)]
 pub(crate) struct DummyRSocket;
 
+impl DummyRSocket {
+    fn rejected() -> Error {
+        Error::with_code(Code::Rejected, "no responder is registered")
+    }
+}
+
 impl RSocket for DummyRSocket {
-    /// Request-Response interaction model of RSocket.
     fn request_response(&self, _payload: Payload) -> Mono<Result<Payload>> {
-        unimplemented!("dummy rsocket");
+        Box::pin(async { Err(DummyRSocket::rejected()) })
     }
 
-    /// Request-Stream interaction model of RSocket.
     fn request_stream(&self, _payload: Payload) -> Flux<Result<Payload>> {
-        unimplemented!("dummy rsocket");
+        Box::pin(tokio_stream::once(Err(DummyRSocket::rejected())))
     }
 
-    /// Request-Channel interaction model of RSocket.
     fn request_channel(
         &self,
         _payloads: Flux<Result<Payload>>,
     ) -> Flux<Result<Payload>> {
-        unimplemented!("dummy rsocket");
+        Box::pin(tokio_stream::once(Err(DummyRSocket::rejected())))
     }
 
-    /// Fire-and-Forget interaction model of RSocket.
     fn fire_and_forget(&self, _payload: Payload) -> Result<()> {
-        unimplemented!("dummy rsocket");
+        Err(DummyRSocket::rejected())
     }
 
-    /// Metadata-Push interaction model of RSocket.
     fn metadata_push(&self, _metadata: Bytes) -> Mono<Result<()>> {
-        unimplemented!("dummy rsocket");
+        Box::pin(async { Err(DummyRSocket::rejected()) })
+    }
+}
+
+/// Waits for the first payload of a channel, and returns the payloads produced by `f`, which is
+/// given the first payload along with all the payloads (the first one included).
+///
+/// This is for responders that decide how to handle a channel from its first payload, which is
+/// received asynchronously. An error in place of the first payload is returned as it is, and
+/// an empty channel produces no payloads, without calling `f`.
+pub(crate) fn with_first_payload<F>(
+    mut payloads: Flux<Result<Payload>>,
+    f: F,
+) -> Flux<Result<Payload>>
+where
+    F: FnOnce(&Payload, Flux<Result<Payload>>) -> Flux<Result<Payload>>
+        + Send
+        + 'static,
+{
+    let dispatch = async move {
+        let first = match payloads.next().await {
+            Some(Ok(first)) => first,
+            Some(Err(err)) => {
+                return Box::pin(tokio_stream::once(Err(err))) as Flux<_>
+            }
+            None => return Box::pin(tokio_stream::empty()) as Flux<_>,
+        };
+        let head = first.clone();
+        f(&head, Box::pin(tokio_stream::once(Ok(first)).chain(payloads)))
+    };
+    Box::pin(futures_util::StreamExt::flatten(futures_util::stream::once(
+        dispatch,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(data: &'static str) -> Payload {
+        Payload::builder().set_data(data).build()
+    }
+
+    #[tokio::test]
+    async fn first_payload() {
+        let payloads = vec![Ok(payload("1")), Ok(payload("2"))];
+        let payloads = with_first_payload(
+            Box::pin(tokio_stream::iter(payloads)),
+            |first, payloads| {
+                assert_eq!(first.data().unwrap(), "1");
+                payloads
+            },
+        );
+        let payloads: Vec<_> = payloads.collect().await;
+        assert_eq!(payloads.len(), 2);
+
+        let err = Error::with_code(Code::Invalid, "invalid");
+        let payloads = with_first_payload(
+            Box::pin(tokio_stream::once(Err(err))),
+            |_, _| unreachable!(),
+        );
+        let payloads: Vec<_> = payloads.collect().await;
+        assert!(payloads[0].as_ref().unwrap_err().is_invalid());
+
+        let payloads = with_first_payload(
+            Box::pin(tokio_stream::empty()),
+            |_, _| unreachable!(),
+        );
+        assert_eq!(payloads.collect::<Vec<_>>().await.len(), 0);
     }
 }
@@ -0,0 +1,1251 @@
+use crate::auth::{self, Authenticated, Authenticator};
+use crate::connection::{
+    ConnectionAcceptor, DuplexConnection, Prefetch, RSocketMachine,
+    ResumableConnection, Role,
+};
+use crate::consts::{
+    DEFAULT_MAX_REASSEMBLED_SIZE, DEFAULT_RESUME_SESSION_DURATION, MIN_MTU,
+};
+use crate::error::{Code, Error, Kind, Result};
+use crate::frame::codec::{ResumeFrame, SetupFrame};
+use crate::frame::{Encode, Frame, MAX_U31};
+use crate::layer::{self, RSocketLayer};
+use crate::lease::LeasePolicy;
+use crate::resume::{InMemoryFramesStore, ResumableFramesStore, SavedSession};
+use crate::rsocket::DummyRSocket;
+use crate::runtime;
+use crate::zipkin::{ZipkinLayer, ZipkinRequester};
+use crate::{Flux, Mono, RSocket};
+
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::fmt;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, warn};
+
+/// The major version of the RSocket protocol supported by the server.
+const SUPPORTED_MAJOR_VERSION: u16 = 1;
+
+/// Accepts or rejects the SETUP of new connections.
+///
+/// The acceptor receives the SETUP frame sent by the client, along with a requester that sends
+/// requests to that client. It returns either the responder that handles the requests from the
+/// client, or an error that rejects the connection.
+///
+/// The error is sent to the client as an ERROR frame. Errors with the code
+/// [`Code::InvalidSetup`], [`Code::UnsupportedSetup`] or [`Code::RejectedSetup`] are sent as they
+/// are, and any other error is sent as `REJECTED_SETUP`.
+///
+/// `SocketAcceptor` is implemented for closures, so that simple acceptors can be written inline.
+///
+/// # Examples
+///
+/// ```
+/// use binate::{Code, Error, Mono, RSocket, Result, SetupFrame};
+///
+/// fn acceptor(
+///     setup: &SetupFrame,
+///     requester: Box<dyn RSocket>,
+/// ) -> Mono<Result<Box<dyn RSocket>>> {
+///     let authorized = matches!(setup.metadata(), Some(token) if token == "secret");
+///     Box::pin(async move {
+///         if authorized {
+///             Ok(requester)
+///         } else {
+///             Err(Error::with_code(Code::RejectedSetup, "unauthorized"))
+///         }
+///     })
+/// }
+/// ```
+pub trait SocketAcceptor: Send + Sync {
+    /// Accepts or rejects the connection set up by `setup`.
+    fn accept(
+        &self,
+        setup: &SetupFrame,
+        requester: Box<dyn RSocket>,
+    ) -> Mono<Result<Box<dyn RSocket>>>;
+}
+
+impl<F> SocketAcceptor for F
+where
+    F: Fn(&SetupFrame, Box<dyn RSocket>) -> Mono<Result<Box<dyn RSocket>>>
+        + Send
+        + Sync,
+{
+    fn accept(
+        &self,
+        setup: &SetupFrame,
+        requester: Box<dyn RSocket>,
+    ) -> Mono<Result<Box<dyn RSocket>>> {
+        (self)(setup, requester)
+    }
+}
+
+/// A running RSocket server.
+///
+/// The server stops accepting new connections when it is dropped or `stop()` is called, but the
+/// connections that have been accepted stay open.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::transport::TcpAcceptor;
+/// use binate::{Mono, RSocket, Result, Server, SetupFrame};
+///
+/// fn acceptor(
+///     _setup: &SetupFrame,
+///     requester: Box<dyn RSocket>,
+/// ) -> Mono<Result<Box<dyn RSocket>>> {
+///     // Echoes the requests back to the client.
+///     Box::pin(async move { Ok(requester) })
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> binate::Result<()> {
+/// let transport = TcpAcceptor::bind("127.0.0.1:7878".parse().unwrap()).await?;
+/// let server = Server::builder().set_acceptor(acceptor).serve(transport);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    transport: Box<dyn Listener>,
+}
+
+impl Server {
+    /// Returns a [`ServerBuilder`] to configure and start a server.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Stops accepting new connections.
+    pub fn stop(&self) {
+        self.transport.stop()
+    }
+
+    /// Returns the port the server is listening to.
+    pub fn listening_port(&self) -> usize {
+        self.transport.listening_port()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("listening_port", &self.listening_port())
+            .finish()
+    }
+}
+
+/// The object safe part of [`ConnectionAcceptor`].
+trait Listener: Send + Sync {
+    fn stop(&self);
+
+    fn listening_port(&self) -> usize;
+}
+
+impl<A> Listener for A
+where
+    A: ConnectionAcceptor + Send + Sync,
+{
+    fn stop(&self) {
+        ConnectionAcceptor::stop(self)
+    }
+
+    fn listening_port(&self) -> usize {
+        ConnectionAcceptor::listening_port(self)
+    }
+}
+
+/// A builder for configuring and starting a [`Server`].
+#[derive(Clone)]
+pub struct ServerBuilder {
+    acceptor: Arc<dyn SocketAcceptor>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    layers: Vec<Arc<dyn RSocketLayer>>,
+    lease_policy: Option<Arc<dyn LeasePolicy>>,
+    prefetch: Prefetch,
+    mtu: Option<usize>,
+    max_reassembled_size: usize,
+    resume_session_duration: Option<Duration>,
+    resume_store: Arc<dyn ResumableFramesStore>,
+    sessions: Arc<DashMap<Bytes, ResumableConnection>>,
+    // The sessions saved in the store by a previous process, which are loaded once.
+    saved_sessions: Arc<DashMap<Bytes, SavedSession>>,
+    saved_sessions_loaded: Arc<Once>,
+    zipkin: bool,
+}
+
+impl ServerBuilder {
+    /// Creates a new `ServerBuilder` with the default configuration.
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            acceptor: Arc::new(accept_all),
+            authenticator: None,
+            layers: Vec::new(),
+            lease_policy: None,
+            prefetch: Prefetch::default(),
+            mtu: None,
+            max_reassembled_size: DEFAULT_MAX_REASSEMBLED_SIZE,
+            resume_session_duration: None,
+            resume_store: Arc::new(InMemoryFramesStore::default()),
+            sessions: Arc::new(DashMap::new()),
+            saved_sessions: Arc::new(DashMap::new()),
+            saved_sessions_loaded: Arc::new(Once::new()),
+            zipkin: false,
+        }
+    }
+
+    /// Sets the acceptor that accepts or rejects the SETUP of new connections.
+    ///
+    /// Every connection is accepted if no acceptor is set, and the requests from clients are
+    /// rejected.
+    pub fn set_acceptor<A>(mut self, acceptor: A) -> Self
+    where
+        A: SocketAcceptor + 'static,
+    {
+        self.acceptor = Arc::new(acceptor);
+        self
+    }
+
+    /// Sets the authenticator that checks the authentication metadata of clients, on SETUP and
+    /// on every request.
+    ///
+    /// Clients that fail to authenticate on SETUP are rejected with `REJECTED_SETUP`, before the
+    /// acceptor is called. Clients that do not authenticate on SETUP must authenticate every
+    /// request, or the request is rejected with `REJECTED`. See the [`auth`](crate::auth)
+    /// module for details.
+    pub fn set_authenticator<A>(mut self, authenticator: A) -> Self
+    where
+        A: Authenticator + 'static,
+    {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Adds a layer that wraps the responders returned by the acceptor.
+    ///
+    /// The layer added first is the outermost one, which sees the requests first.
+    pub fn add_layer<L>(mut self, layer: L) -> Self
+    where
+        L: RSocketLayer + 'static,
+    {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Enables leases, and sets the policy that issues leases to clients.
+    ///
+    /// Leases are only used on the connections of clients that request them in their SETUP
+    /// frames, where the server only sends requests while it holds a valid lease from the
+    /// client. Such clients are rejected with `UNSUPPORTED_SETUP` unless leases are enabled.
+    pub fn set_lease_policy<P>(mut self, policy: P) -> Self
+    where
+        P: LeasePolicy + 'static,
+    {
+        self.lease_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets the number of payloads to ask for when a stream starts (defaults to 256), and the
+    /// number of outstanding payloads under which more payloads are asked for as the payloads
+    /// are consumed (defaults to 64).
+    ///
+    /// This applies to the streams and channels that the server requests, as well as the
+    /// channels requested by clients, which are never sent more payloads than they ask for. A
+    /// `prefetch` of `2^31 - 1` asks for an unbounded number of payloads.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `prefetch` is 0 or greater than `2^31 - 1`, or if
+    /// `low_water_mark` is not less than `prefetch`.
+    pub fn set_prefetch(mut self, prefetch: u32, low_water_mark: u32) -> Self {
+        assert!(prefetch > 0 && prefetch <= MAX_U31);
+        assert!(low_water_mark < prefetch);
+        self.prefetch = Prefetch { prefetch, low_water_mark };
+        self
+    }
+
+    /// Sets the maximum length (in bytes) of the frames sent to clients, over which payloads
+    /// are split into fragments (by default payloads are never fragmented).
+    ///
+    /// The length counts the frame headers, but not the frame length prefix that some
+    /// transports put before each frame.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the given `mtu` is less than 64.
+    pub fn set_mtu(mut self, mtu: usize) -> Self {
+        assert!(mtu >= MIN_MTU);
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Sets the maximum size (in bytes) of the payloads reassembled from the fragments received
+    /// from clients (defaults to 64 MiB).
+    ///
+    /// The connection is closed with a `CONNECTION_ERROR` if a fragmented payload exceeds this
+    /// size.
+    pub fn set_max_reassembled_size(mut self, max_size: usize) -> Self {
+        self.max_reassembled_size = max_size;
+        self
+    }
+
+    /// Enables session resumption, where the session of a client is kept for 120 seconds after
+    /// its connection is lost.
+    ///
+    /// Clients that request resumption in their SETUP frames are rejected with
+    /// `UNSUPPORTED_SETUP` unless resumption is enabled.
+    pub fn set_resume(self) -> Self {
+        self.set_resume_session_duration(DEFAULT_RESUME_SESSION_DURATION)
+    }
+
+    /// Enables session resumption, and sets the time that the session of a client is kept after
+    /// its connection is lost.
+    pub fn set_resume_session_duration(mut self, duration: Duration) -> Self {
+        self.resume_session_duration = Some(duration);
+        self
+    }
+
+    /// Sets the store that keeps the frames sent on resumable sessions (defaults to an
+    /// [`InMemoryFramesStore`] that keeps 1 MiB of frames for each session).
+    ///
+    /// The sessions that a store such as a [`FileFramesStore`] saved before the process
+    /// restarted are set up again with their SETUP frames when they are resumed, which calls
+    /// the acceptor again. The ones that are not resumed within the session duration are
+    /// removed from the store.
+    ///
+    /// [`FileFramesStore`]: crate::resume::FileFramesStore
+    pub fn set_resume_store<S>(mut self, store: S) -> Self
+    where
+        S: ResumableFramesStore + 'static,
+    {
+        self.resume_store = Arc::new(store);
+        self
+    }
+
+    /// Propagates Zipkin traces across the connections of the server.
+    ///
+    /// The responders returned by the acceptor are wrapped in a [`ZipkinLayer`], outside of the
+    /// layers added by [`add_layer`](Self::add_layer), and the requesters given to the acceptor
+    /// are wrapped in a [`ZipkinRequester`]. See the [`zipkin`](crate::zipkin) module for
+    /// details.
+    pub fn set_zipkin_tracing(mut self) -> Self {
+        self.zipkin = true;
+        self
+    }
+
+    /// Starts accepting connections from the given transport.
+    pub fn serve<A>(self, transport: A) -> Server
+    where
+        A: ConnectionAcceptor + Send + Sync + 'static,
+        A::Connection: 'static,
+    {
+        let server = self;
+        transport.start(move |conn| {
+            let server = server.clone();
+            runtime::spawn(async move {
+                if let Err(e) = server.serve_connection(conn).await {
+                    debug!("connection is not set up: {}", e);
+                }
+            });
+        });
+        Server { transport: Box::new(transport) }
+    }
+
+    /// Sets up a single connection that has been established, e.g. one end of a
+    /// [`LocalConnection`](crate::transport::LocalConnection).
+    ///
+    /// This waits for the SETUP (or RESUME) frame of the client, and returns the error sent to
+    /// the client if the connection is rejected.
+    pub async fn serve_connection<T>(&self, conn: T) -> Result<()>
+    where
+        T: DuplexConnection + 'static,
+    {
+        self.load_saved_sessions();
+        let mut frames = conn.receive();
+        match frames.next().await {
+            Some(Frame::Setup(setup)) => self.setup(conn, frames, setup).await,
+            Some(Frame::Resume(resume)) => {
+                self.resume(Arc::new(conn), frames, resume).await
+            }
+            Some(frame) => {
+                let err = Error::unexpected_frame(
+                    Code::InvalidSetup,
+                    "SETUP",
+                    &frame,
+                );
+                reject(&conn, err).await
+            }
+            None => Err(Error::new(
+                Kind::ConnectionError,
+                Some("connection was closed before SETUP"),
+            )),
+        }
+    }
+
+    async fn setup<T>(
+        &self,
+        conn: T,
+        frames: Flux<Frame>,
+        setup: SetupFrame,
+    ) -> Result<()>
+    where
+        T: DuplexConnection + 'static,
+    {
+        if setup.keepalive_interval().as_millis() == 0
+            || setup.keepalive_timeout().as_millis() == 0
+        {
+            let err = Error::with_code(
+                Code::InvalidSetup,
+                "keepalive interval and timeout must be greater than 0",
+            );
+            return reject(&conn, err).await;
+        }
+        if setup.version().major() != SUPPORTED_MAJOR_VERSION {
+            let err = Error::with_code(
+                Code::UnsupportedSetup,
+                format!("unsupported version {}", setup.version()),
+            );
+            return reject(&conn, err).await;
+        }
+        if setup.is_lease() && self.lease_policy.is_none() {
+            let err = Error::with_code(
+                Code::UnsupportedSetup,
+                "lease is not supported",
+            );
+            return reject(&conn, err).await;
+        }
+        let authenticated = match &self.authenticator {
+            Some(authenticator) => {
+                match auth::authenticate_setup(&**authenticator, &setup) {
+                    Ok(authenticated) => authenticated,
+                    Err(err) => return reject(&conn, err).await,
+                }
+            }
+            None => false,
+        };
+
+        let (rsm, frames) = match setup.resume_token() {
+            Some(token) => {
+                let session_duration = match self.resume_session_duration {
+                    Some(duration) => duration,
+                    None => {
+                        let err = Error::with_code(
+                            Code::UnsupportedSetup,
+                            "resumption is not supported",
+                        );
+                        return reject(&conn, err).await;
+                    }
+                };
+                let session = match self.sessions.entry(token.clone()) {
+                    Entry::Occupied(_) => {
+                        let err = Error::with_code(
+                            Code::RejectedSetup,
+                            "resume token is already in use",
+                        );
+                        return reject(&conn, err).await;
+                    }
+                    Entry::Vacant(entry) => {
+                        // A saved session with the same token is replaced.
+                        self.saved_sessions.remove(token);
+                        let session = ResumableConnection::new(
+                            token.clone(),
+                            self.resume_store.clone(),
+                        );
+                        entry.insert(session).clone()
+                    }
+                };
+                let encoded = Frame::Setup(setup.clone()).to_bytes();
+                if let Err(err) = self.resume_store.save_setup(token, encoded)
+                {
+                    warn!("failed to save SETUP frame: {}", err);
+                }
+                session.attach(Arc::new(conn), frames);
+                self.expire_session(session.clone(), session_duration);
+
+                let frames = session.receive();
+                let rsm = RSocketMachine::unstarted_resumable(
+                    Role::Server,
+                    session,
+                    setup.keepalive_interval(),
+                    setup.keepalive_timeout(),
+                );
+                (rsm, frames)
+            }
+            None => {
+                let rsm = RSocketMachine::unstarted(
+                    Role::Server,
+                    conn,
+                    setup.keepalive_interval(),
+                    setup.keepalive_timeout(),
+                );
+                (rsm, frames)
+            }
+        };
+        self.start(rsm, frames, &setup, authenticated).await
+    }
+
+    /// Starts the machine of a session that is set up, and sets the responder returned by the
+    /// acceptor.
+    async fn start(
+        &self,
+        rsm: RSocketMachine,
+        frames: Flux<Frame>,
+        setup: &SetupFrame,
+        authenticated: bool,
+    ) -> Result<()> {
+        // The requests from the client are held until the responder is set, so that requests
+        // sent right after the SETUP frame are not rejected. Other frames are handled right
+        // away, as the acceptor may send requests to the client and wait for their responses.
+        let mut responder = rsm.lock_request_handler().await;
+        rsm.set_prefetch(self.prefetch);
+        if let Some(mtu) = self.mtu {
+            rsm.set_mtu(mtu);
+        }
+        rsm.set_max_reassembled_size(self.max_reassembled_size);
+        if setup.is_lease() {
+            rsm.enable_lease(self.lease_policy.clone());
+        }
+        rsm.start(frames);
+        let mut requester: Box<dyn RSocket> = Box::new(rsm.clone());
+        if self.zipkin {
+            requester = Box::new(ZipkinRequester::new(requester));
+        }
+        match self.acceptor.accept(setup, requester).await {
+            Ok(accepted) => {
+                let mut accepted = layer::layered(&self.layers, accepted);
+                if self.zipkin {
+                    accepted = ZipkinLayer::new().layer(accepted);
+                }
+                if let Some(authenticator) = &self.authenticator {
+                    accepted = Box::new(Authenticated::new(
+                        accepted,
+                        authenticator.clone(),
+                        setup,
+                        authenticated,
+                    ));
+                }
+                *responder = accepted;
+                Ok(())
+            }
+            Err(err) => {
+                drop(responder);
+                let err = match err.code() {
+                    Some(Code::InvalidSetup)
+                    | Some(Code::UnsupportedSetup)
+                    | Some(Code::RejectedSetup) => err,
+                    _ => {
+                        Error::with_code(Code::RejectedSetup, err.to_string())
+                    }
+                };
+                let result = rsm.send(Frame::Error(err.to_frame(0))).await;
+                rsm.close();
+                result.and(Err(err))
+            }
+        }
+    }
+
+    async fn resume(
+        &self,
+        conn: Arc<dyn DuplexConnection>,
+        frames: Flux<Frame>,
+        resume: ResumeFrame,
+    ) -> Result<()> {
+        let session = self
+            .sessions
+            .get(resume.resume_token())
+            .map(|session| session.clone());
+        if session.is_none() {
+            if let Some((_, saved)) =
+                self.saved_sessions.remove(resume.resume_token())
+            {
+                return self.restore(conn, frames, resume, saved).await;
+            }
+        }
+        let result = match session {
+            Some(session) => {
+                let result =
+                    session.accept_resume(conn.clone(), frames, &resume);
+                if let Err(err) = &result {
+                    // Positions that cannot be replayed will never be.
+                    session.expire(err.to_string());
+                }
+                result
+            }
+            None => Err(Error::with_code(
+                Code::RejectedResume,
+                "unknown resume token",
+            )),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => reject(&*conn, err).await,
+        }
+    }
+
+    /// Sets up a session saved by a previous process again, and resumes it.
+    async fn restore(
+        &self,
+        conn: Arc<dyn DuplexConnection>,
+        frames: Flux<Frame>,
+        resume: ResumeFrame,
+        saved: SavedSession,
+    ) -> Result<()> {
+        let session_duration = self
+            .resume_session_duration
+            .unwrap_or(DEFAULT_RESUME_SESSION_DURATION);
+        let setup = match Frame::decode(&mut saved.setup().clone()) {
+            Ok(Frame::Setup(setup)) => setup,
+            _ => {
+                let err = Error::with_code(
+                    Code::RejectedResume,
+                    "saved SETUP frame is invalid",
+                );
+                return self.reject_saved(&*conn, saved.token(), err).await;
+            }
+        };
+        if setup.is_lease() && self.lease_policy.is_none() {
+            let err = Error::with_code(
+                Code::RejectedResume,
+                "lease is not supported",
+            );
+            return self.reject_saved(&*conn, saved.token(), err).await;
+        }
+        let authenticated = match &self.authenticator {
+            Some(authenticator) => {
+                match auth::authenticate_setup(&**authenticator, &setup) {
+                    Ok(authenticated) => authenticated,
+                    Err(err) => {
+                        let err = Error::with_code(
+                            Code::RejectedResume,
+                            err.to_string(),
+                        );
+                        return self
+                            .reject_saved(&*conn, saved.token(), err)
+                            .await;
+                    }
+                }
+            }
+            None => false,
+        };
+
+        let session = match self.sessions.entry(saved.token().clone()) {
+            Entry::Occupied(_) => {
+                let err = Error::with_code(
+                    Code::RejectedResume,
+                    "resume token is already in use",
+                );
+                return reject(&*conn, err).await;
+            }
+            Entry::Vacant(entry) => {
+                let session = ResumableConnection::restore(
+                    saved.token().clone(),
+                    self.resume_store.clone(),
+                    saved.sent_position(),
+                    saved.received_position(),
+                );
+                entry.insert(session).clone()
+            }
+        };
+        self.expire_session(session.clone(), session_duration);
+        if let Err(err) = session.accept_resume(conn.clone(), frames, &resume)
+        {
+            session.expire(err.to_string());
+            return reject(&*conn, err).await;
+        }
+
+        let frames = session.receive();
+        let rsm = RSocketMachine::unstarted_resumable(
+            Role::Server,
+            session,
+            setup.keepalive_interval(),
+            setup.keepalive_timeout(),
+        );
+        self.start(rsm, frames, &setup, authenticated).await
+    }
+
+    /// Rejects the resumption of a session saved by a previous process, which is removed from
+    /// the store as it can never be resumed.
+    async fn reject_saved(
+        &self,
+        conn: &dyn DuplexConnection,
+        token: &Bytes,
+        err: Error,
+    ) -> Result<()> {
+        if let Err(err) = self.resume_store.remove(token) {
+            warn!("failed to remove saved session: {}", err);
+        }
+        reject(conn, err).await
+    }
+
+    /// Loads the sessions saved in the store by a previous process, if resumption is enabled.
+    /// The ones that are not resumed within the session duration are removed from the store.
+    fn load_saved_sessions(&self) {
+        let duration = match self.resume_session_duration {
+            Some(duration) => duration,
+            None => return,
+        };
+        self.saved_sessions_loaded.call_once(|| {
+            for session in self.resume_store.saved_sessions() {
+                self.saved_sessions.insert(session.token().clone(), session);
+            }
+            if self.saved_sessions.is_empty() {
+                return;
+            }
+            let saved_sessions = self.saved_sessions.clone();
+            let store = self.resume_store.clone();
+            runtime::spawn(async move {
+                tokio::time::sleep(duration).await;
+                let tokens: Vec<Bytes> =
+                    saved_sessions.iter().map(|s| s.key().clone()).collect();
+                for token in tokens {
+                    if saved_sessions.remove(&token).is_none() {
+                        continue;
+                    }
+                    if let Err(err) = store.remove(&token) {
+                        warn!("failed to remove saved session: {}", err);
+                    }
+                }
+            });
+        });
+    }
+
+    /// Closes the session if it is not resumed in time after its connection is lost, and removes
+    /// it once it is closed.
+    fn expire_session(
+        &self,
+        session: ResumableConnection,
+        duration: Duration,
+    ) {
+        let sessions = self.sessions.clone();
+        runtime::spawn(async move {
+            while session.disconnected().await {
+                match timeout(duration, session.reconnected()).await {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(_) => {
+                        session.expire("resume session expired");
+                        break;
+                    }
+                }
+            }
+            sessions.remove_if(session.token(), |_, s| s.ptr_eq(&session));
+        });
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder::new()
+    }
+}
+
+impl fmt::Debug for ServerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerBuilder")
+            .field("authenticator", &self.authenticator.is_some())
+            .field("layers", &self.layers.len())
+            .field("lease_policy", &self.lease_policy.is_some())
+            .field("prefetch", &self.prefetch)
+            .field("mtu", &self.mtu)
+            .field("max_reassembled_size", &self.max_reassembled_size)
+            .field("resume_session_duration", &self.resume_session_duration)
+            .field("zipkin", &self.zipkin)
+            .finish()
+    }
+}
+
+fn accept_all(
+    _setup: &SetupFrame,
+    _requester: Box<dyn RSocket>,
+) -> Mono<Result<Box<dyn RSocket>>> {
+    Box::pin(async { Ok(Box::new(DummyRSocket) as Box<dyn RSocket>) })
+}
+
+/// Sends the given error to the client and closes the connection.
+async fn reject<T>(conn: &T, err: Error) -> Result<()>
+where
+    T: DuplexConnection + ?Sized,
+{
+    if let Err(e) = conn.send(Frame::Error(err.to_frame(0))).await {
+        error!("failed to reject connection: {}", e);
+    }
+    conn.close();
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::Authentication;
+    use crate::frame::codec::*;
+    use crate::frame::Flags;
+    use crate::layer::ConcurrencyLimitLayer;
+    use crate::lease::FixedLeasePolicy;
+    use crate::payload::Payload;
+    use crate::resume::FileFramesStore;
+    use crate::test_helpers::*;
+    use crate::transport::{LocalConnection, TcpAcceptor, TcpConnection};
+    use crate::Client;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Accepts clients whose SETUP data is "secret", and sends a request back to the client
+    /// before accepting it.
+    fn authenticate(
+        setup: &SetupFrame,
+        requester: Box<dyn RSocket>,
+    ) -> Mono<Result<Box<dyn RSocket>>> {
+        let authorized =
+            matches!(setup.data(), Some(data) if data == "secret");
+        Box::pin(async move {
+            if !authorized {
+                return Err(Error::with_code(
+                    Code::RejectedSetup,
+                    "unauthorized",
+                ));
+            }
+            let hello = Payload::builder().set_data("hello").build();
+            requester.request_response(hello).await?;
+            Ok(Box::new(EchoRSocket) as Box<dyn RSocket>)
+        })
+    }
+
+    fn setup(data: &'static str) -> Frame {
+        Frame::Setup(SetupFrame::builder().set_data(Bytes::from(data)).build())
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<ServerBuilder>();
+        assert_sync::<ServerBuilder>();
+        assert_send::<Server>();
+        assert_sync::<Server>();
+    }
+
+    #[tokio::test]
+    async fn accepted() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let builder = Server::builder().set_acceptor(authenticate);
+        let server = tokio::spawn(async move {
+            builder.serve_connection(server_conn).await
+        });
+
+        let client = Client::builder()
+            .set_setup_payload(Payload::builder().set_data("secret").build())
+            .set_responder(Box::new(EchoRSocket))
+            .connect(client_conn)
+            .await
+            .unwrap();
+        // Sent before the acceptor completes, but served by the responder it returns.
+        let ping = Payload::builder().set_data("ping").build();
+        assert_eq!(client.request_response(ping.clone()).await.unwrap(), ping);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn layers() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let builder = Server::builder()
+            .set_acceptor(authenticate)
+            .add_layer(ConcurrencyLimitLayer::new(0));
+        tokio::spawn(
+            async move { builder.serve_connection(server_conn).await },
+        );
+
+        let client = Client::builder()
+            .set_setup_payload(Payload::builder().set_data("secret").build())
+            .set_responder(Box::new(EchoRSocket))
+            .connect(client_conn)
+            .await
+            .unwrap();
+        let ping = Payload::builder().set_data("ping").build();
+        let err = client.request_response(ping).await.unwrap_err();
+        assert!(err.is_rejected());
+    }
+
+    fn bearer(token: &str) -> Authentication {
+        Authentication::bearer(token)
+    }
+
+    fn check_token(auth: &Authentication) -> Result<()> {
+        if *auth == bearer("token") {
+            Ok(())
+        } else {
+            Err(Error::with_code(Code::ApplicationError, "invalid token"))
+        }
+    }
+
+    async fn connect_authenticated(
+        setup_auth: Option<&str>,
+    ) -> Result<Client> {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let builder = Server::builder()
+            .set_acceptor(|_: &SetupFrame, _: Box<dyn RSocket>| {
+                Box::pin(async {
+                    Ok(Box::new(EchoRSocket) as Box<dyn RSocket>)
+                }) as Mono<Result<Box<dyn RSocket>>>
+            })
+            .set_authenticator(check_token);
+        tokio::spawn(
+            async move { builder.serve_connection(server_conn).await },
+        );
+        let mut setup = Payload::builder();
+        if let Some(token) = setup_auth {
+            setup = setup.set_metadata(bearer(token).to_bytes());
+        }
+        Client::builder()
+            .set_metadata_mimetype("message/x.rsocket.authentication.v0")
+            .set_setup_payload(setup.build())
+            .connect(client_conn)
+            .await
+    }
+
+    #[tokio::test]
+    async fn authenticated_setup() {
+        let client = connect_authenticated(Some("token")).await.unwrap();
+        let ping = Payload::builder().set_data("ping").build();
+        assert!(client.request_response(ping).await.is_ok());
+
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let setup = SetupFrame::builder()
+            .set_metadata_mimetype("message/x.rsocket.authentication.v0")
+            .set_metadata(bearer("wrong").to_bytes())
+            .build();
+        client_conn.send_and_forget(Frame::Setup(setup)).unwrap();
+        let err = Server::builder()
+            .set_authenticator(check_token)
+            .serve_connection(server_conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedSetup));
+    }
+
+    #[tokio::test]
+    async fn authenticated_requests() {
+        let client = connect_authenticated(None).await.unwrap();
+        let ping = Payload::builder().set_data("ping").build();
+        let err = client.request_response(ping).await.unwrap_err();
+        assert!(err.is_rejected());
+
+        let ping = Payload::builder()
+            .set_metadata(bearer("wrong").to_bytes())
+            .set_data("ping")
+            .build();
+        let err = client.request_response(ping).await.unwrap_err();
+        assert!(err.is_rejected());
+
+        let ping = Payload::builder()
+            .set_metadata(bearer("token").to_bytes())
+            .set_data("ping")
+            .build();
+        assert!(client.request_response(ping).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejected() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        client_conn.send_and_forget(setup("wrong")).unwrap();
+
+        let err = Server::builder()
+            .set_acceptor(authenticate)
+            .serve_connection(server_conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedSetup));
+        match frames.next().await.unwrap() {
+            Frame::Error(frame) => {
+                assert_eq!(frame.stream_id(), 0);
+                assert_eq!(frame.error_code(), ErrorFrame::REJECTED_SETUP);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_with_other_error() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        client_conn.send_and_forget(setup("data")).unwrap();
+
+        let acceptor = |_: &SetupFrame, _: Box<dyn RSocket>| {
+            Box::pin(async {
+                Err(Error::with_code(Code::ApplicationError, "failed"))
+            }) as Mono<Result<Box<dyn RSocket>>>
+        };
+        let err = Server::builder()
+            .set_acceptor(acceptor)
+            .serve_connection(server_conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedSetup));
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::REJECTED_SETUP
+        ));
+    }
+
+    #[tokio::test]
+    async fn unsupported_version() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let setup = SetupFrame::builder().set_version(2, 0).build();
+        client_conn.send_and_forget(Frame::Setup(setup)).unwrap();
+
+        let err =
+            Server::builder().serve_connection(server_conn).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::UnsupportedSetup));
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::UNSUPPORTED_SETUP
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_setup() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let payload = Payload::builder().set_data("secret").build();
+        let request = RequestResponseFrame::new(1, false, payload);
+        client_conn.send_and_forget(Frame::RequestResponse(request)).unwrap();
+
+        let err =
+            Server::builder().serve_connection(server_conn).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::InvalidSetup));
+        // Only the frame type is sent back, not the payload.
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::INVALID_SETUP
+                    && frame.data_utf8()
+                        == Some("expected SETUP but got REQUEST_RESPONSE")
+        ));
+    }
+
+    #[tokio::test]
+    async fn serve() {
+        let transport =
+            TcpAcceptor::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = transport.local_addr();
+        let server =
+            Server::builder().set_acceptor(authenticate).serve(transport);
+        assert_eq!(server.listening_port(), addr.port() as usize);
+
+        let client = Client::builder()
+            .set_setup_payload(Payload::builder().set_data("secret").build())
+            .set_responder(Box::new(EchoRSocket))
+            .connect(TcpConnection::new(addr))
+            .await
+            .unwrap();
+        let ping = Payload::builder().set_data("ping").build();
+        assert_eq!(client.request_response(ping.clone()).await.unwrap(), ping);
+
+        // Requests of rejected clients fail.
+        let client =
+            Client::builder().connect(TcpConnection::new(addr)).await.unwrap();
+        assert!(client.request_response(ping).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lease_unsupported() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let setup = SetupFrame::builder().set_lease_flag().build();
+        client_conn.send_and_forget(Frame::Setup(setup)).unwrap();
+
+        let err =
+            Server::builder().serve_connection(server_conn).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::UnsupportedSetup));
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::UNSUPPORTED_SETUP
+        ));
+    }
+
+    #[tokio::test]
+    async fn lease() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let policy = FixedLeasePolicy::new(Duration::from_secs(60), 1);
+        let builder = Server::builder()
+            .set_acceptor(|_: &SetupFrame, _: Box<dyn RSocket>| {
+                Box::pin(async {
+                    Ok(Box::new(EchoRSocket) as Box<dyn RSocket>)
+                }) as Mono<Result<Box<dyn RSocket>>>
+            })
+            .set_lease_policy(policy);
+        tokio::spawn(
+            async move { builder.serve_connection(server_conn).await },
+        );
+
+        let client =
+            Client::builder().set_lease().connect(client_conn).await.unwrap();
+        let ping = Payload::builder().set_data("ping").build();
+        // Requests are refused until the lease from the server is received.
+        let response = loop {
+            match client.request_response(ping.clone()).await {
+                Err(err) if err.is_rejected() => {
+                    tokio::time::sleep(Duration::from_millis(1)).await
+                }
+                response => break response,
+            }
+        };
+        assert_eq!(response.unwrap(), ping);
+        let err = client.request_response(ping).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::Rejected));
+    }
+
+    #[tokio::test]
+    async fn resume_unsupported() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let setup = SetupFrame::builder()
+            .set_resume_token(Bytes::from("token"))
+            .build();
+        client_conn.send_and_forget(Frame::Setup(setup)).unwrap();
+
+        let err =
+            Server::builder().serve_connection(server_conn).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::UnsupportedSetup));
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::UNSUPPORTED_SETUP
+        ));
+    }
+
+    #[tokio::test]
+    async fn resume_unknown_session() {
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let resume =
+            ResumeFrame::new(Default::default(), Bytes::from("token"), 0, 0);
+        client_conn.send_and_forget(Frame::Resume(resume)).unwrap();
+
+        let err = Server::builder()
+            .set_resume()
+            .serve_connection(server_conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+        assert!(matches!(
+            frames.next().await,
+            Some(Frame::Error(frame))
+                if frame.error_code() == ErrorFrame::REJECTED_RESUME
+        ));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "binate-server-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Saves a session as a server process would before it exits, with a response sent on
+    /// stream 1 and 10 bytes received.
+    fn save_session(dir: &Path, token: &Bytes) -> Frame {
+        let setup = SetupFrame::builder()
+            .set_resume_token(token.clone())
+            .set_data(Bytes::from("secret"))
+            .build();
+        let response = Frame::Payload(PayloadFrame::new(
+            1,
+            Flags::NEXT | Flags::COMPLETE,
+            Payload::builder().set_data("saved").build(),
+        ));
+        let store = FileFramesStore::open(dir, 1024).unwrap();
+        store.save_setup(token, Frame::Setup(setup).to_bytes()).unwrap();
+        store.save(token, 0, response.to_bytes()).unwrap();
+        store.save_received_position(token, 10).unwrap();
+        // Waits for the frames to be written.
+        store.load(token, 0).unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn resume_saved_session() {
+        let dir = temp_dir("resume");
+        let token = Bytes::from("token");
+        let response = save_session(&dir, &token);
+
+        // The acceptor is called again with the saved SETUP frame.
+        let builder = Server::builder()
+            .set_resume()
+            .set_resume_store(FileFramesStore::open(&dir, 1024).unwrap())
+            .set_acceptor(|setup: &SetupFrame, _: Box<dyn RSocket>| {
+                let authorized =
+                    matches!(setup.data(), Some(data) if data == "secret");
+                Box::pin(async move {
+                    assert!(authorized);
+                    Ok(Box::new(EchoRSocket) as Box<dyn RSocket>)
+                }) as Mono<Result<Box<dyn RSocket>>>
+            });
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let mut frames = client_conn.receive();
+        let resume = ResumeFrame::new(Default::default(), token, 0, 10);
+        client_conn.send_and_forget(Frame::Resume(resume)).unwrap();
+        builder.serve_connection(server_conn).await.unwrap();
+
+        assert_eq!(
+            frames.next().await.unwrap(),
+            Frame::ResumeOk(ResumeOkFrame::new(10))
+        );
+        assert_eq!(frames.next().await.unwrap(), response);
+
+        let ping = Payload::builder().set_data("ping").build();
+        let request = RequestResponseFrame::new(3, false, ping.clone());
+        client_conn.send_and_forget(Frame::RequestResponse(request)).unwrap();
+        assert_eq!(
+            frames.next().await.unwrap(),
+            Frame::Payload(PayloadFrame::new(
+                3,
+                Flags::NEXT | Flags::COMPLETE,
+                ping
+            ))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn saved_session_expires() {
+        tokio::time::pause();
+        let dir = temp_dir("expire");
+        let token = Bytes::from("token");
+        save_session(&dir, &token);
+
+        let builder = Server::builder()
+            .set_resume_session_duration(Duration::from_secs(10))
+            .set_resume_store(FileFramesStore::open(&dir, 1024).unwrap());
+        let (client_conn, server_conn) = LocalConnection::pair();
+        let resume =
+            ResumeFrame::new(Default::default(), Bytes::from("other"), 0, 0);
+        client_conn.send_and_forget(Frame::Resume(resume)).unwrap();
+        let err = builder.serve_connection(server_conn).await.unwrap_err();
+        assert_eq!(err.code(), Some(Code::RejectedResume));
+        assert!(builder.saved_sessions.contains_key(&token));
+
+        // The session that is not resumed in time is removed from the store.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        tokio::task::yield_now().await;
+        assert!(builder.saved_sessions.is_empty());
+        builder.resume_store.load(&token, 0).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
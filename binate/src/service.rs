@@ -0,0 +1,410 @@
+//! Adapters between [`RSocket`] and [`tower::Service`].
+//!
+//! The interaction models of an `RSocket` (such as a [`Client`]) can be turned into services with
+//! [`RequestResponseService`], [`RequestStreamService`], [`RequestChannelService`],
+//! [`FireAndForgetService`] and [`MetadataPushService`], so that tower middleware (timeouts,
+//! retries, rate and concurrency limits, load shedding...) can be layered around the requests
+//! sent.
+//!
+//! Conversely, [`ServiceRSocket`] turns a request-response service into a responder.
+//!
+//! # Examples
+//!
+//! ```
+//! use binate::service::RequestResponseService;
+//! use binate::{Client, Payload};
+//! use std::time::Duration;
+//! use tower::{Service, ServiceBuilder, ServiceExt};
+//!
+//! async fn ping(client: Client) -> Result<Payload, tower::BoxError> {
+//!     let mut service = ServiceBuilder::new()
+//!         .timeout(Duration::from_secs(1))
+//!         .service(RequestResponseService::new(client));
+//!     let ping = Payload::builder().set_data("ping").build();
+//!     service.ready().await?.call(ping).await
+//! }
+//! ```
+//!
+//! [`tower::Service`]: tower_service::Service
+//! [`Client`]: crate::Client
+use crate::error::{Code, Error, Result};
+use crate::payload::{Metadata, Payload};
+use crate::{Flux, Mono, RSocket};
+
+use futures_util::future::poll_fn;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use tower::timeout::error::Elapsed;
+use tower_service::Service;
+
+/// The errors of the services that [`ServiceRSocket`] takes.
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+macro_rules! rsocket_service {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone)]
+        pub struct $name<R> {
+            rsocket: R,
+        }
+
+        impl<R: RSocket> $name<R> {
+            /// Creates a new service that sends its requests to the given `RSocket`.
+            pub fn new(rsocket: R) -> Self {
+                $name { rsocket }
+            }
+
+            /// Returns a reference to the underlying `RSocket`.
+            pub fn get_ref(&self) -> &R {
+                &self.rsocket
+            }
+
+            /// Consumes this service, returning the underlying `RSocket`.
+            pub fn into_inner(self) -> R {
+                self.rsocket
+            }
+        }
+
+        impl<R> fmt::Debug for $name<R> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($name)).finish()
+            }
+        }
+    };
+}
+
+rsocket_service! {
+    /// A [`Service`] that sends request-response requests, resolving to the response payload.
+    RequestResponseService
+}
+
+rsocket_service! {
+    /// A [`Service`] that sends request-stream requests, resolving to the stream of payloads
+    /// received.
+    ///
+    /// The future resolves as soon as the request is made, so timeouts only apply to making the
+    /// request, not to receiving the stream.
+    RequestStreamService
+}
+
+rsocket_service! {
+    /// A [`Service`] that sends request-channel requests, resolving to the stream of payloads
+    /// received.
+    ///
+    /// The future resolves as soon as the request is made, so timeouts only apply to making the
+    /// request, not to exchanging the payloads.
+    RequestChannelService
+}
+
+rsocket_service! {
+    /// A [`Service`] that sends fire-and-forget requests.
+    FireAndForgetService
+}
+
+rsocket_service! {
+    /// A [`Service`] that pushes metadata.
+    MetadataPushService
+}
+
+impl<R: RSocket> Service<Payload> for RequestResponseService<R> {
+    type Response = Payload;
+    type Error = Error;
+    type Future = Mono<Result<Payload>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, payload: Payload) -> Self::Future {
+        self.rsocket.request_response(payload)
+    }
+}
+
+impl<R: RSocket> Service<Payload> for RequestStreamService<R> {
+    type Response = Flux<Result<Payload>>;
+    type Error = Error;
+    type Future = Ready<Result<Flux<Result<Payload>>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, payload: Payload) -> Self::Future {
+        ready(Ok(self.rsocket.request_stream(payload)))
+    }
+}
+
+impl<R: RSocket> Service<Flux<Result<Payload>>> for RequestChannelService<R> {
+    type Response = Flux<Result<Payload>>;
+    type Error = Error;
+    type Future = Ready<Result<Flux<Result<Payload>>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, payloads: Flux<Result<Payload>>) -> Self::Future {
+        ready(Ok(self.rsocket.request_channel(payloads)))
+    }
+}
+
+impl<R: RSocket> Service<Payload> for FireAndForgetService<R> {
+    type Response = ();
+    type Error = Error;
+    type Future = Ready<Result<()>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, payload: Payload) -> Self::Future {
+        ready(self.rsocket.fire_and_forget(payload))
+    }
+}
+
+impl<R: RSocket> Service<Metadata> for MetadataPushService<R> {
+    type Response = ();
+    type Error = Error;
+    type Future = Mono<Result<()>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, metadata: Metadata) -> Self::Future {
+        self.rsocket.metadata_push(metadata)
+    }
+}
+
+/// A responder that handles request-response requests with a [`Service`].
+///
+/// The service is cloned for each request, which waits for the clone to be ready before it is
+/// called, so middleware that shares its state between clones (such as concurrency limits)
+/// applies to all requests. Errors returned by the service are sent to the requester as they
+/// are if they are [`Error`]s, or as `APPLICATION_ERROR`s otherwise.
+///
+/// The other interaction models are rejected.
+///
+/// # Examples
+///
+/// ```
+/// use binate::service::ServiceRSocket;
+/// use binate::Payload;
+/// use std::time::Duration;
+/// use tower::ServiceBuilder;
+///
+/// let responder = ServiceRSocket::new(
+///     ServiceBuilder::new()
+///         .concurrency_limit(64)
+///         .timeout(Duration::from_secs(5))
+///         .service_fn(|payload: Payload| async move {
+///             Ok::<_, binate::Error>(payload)
+///         }),
+/// );
+/// ```
+pub struct ServiceRSocket<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ServiceRSocket<S> {
+    /// Creates a new `ServiceRSocket` that handles request-response requests with the given
+    /// service.
+    pub fn new(service: S) -> ServiceRSocket<S> {
+        ServiceRSocket { service: Mutex::new(service) }
+    }
+
+    /// Consumes this `ServiceRSocket`, returning the underlying service.
+    pub fn into_inner(self) -> S {
+        self.service.into_inner().unwrap()
+    }
+
+    fn rejected() -> Error {
+        Error::with_code(Code::Rejected, "interaction is not supported")
+    }
+}
+
+impl<S> RSocket for ServiceRSocket<S>
+where
+    S: Service<Payload, Response = Payload> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        let mut service = self.service.lock().unwrap().clone();
+        Box::pin(async move {
+            poll_fn(|cx| service.poll_ready(cx)).await.map_err(into_error)?;
+            service.call(payload).await.map_err(into_error)
+        })
+    }
+
+    fn request_stream(&self, _payload: Payload) -> Flux<Result<Payload>> {
+        Box::pin(tokio_stream::once(Err(Self::rejected())))
+    }
+
+    fn request_channel(
+        &self,
+        _payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        Box::pin(tokio_stream::once(Err(Self::rejected())))
+    }
+
+    fn fire_and_forget(&self, _payload: Payload) -> Result<()> {
+        Err(Self::rejected())
+    }
+
+    fn metadata_push(&self, _metadata: Metadata) -> Mono<Result<()>> {
+        Box::pin(async { Err(Self::rejected()) })
+    }
+}
+
+impl<S> fmt::Debug for ServiceRSocket<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceRSocket").finish()
+    }
+}
+
+/// Converts the error of a service into an [`Error`], keeping it as it is if it is one already.
+///
+/// A request that times out in a tower `Timeout` is canceled, as with
+/// [`TimeoutLayer`](crate::layer::TimeoutLayer).
+fn into_error(err: impl Into<BoxError>) -> Error {
+    let err = match err.into().downcast::<Error>() {
+        Ok(err) => return *err,
+        Err(err) => err,
+    };
+    match err.downcast::<Elapsed>() {
+        Ok(_) => Error::with_code(Code::Canceled, "request timed out"),
+        Err(err) => Error::with_code(Code::ApplicationError, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use bytes::Bytes;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    fn payload(data: &'static str) -> Payload {
+        Payload::builder().set_data(data).build()
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<RequestResponseService<EchoRSocket>>();
+        assert_sync::<RequestResponseService<EchoRSocket>>();
+        assert_send::<ServiceRSocket<RequestResponseService<EchoRSocket>>>();
+        assert_sync::<ServiceRSocket<RequestResponseService<EchoRSocket>>>();
+    }
+
+    #[tokio::test]
+    async fn rsocket_services() {
+        let response = RequestResponseService::new(EchoRSocket)
+            .oneshot(payload("ping"))
+            .await
+            .unwrap();
+        assert_eq!(response, payload("ping"));
+
+        let stream = RequestStreamService::new(EchoRSocket)
+            .oneshot(payload("ping"))
+            .await
+            .unwrap();
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 1);
+
+        let payloads = Box::pin(tokio_stream::once(Ok(payload("ping"))));
+        let mut stream = RequestChannelService::new(EchoRSocket)
+            .oneshot(payloads)
+            .await
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), payload("ping"));
+
+        FireAndForgetService::new(EchoRSocket)
+            .oneshot(payload("ping"))
+            .await
+            .unwrap();
+        MetadataPushService::new(EchoRSocket)
+            .oneshot(Bytes::from("metadata"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn service_rsocket() {
+        let responder =
+            ServiceRSocket::new(RequestResponseService::new(EchoRSocket));
+        let response = responder.request_response(payload("ping")).await;
+        assert_eq!(response.unwrap(), payload("ping"));
+
+        let err = responder.fire_and_forget(payload("ping")).unwrap_err();
+        assert!(err.is_rejected());
+        let mut stream = responder.request_stream(payload("ping"));
+        assert!(stream.next().await.unwrap().unwrap_err().is_rejected());
+    }
+
+    #[tokio::test]
+    async fn service_rsocket_errors() {
+        tokio::time::pause();
+        let responder = ServiceRSocket::new(tower::service_fn(
+            |_payload: Payload| async {
+                Err::<Payload, _>(Error::with_code(Code::Invalid, "invalid"))
+            },
+        ));
+        let err = responder.request_response(payload("ping")).await;
+        assert!(err.unwrap_err().is_invalid());
+
+        let responder = ServiceRSocket::new(
+            ServiceBuilder::new()
+                .timeout(Duration::from_millis(10))
+                .service_fn(|payload: Payload| async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok::<_, Error>(payload)
+                }),
+        );
+        let err = responder.request_response(payload("ping")).await;
+        assert!(err.unwrap_err().is_cancel());
+
+        let responder = ServiceRSocket::new(tower::service_fn(
+            |_payload: Payload| async {
+                Err::<Payload, _>(std::io::Error::other("failed"))
+            },
+        ));
+        let err = responder.request_response(payload("ping")).await;
+        assert!(err.unwrap_err().is_application_error());
+    }
+
+    #[tokio::test]
+    async fn service_rsocket_concurrency_limit() {
+        tokio::time::pause();
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let rx = std::sync::Arc::new(tokio::sync::Mutex::new(Some(rx)));
+        let responder = ServiceRSocket::new(
+            ServiceBuilder::new().concurrency_limit(1).service_fn(
+                move |payload: Payload| {
+                    let rx = rx.clone();
+                    async move {
+                        if let Some(rx) = rx.lock().await.take() {
+                            let _ = rx.await;
+                        }
+                        Ok::<_, Error>(payload)
+                    }
+                },
+            ),
+        );
+
+        let first = tokio::spawn(responder.request_response(payload("1")));
+        tokio::task::yield_now().await;
+        let mut second = responder.request_response(payload("2"));
+        // The second request waits for the first one to finish.
+        let timeout =
+            tokio::time::timeout(Duration::from_millis(20), &mut second);
+        assert!(timeout.await.is_err());
+        tx.send(()).unwrap();
+        assert_eq!(first.await.unwrap().unwrap(), payload("1"));
+        assert_eq!(second.await.unwrap(), payload("2"));
+    }
+}
@@ -1,3 +1,41 @@
+#[cfg(test)]
+use crate::error::Result;
+#[cfg(test)]
+use crate::payload::{Metadata, Payload};
+#[cfg(test)]
+use crate::{Flux, Mono, RSocket};
+
 pub(crate) fn assert_send<T: Send>() {}
 
 pub(crate) fn assert_sync<T: Sync>() {}
+
+/// Responds to every request with its payload.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub(crate) struct EchoRSocket;
+
+#[cfg(test)]
+impl RSocket for EchoRSocket {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        Box::pin(async move { Ok(payload) })
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        Box::pin(tokio_stream::once(Ok(payload)))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        payloads
+    }
+
+    fn fire_and_forget(&self, _payload: Payload) -> Result<()> {
+        Ok(())
+    }
+
+    fn metadata_push(&self, _metadata: Metadata) -> Mono<Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
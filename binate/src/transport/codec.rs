@@ -0,0 +1,286 @@
+use crate::error::{Error, Result};
+use crate::frame::{Encode, Frame, U24};
+
+use bytes::{Buf, BytesMut};
+use std::io;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// The default maximum frame size, which is also the largest frame size a 24-bit length prefix
+/// can describe.
+const DEFAULT_MAX_FRAME_SIZE: usize = U24::MAX as usize;
+
+/// A codec for transports that preserve message boundaries, where each message carries exactly
+/// one frame without a length prefix (e.g. WebSocket).
+///
+/// The decoder treats all the bytes available as a single frame. Extension frames that are not
+/// understood but have the IGNORE flag set are skipped.
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_size: usize,
+}
+
+impl FrameCodec {
+    /// Creates a new `FrameCodec` with the default maximum frame size (16,777,215 bytes).
+    pub fn new() -> FrameCodec {
+        FrameCodec { max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+
+    /// Sets the maximum size (in bytes) of the frames that can be encoded or decoded.
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec::new()
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        check_frame_size(src.len(), self.max_frame_size)?;
+        let mut buf = src.split().freeze();
+        Ok(Frame::decode_or_skip(&mut buf)?)
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<()> {
+        check_frame_size(frame.len(), self.max_frame_size)?;
+        frame.encode(dst);
+        Ok(())
+    }
+}
+
+/// A codec for byte stream transports (e.g. TCP), where each frame is prefixed with its length
+/// encoded as a 24-bit unsigned integer.
+///
+/// The decoder buffers partial frames until enough bytes are read to decode a whole frame.
+/// Extension frames that are not understood but have the IGNORE flag set are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::transport::LengthPrefixedCodec;
+/// use tokio::net::TcpStream;
+/// use tokio_util::codec::Framed;
+///
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let stream = TcpStream::connect("127.0.0.1:7878").await?;
+/// let framed = Framed::new(stream, LengthPrefixedCodec::new());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LengthPrefixedCodec {
+    max_frame_size: usize,
+}
+
+impl LengthPrefixedCodec {
+    /// Creates a new `LengthPrefixedCodec` with the default maximum frame size (16,777,215
+    /// bytes).
+    pub fn new() -> LengthPrefixedCodec {
+        LengthPrefixedCodec { max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+
+    /// Sets the maximum size (in bytes) of the frames that can be encoded or decoded, excluding
+    /// the length prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value given is greater than `U24::MAX` (max value 16,777,215).
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        assert!(max_frame_size <= DEFAULT_MAX_FRAME_SIZE);
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Default for LengthPrefixedCodec {
+    fn default() -> Self {
+        LengthPrefixedCodec::new()
+    }
+}
+
+impl Decoder for LengthPrefixedCodec {
+    type Item = Frame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>> {
+        loop {
+            if src.len() < 3 {
+                return Ok(None);
+            }
+            let len = U24::new(src[0], u16::from_be_bytes([src[1], src[2]]))
+                .into_usize();
+            check_frame_size(len, self.max_frame_size)?;
+
+            if src.len() < 3 + len {
+                // Reserves enough space for the rest of this frame.
+                src.reserve(3 + len - src.len());
+                return Ok(None);
+            }
+            src.advance(3);
+            let mut buf = src.split_to(len).freeze();
+            if let Some(frame) = Frame::decode_or_skip(&mut buf)? {
+                return Ok(Some(frame));
+            }
+        }
+    }
+}
+
+impl Encoder<Frame> for LengthPrefixedCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<()> {
+        let len = frame.len();
+        check_frame_size(len, self.max_frame_size)?;
+        dst.reserve(3 + len);
+        U24::from_usize(len).encode(dst);
+        frame.encode(dst);
+        Ok(())
+    }
+}
+
+fn check_frame_size(len: usize, max_frame_size: usize) -> Result<()> {
+    if len > max_frame_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "frame size {} exceeds the maximum frame size {}",
+                len, max_frame_size
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::frame::{Flags, FrameType};
+    use crate::payload::Payload;
+    use bytes::BufMut;
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    fn request(stream_id: u32, data: &'static str) -> Frame {
+        Frame::RequestResponse(RequestResponseFrame::new(
+            stream_id,
+            false,
+            Payload::builder().set_data(data).build(),
+        ))
+    }
+
+    #[test]
+    fn length_prefixed_partial_frames() {
+        let mut codec = LengthPrefixedCodec::new();
+        let mut encoded = BytesMut::new();
+        codec.encode(request(1, "first"), &mut encoded).unwrap();
+        codec.encode(request(3, "second"), &mut encoded).unwrap();
+        assert_eq!(encoded.len(), 3 + 11 + 3 + 12);
+
+        // Feeds the bytes one by one.
+        let mut src = BytesMut::new();
+        let mut frames = Vec::new();
+        for byte in encoded.iter() {
+            src.extend_from_slice(&[*byte]);
+            if let Some(frame) = codec.decode(&mut src).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, vec![request(1, "first"), request(3, "second")]);
+        assert!(src.is_empty());
+    }
+
+    fn ext_frame(ignore: bool) -> BytesMut {
+        let flags = if ignore { Flags::IGNORE.bits() } else { 0 };
+        let mut buf = BytesMut::new();
+        buf.put_u32(1);
+        buf.put_u16(FrameType::EXT.bits() | flags);
+        buf.put_u32(0xABCD);
+        buf
+    }
+
+    #[test]
+    fn ext_frames() {
+        let mut codec = LengthPrefixedCodec::new();
+        let mut src = BytesMut::new();
+        for ignore in [true, false] {
+            let frame = ext_frame(ignore);
+            U24::from_usize(frame.len()).encode(&mut src);
+            src.extend_from_slice(&frame);
+            codec.encode(request(1, "data"), &mut src).unwrap();
+        }
+        // The ignorable frame is skipped, while the other one is an error rather than a panic.
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(request(1, "data")));
+        assert!(codec.decode(&mut src).unwrap_err().is_decode());
+
+        let mut codec = FrameCodec::new();
+        assert_eq!(codec.decode(&mut ext_frame(true)).unwrap(), None);
+        assert!(codec.decode(&mut ext_frame(false)).unwrap_err().is_decode());
+    }
+
+    #[test]
+    fn unprefixed() {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(request(1, "data"), &mut buf).unwrap();
+        assert_eq!(buf.len(), 10);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(request(1, "data")));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn max_frame_size() {
+        let mut buf = BytesMut::new();
+        let mut codec = LengthPrefixedCodec::new().max_frame_size(10);
+        assert!(codec.encode(request(1, "too large"), &mut buf).is_err());
+        assert!(buf.is_empty());
+
+        LengthPrefixedCodec::new()
+            .encode(request(1, "large"), &mut buf)
+            .unwrap();
+        // The frame is rejected as soon as its length prefix is read.
+        let mut prefix = buf.split_to(3);
+        assert!(codec.decode(&mut prefix).is_err());
+
+        let mut codec = FrameCodec::new().max_frame_size(10);
+        assert!(codec.encode(request(1, "too large"), &mut buf).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn max_frame_size_exceeds_u24() {
+        LengthPrefixedCodec::new().max_frame_size(U24::MAX as usize + 1);
+    }
+
+    #[tokio::test]
+    async fn framed() {
+        let (client, server) = tokio::io::duplex(8);
+        let mut writer = FramedWrite::new(client, LengthPrefixedCodec::new());
+        let mut reader = FramedRead::new(server, LengthPrefixedCodec::new());
+
+        tokio::spawn(async move {
+            use futures_util::SinkExt;
+            writer.send(request(1, "hello")).await.unwrap();
+            writer.send(request(3, "world")).await.unwrap();
+        });
+        assert_eq!(reader.next().await.unwrap().unwrap(), request(1, "hello"));
+        assert_eq!(reader.next().await.unwrap().unwrap(), request(3, "world"));
+        assert!(reader.next().await.is_none());
+    }
+}
@@ -0,0 +1,286 @@
+use super::codec::LengthPrefixedCodec;
+use super::status::Status;
+use crate::connection::{ConnectionStatus, DuplexConnection};
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::runtime;
+use crate::{Flux, Mono};
+
+use futures_util::{Sink, SinkExt};
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// A frame waiting to be written, along with an optional sender to notify once it is written.
+type Outbound = (Frame, Option<oneshot::Sender<Result<()>>>);
+
+/// A `DuplexConnection` over a byte stream, where each frame is prefixed with its length encoded
+/// as a 24-bit unsigned integer.
+///
+/// Frames are queued until the byte stream is attached via [`IoConnection::start`], so that
+/// requests can be issued while the underlying connection is still being established.
+pub(crate) struct IoConnection {
+    outbound: Arc<Mutex<Option<mpsc::UnboundedSender<Outbound>>>>,
+    outbound_rx: Mutex<Option<mpsc::UnboundedReceiver<Outbound>>>,
+    inbound: Mutex<Option<mpsc::UnboundedSender<Frame>>>,
+    inbound_rx: Mutex<Option<mpsc::UnboundedReceiver<Frame>>>,
+    status: Status,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl IoConnection {
+    /// Creates a new `IoConnection` that has no byte stream attached.
+    pub(crate) fn new() -> IoConnection {
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let (inbound, inbound_rx) = mpsc::unbounded_channel();
+        IoConnection {
+            outbound: Arc::new(Mutex::new(Some(outbound))),
+            outbound_rx: Mutex::new(Some(outbound_rx)),
+            inbound: Mutex::new(Some(inbound)),
+            inbound_rx: Mutex::new(Some(inbound_rx)),
+            status: Status::new(ConnectionStatus::Unconnected),
+            reader: Mutex::new(None),
+        }
+    }
+
+    /// Returns the current connection status.
+    pub(crate) fn status(&self) -> ConnectionStatus {
+        self.status.get()
+    }
+
+    /// Updates the connection status, unless the connection is already closed.
+    pub(crate) fn set_status(&self, status: ConnectionStatus) {
+        self.status.set(status);
+    }
+
+    /// Attaches the given byte stream and starts reading and writing frames on it, where each
+    /// frame is prefixed with its length encoded as a 24-bit unsigned integer.
+    ///
+    /// This does nothing if the connection has already been started or closed.
+    pub(crate) fn start<T>(&self, io: T)
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(io);
+        self.start_framed(
+            FramedRead::new(reader, LengthPrefixedCodec::new()),
+            FramedWrite::new(writer, LengthPrefixedCodec::new()),
+        );
+    }
+
+    /// Starts receiving frames from the given stream and sending frames into the given sink.
+    ///
+    /// This does nothing if the connection has already been started or closed.
+    pub(crate) fn start_framed<St, Si>(&self, stream: St, sink: Si)
+    where
+        St: Stream<Item = Result<Frame>> + Send + Unpin + 'static,
+        Si: Sink<Frame, Error = Error> + Send + Unpin + 'static,
+    {
+        let outbound_rx = self.outbound_rx.lock().unwrap().take();
+        let inbound = self.inbound.lock().unwrap().take();
+        let (outbound_rx, inbound) = match (outbound_rx, inbound) {
+            (Some(outbound_rx), Some(inbound)) => (outbound_rx, inbound),
+            _ => return,
+        };
+
+        *self.reader.lock().unwrap() = Some(runtime::spawn(read_frames(
+            stream,
+            inbound,
+            self.status.clone(),
+        )));
+        // The writer stops once all pending frames are written after the connection is closed.
+        runtime::spawn(write_frames(sink, outbound_rx, self.status.clone()));
+        self.status.set(ConnectionStatus::Connected);
+    }
+
+    fn shutdown(&self) {
+        self.outbound.lock().unwrap().take();
+        self.inbound.lock().unwrap().take();
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+    }
+}
+
+impl DuplexConnection for IoConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        let (tx, rx) = oneshot::channel();
+        let result = enqueue(&self.outbound, &self.status, (frame, Some(tx)));
+        Box::pin(async move {
+            result?;
+            rx.await.unwrap_or_else(|_| Err(not_connected()))
+        })
+    }
+
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        enqueue(&self.outbound, &self.status, (frame, None))
+    }
+
+    fn send_stream(&self, mut frames: Flux<Frame>) {
+        let outbound = self.outbound.clone();
+        let status = self.status.clone();
+        runtime::spawn(async move {
+            while let Some(frame) = frames.next().await {
+                if enqueue(&outbound, &status, (frame, None)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        match self.inbound_rx.lock().unwrap().take() {
+            Some(rx) => Box::pin(UnboundedReceiverStream::new(rx)),
+            None => Box::pin(tokio_stream::empty()),
+        }
+    }
+
+    fn connect(&self) {}
+
+    fn close(&self) {
+        // The status is closed first, so that the exiting tasks do not report an error.
+        self.status.set(ConnectionStatus::Closed);
+        self.shutdown();
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.status.stream()
+    }
+}
+
+impl Drop for IoConnection {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+async fn read_frames<St>(
+    mut stream: St,
+    inbound: mpsc::UnboundedSender<Frame>,
+    status: Status,
+) where
+    St: Stream<Item = Result<Frame>> + Unpin,
+{
+    let _guard = ExitGuard::new(&status, "connection reader stopped");
+    while let Some(frame) = stream.next().await {
+        match frame {
+            Ok(frame) => {
+                if inbound.send(frame).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                status.set(ConnectionStatus::Error(e.to_string()));
+                return;
+            }
+        }
+    }
+    status
+        .set(ConnectionStatus::Error("connection closed by peer".to_owned()));
+}
+
+async fn write_frames<Si>(
+    mut sink: Si,
+    mut outbound: mpsc::UnboundedReceiver<Outbound>,
+    status: Status,
+) where
+    Si: Sink<Frame, Error = Error> + Unpin,
+{
+    let _guard = ExitGuard::new(&status, "connection writer stopped");
+    while let Some((frame, notify)) = outbound.recv().await {
+        let result = sink.send(frame).await;
+        let error = result.as_ref().err().map(ToString::to_string);
+        if let Some(notify) = notify {
+            let _ = notify.send(result);
+        }
+        if let Some(error) = error {
+            status.set(ConnectionStatus::Error(error));
+            return;
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// Sets the connection status to an error once a task of the connection exits, even if it
+/// panics, so that callers waiting for the connection do not hang.
+///
+/// This does nothing if the task has already set the status, or the connection is closed.
+struct ExitGuard {
+    status: Status,
+    reason: &'static str,
+}
+
+impl ExitGuard {
+    fn new(status: &Status, reason: &'static str) -> ExitGuard {
+        ExitGuard { status: status.clone(), reason }
+    }
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        self.status.set(ConnectionStatus::Error(self.reason.to_owned()));
+    }
+}
+
+fn enqueue(
+    outbound: &Mutex<Option<mpsc::UnboundedSender<Outbound>>>,
+    status: &Status,
+    item: Outbound,
+) -> Result<()> {
+    if status.is_closed() {
+        return Err(not_connected());
+    }
+    match &*outbound.lock().unwrap() {
+        Some(tx) => tx.send(item).map_err(|_| not_connected()),
+        None => Err(not_connected()),
+    }
+}
+
+pub(crate) fn not_connected() -> crate::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "connection is closed").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::sink;
+
+    async fn wait_for_error(conn: &IoConnection) -> ConnectionStatus {
+        let mut statuses = conn.connection_status();
+        while let Some(status) = statuses.next().await {
+            if let ConnectionStatus::Error(_) = status {
+                return status;
+            }
+        }
+        conn.status()
+    }
+
+    #[tokio::test]
+    async fn panicking_reader() {
+        let conn = IoConnection::new();
+        let frames = tokio_stream::iter(0..1).map(|_| -> Result<Frame> {
+            panic!("reader panicked");
+        });
+        let sink = sink::drain().sink_map_err(|never| match never {});
+        conn.start_framed(frames, sink);
+        assert_eq!(
+            wait_for_error(&conn).await,
+            ConnectionStatus::Error("connection reader stopped".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_without_error() {
+        let conn = IoConnection::new();
+        let sink = sink::drain().sink_map_err(|never| match never {});
+        conn.start_framed(tokio_stream::pending(), sink);
+        conn.close();
+        tokio::task::yield_now().await;
+        assert_eq!(conn.status(), ConnectionStatus::Closed);
+    }
+}
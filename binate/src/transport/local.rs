@@ -0,0 +1,232 @@
+use super::io::not_connected;
+use super::status::Status;
+use crate::connection::{ConnectionStatus, DuplexConnection};
+use crate::error::Result;
+use crate::frame::{Encode, Frame};
+use crate::runtime;
+use crate::{Flux, Mono};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+
+type Sender = Arc<Mutex<Option<mpsc::UnboundedSender<Frame>>>>;
+
+/// An in-process `DuplexConnection`, connected to its peer by channels.
+///
+/// Frames are passed to the peer as they are, unless the pair is created by
+/// [`LocalConnection::pair_with_codec`].
+///
+/// # Examples
+///
+/// ```
+/// use binate::connection::DuplexConnection;
+/// use binate::transport::LocalConnection;
+/// use tokio_stream::StreamExt;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let (client, server) = LocalConnection::pair();
+/// client.close();
+/// assert!(server.receive().next().await.is_none());
+/// # }
+/// ```
+pub struct LocalConnection {
+    outbound: Sender,
+    // The sending half of the peer, which is dropped on `close()` so that both ends of the
+    // connection stop receiving frames.
+    inbound: Sender,
+    inbound_rx: Mutex<Option<mpsc::UnboundedReceiver<Frame>>>,
+    status: Status,
+    peer_status: Status,
+    codec: bool,
+}
+
+impl LocalConnection {
+    /// Creates a pair of connected `LocalConnection`s.
+    pub fn pair() -> (LocalConnection, LocalConnection) {
+        LocalConnection::new_pair(false)
+    }
+
+    /// Creates a pair of connected `LocalConnection`s, where every frame is encoded into bytes
+    /// and decoded back before it is passed to the peer.
+    ///
+    /// This is useful for exercising the frame codec without binding sockets.
+    pub fn pair_with_codec() -> (LocalConnection, LocalConnection) {
+        LocalConnection::new_pair(true)
+    }
+
+    fn new_pair(codec: bool) -> (LocalConnection, LocalConnection) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        let a_tx = Arc::new(Mutex::new(Some(a_tx)));
+        let b_tx = Arc::new(Mutex::new(Some(b_tx)));
+        let a_status = Status::new(ConnectionStatus::Connected);
+        let b_status = Status::new(ConnectionStatus::Connected);
+
+        let a = LocalConnection {
+            outbound: a_tx.clone(),
+            inbound: b_tx.clone(),
+            inbound_rx: Mutex::new(Some(b_rx)),
+            status: a_status.clone(),
+            peer_status: b_status.clone(),
+            codec,
+        };
+        let b = LocalConnection {
+            outbound: b_tx,
+            inbound: a_tx,
+            inbound_rx: Mutex::new(Some(a_rx)),
+            status: b_status,
+            peer_status: a_status,
+            codec,
+        };
+        (a, b)
+    }
+}
+
+impl DuplexConnection for LocalConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        let result = self.send_and_forget(frame);
+        Box::pin(async move { result })
+    }
+
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        send(&self.outbound, &self.status, self.codec, frame)
+    }
+
+    fn send_stream(&self, mut frames: Flux<Frame>) {
+        let outbound = self.outbound.clone();
+        let status = self.status.clone();
+        let codec = self.codec;
+        runtime::spawn(async move {
+            while let Some(frame) = frames.next().await {
+                if send(&outbound, &status, codec, frame).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        match self.inbound_rx.lock().unwrap().take() {
+            Some(rx) => Box::pin(UnboundedReceiverStream::new(rx)),
+            None => Box::pin(tokio_stream::empty()),
+        }
+    }
+
+    fn connect(&self) {}
+
+    fn close(&self) {
+        self.outbound.lock().unwrap().take();
+        self.inbound.lock().unwrap().take();
+        self.status.set(ConnectionStatus::Closed);
+        self.peer_status.set(ConnectionStatus::Error(
+            "connection closed by peer".to_owned(),
+        ));
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.status.stream()
+    }
+}
+
+impl fmt::Debug for LocalConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalConnection")
+            .field("status", &self.status.get())
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+fn send(
+    outbound: &Mutex<Option<mpsc::UnboundedSender<Frame>>>,
+    status: &Status,
+    codec: bool,
+    frame: Frame,
+) -> Result<()> {
+    if status.is_closed() {
+        return Err(not_connected());
+    }
+    let frame =
+        if codec { Frame::decode(&mut frame.to_bytes())? } else { frame };
+    match &*outbound.lock().unwrap() {
+        Some(tx) => tx.send(frame).map_err(|_| not_connected()),
+        None => Err(not_connected()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::frame::Flags;
+    use crate::payload::Payload;
+    use crate::test_helpers::*;
+
+    fn next(stream_id: u32, data: &'static str) -> Frame {
+        Frame::Payload(PayloadFrame::new(
+            stream_id,
+            Flags::NEXT,
+            Payload::builder().set_metadata("m").set_data(data).build(),
+        ))
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<LocalConnection>();
+        assert_sync::<LocalConnection>();
+    }
+
+    #[tokio::test]
+    async fn send_and_receive() {
+        for (client, server) in
+            [LocalConnection::pair(), LocalConnection::pair_with_codec()]
+        {
+            assert_eq!(
+                client.connection_status().next().await,
+                Some(ConnectionStatus::Connected)
+            );
+
+            client.send_and_forget(next(1, "a")).unwrap();
+            client.send(next(1, "b")).await.unwrap();
+            let mut frames = server.receive();
+            assert_eq!(frames.next().await.unwrap(), next(1, "a"));
+            assert_eq!(frames.next().await.unwrap(), next(1, "b"));
+
+            server.send_stream(Box::pin(tokio_stream::iter(vec![
+                next(2, "c"),
+                next(2, "d"),
+            ])));
+            let mut frames = client.receive();
+            assert_eq!(frames.next().await.unwrap(), next(2, "c"));
+            assert_eq!(frames.next().await.unwrap(), next(2, "d"));
+        }
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let (client, server) = LocalConnection::pair();
+        let mut client_frames = client.receive();
+        let mut server_frames = server.receive();
+        client.send_and_forget(next(1, "last")).unwrap();
+        client.close();
+
+        assert_eq!(
+            client.connection_status().next().await,
+            Some(ConnectionStatus::Closed)
+        );
+        assert!(matches!(
+            server.connection_status().next().await,
+            Some(ConnectionStatus::Error(_))
+        ));
+        assert!(client.send_and_forget(next(1, "closed")).is_err());
+        assert!(server.send_and_forget(next(2, "closed")).is_err());
+
+        assert_eq!(server_frames.next().await.unwrap(), next(1, "last"));
+        assert!(server_frames.next().await.is_none());
+        assert!(client_frames.next().await.is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Transport implementations of [`DuplexConnection`].
+//!
+//! [`DuplexConnection`]: crate::connection::DuplexConnection
+mod codec;
+mod io;
+mod local;
+mod status;
+mod tcp;
+
+pub use self::codec::{FrameCodec, LengthPrefixedCodec};
+pub use self::local::LocalConnection;
+pub use self::tcp::{TcpAcceptor, TcpConnection};
+
+pub(crate) use self::io::not_connected;
+pub(crate) use self::status::Status;
+
+use crate::consts::ACCEPT_ERROR_BACKOFF;
+
+use std::io::{Error as IoError, ErrorKind};
+use tracing::error;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use self::unix::{UnixAcceptor, UnixConnection};
+
+cfg_doc! {
+    #[feature = "websocket"]
+    mod websocket;
+    pub use self::websocket::{WebSocketAcceptor, WebSocketConnection};
+}
+
+/// Logs the given error from accepting a connection, and waits before accepting again unless
+/// the error only concerns the connection that was being accepted.
+///
+/// Errors such as running out of file descriptors persist until other connections are closed,
+/// so accepting again right away would only spin and flood the log.
+pub(crate) async fn accept_error(err: IoError) {
+    error!("failed to accept connection: {}", err);
+    if !is_connection_error(&err) {
+        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+    }
+}
+
+/// Returns true if the given error from accepting a connection only concerns that connection.
+fn is_connection_error(err: &IoError) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_errors() {
+        let err = |kind| IoError::new(kind, "accept failed");
+        assert!(is_connection_error(&err(ErrorKind::ConnectionAborted)));
+        assert!(is_connection_error(&err(ErrorKind::ConnectionReset)));
+        // Running out of file descriptors (EMFILE) persists.
+        assert!(!is_connection_error(&IoError::from_raw_os_error(24)));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::connection::ConnectionStatus;
+use crate::Flux;
+
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+/// The connection status shared between a transport and its background tasks.
+#[derive(Clone)]
+pub(crate) struct Status {
+    tx: Arc<Mutex<watch::Sender<ConnectionStatus>>>,
+    rx: watch::Receiver<ConnectionStatus>,
+}
+
+impl Status {
+    pub(crate) fn new(status: ConnectionStatus) -> Status {
+        let (tx, rx) = watch::channel(status);
+        Status { tx: Arc::new(Mutex::new(tx)), rx }
+    }
+
+    pub(crate) fn get(&self) -> ConnectionStatus {
+        self.rx.borrow().clone()
+    }
+
+    /// Returns true if the connection has been closed, either by `close()` or by an error.
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(
+            *self.rx.borrow(),
+            ConnectionStatus::Closed | ConnectionStatus::Error(_)
+        )
+    }
+
+    /// Updates the status, unless the connection has already been closed.
+    pub(crate) fn set(&self, status: ConnectionStatus) {
+        let tx = self.tx.lock().unwrap();
+        // A closed connection can never be reopened.
+        if !self.is_closed() && *self.rx.borrow() != status {
+            let _ = tx.send(status);
+        }
+    }
+
+    /// Returns a stream that publishes the current status and thereafter updates as it changes.
+    pub(crate) fn stream(&self) -> Flux<ConnectionStatus> {
+        Box::pin(WatchStream::new(self.rx.clone()))
+    }
+}
@@ -0,0 +1,379 @@
+use super::accept_error;
+use super::io::IoConnection;
+use crate::connection::{
+    ConnectionAcceptor, ConnectionStatus, DuplexConnection,
+};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::runtime;
+use crate::{Flux, Mono};
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// A `DuplexConnection` over TCP.
+///
+/// Frames are prefixed with their length encoded as a 24-bit unsigned integer, as required by
+/// the RSocket TCP transport binding.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::connection::DuplexConnection;
+/// use binate::transport::TcpConnection;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let conn = TcpConnection::new("127.0.0.1:7878".parse().unwrap());
+/// conn.connect();
+/// # }
+/// ```
+pub struct TcpConnection {
+    addr: SocketAddr,
+    inner: Arc<IoConnection>,
+}
+
+impl TcpConnection {
+    /// Creates a new `TcpConnection` to the given address.
+    ///
+    /// The connection is not established until `connect()` is called, but frames can be sent
+    /// right away and are delivered once the connection is established.
+    pub fn new(addr: SocketAddr) -> TcpConnection {
+        TcpConnection { addr, inner: Arc::new(IoConnection::new()) }
+    }
+
+    /// Returns the address of the remote peer.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn accepted(stream: TcpStream, addr: SocketAddr) -> TcpConnection {
+        let conn = TcpConnection::new(addr);
+        if let Err(e) = stream.set_nodelay(true) {
+            error!("failed to set TCP_NODELAY: {}", e);
+        }
+        conn.inner.start(stream);
+        conn
+    }
+}
+
+impl DuplexConnection for TcpConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        self.inner.send(frame)
+    }
+
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        self.inner.send_and_forget(frame)
+    }
+
+    fn send_stream(&self, frames: Flux<Frame>) {
+        self.inner.send_stream(frames)
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        self.inner.receive()
+    }
+
+    fn connect(&self) {
+        if self.inner.status() != ConnectionStatus::Unconnected {
+            return;
+        }
+        self.inner.set_status(ConnectionStatus::Connecting);
+
+        let addr = self.addr;
+        let inner = self.inner.clone();
+        runtime::spawn(async move {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    if let Err(e) = stream.set_nodelay(true) {
+                        error!("failed to set TCP_NODELAY: {}", e);
+                    }
+                    inner.start(stream);
+                }
+                Err(e) => {
+                    inner.set_status(ConnectionStatus::Error(e.to_string()))
+                }
+            }
+        });
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.inner.connection_status()
+    }
+}
+
+impl fmt::Debug for TcpConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpConnection")
+            .field("addr", &self.addr)
+            .field("status", &self.inner.status())
+            .finish()
+    }
+}
+
+/// A TCP server that accepts connections and turns them into [`TcpConnection`]s.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::transport::TcpAcceptor;
+///
+/// # #[tokio::main]
+/// # async fn main() -> binate::Result<()> {
+/// let acceptor = TcpAcceptor::bind("127.0.0.1:7878".parse().unwrap()).await?;
+/// loop {
+///     let conn = acceptor.accept().await?;
+///     println!("accepted connection from {}", conn.peer_addr());
+/// }
+/// # }
+/// ```
+pub struct TcpAcceptor {
+    listener: Arc<TcpListener>,
+    local_addr: SocketAddr,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl TcpAcceptor {
+    /// Creates a new `TcpAcceptor` bound to the given address.
+    ///
+    /// Binding with a port number of 0 will request that the OS assigns a port to this acceptor.
+    /// The port allocated can be queried via [`local_addr`](TcpAcceptor::local_addr).
+    pub async fn bind(addr: SocketAddr) -> Result<TcpAcceptor> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        Ok(TcpAcceptor {
+            listener: Arc::new(listener),
+            local_addr,
+            task: Mutex::new(None),
+        })
+    }
+
+    /// Returns the local address that this acceptor is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Accepts a new incoming connection.
+    pub async fn accept(&self) -> Result<TcpConnection> {
+        let (stream, addr) = self.listener.accept().await?;
+        Ok(TcpConnection::accepted(stream, addr))
+    }
+}
+
+impl ConnectionAcceptor for TcpAcceptor {
+    type Connection = TcpConnection;
+
+    fn start<F>(&self, mut on_accept: F)
+    where
+        F: FnMut(TcpConnection) + Send + 'static,
+    {
+        let mut task = self.task.lock().unwrap();
+        if task.is_some() {
+            return;
+        }
+
+        let listener = self.listener.clone();
+        *task = Some(runtime::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, addr)) => {
+                        on_accept(TcpConnection::accepted(stream, addr))
+                    }
+                    Err(e) => accept_error(e).await,
+                }
+            }
+        }));
+    }
+
+    fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap().as_ref() {
+            task.abort();
+        }
+    }
+
+    fn listening_port(&self) -> usize {
+        self.local_addr.port() as usize
+    }
+}
+
+impl Drop for TcpAcceptor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl fmt::Debug for TcpAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpAcceptor")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::payload::Payload;
+    use crate::test_helpers::*;
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc;
+    use tokio_stream::StreamExt;
+
+    fn request(stream_id: u32, data: &'static str) -> Frame {
+        Frame::RequestResponse(RequestResponseFrame::new(
+            stream_id,
+            false,
+            Payload::builder().set_data(data).build(),
+        ))
+    }
+
+    async fn acceptor() -> TcpAcceptor {
+        TcpAcceptor::bind("127.0.0.1:0".parse().unwrap()).await.unwrap()
+    }
+
+    async fn wait_for(conn: &impl DuplexConnection, status: ConnectionStatus) {
+        let mut statuses = conn.connection_status();
+        while let Some(s) = statuses.next().await {
+            if s == status {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<TcpConnection>();
+        assert_sync::<TcpConnection>();
+        assert_send::<TcpAcceptor>();
+        assert_sync::<TcpAcceptor>();
+    }
+
+    #[tokio::test]
+    async fn send_and_receive() {
+        let acceptor = acceptor().await;
+        let client = TcpConnection::new(acceptor.local_addr());
+        assert_eq!(
+            client.connection_status().next().await,
+            Some(ConnectionStatus::Unconnected)
+        );
+
+        // Frames sent before the connection is established are queued.
+        client.send_and_forget(request(1, "queued")).unwrap();
+        client.connect();
+        let server = acceptor.accept().await.unwrap();
+        wait_for(&client, ConnectionStatus::Connected).await;
+        client.send(request(3, "sent")).await.unwrap();
+
+        let mut frames = server.receive();
+        assert_eq!(frames.next().await.unwrap(), request(1, "queued"));
+        assert_eq!(frames.next().await.unwrap(), request(3, "sent"));
+
+        server.send_stream(Box::pin(tokio_stream::iter(vec![
+            request(2, "a"),
+            request(4, "b"),
+        ])));
+        let mut frames = client.receive();
+        assert_eq!(frames.next().await.unwrap(), request(2, "a"));
+        assert_eq!(frames.next().await.unwrap(), request(4, "b"));
+    }
+
+    #[tokio::test]
+    async fn length_prefixed_framing() {
+        let acceptor = acceptor().await;
+        let mut peer =
+            TcpStream::connect(acceptor.local_addr()).await.unwrap();
+        let server = acceptor.accept().await.unwrap();
+
+        let frame = Frame::MetadataPush(MetadataPushFrame::new(Bytes::from(
+            "metadata",
+        )));
+        server.send(frame.clone()).await.unwrap();
+
+        let mut buf = [0; 3 + 6 + 8];
+        peer.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[..3], [0, 0, 14]);
+        assert_eq!(Frame::decode(&mut &buf[3..]).unwrap(), frame);
+
+        peer.write_all(&buf).await.unwrap();
+        assert_eq!(server.receive().next().await.unwrap(), frame);
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let acceptor = acceptor().await;
+        let client = TcpConnection::new(acceptor.local_addr());
+        client.connect();
+        let server = acceptor.accept().await.unwrap();
+        wait_for(&client, ConnectionStatus::Connected).await;
+
+        client.send_and_forget(request(1, "last")).unwrap();
+        client.close();
+        assert_eq!(
+            client.connection_status().next().await,
+            Some(ConnectionStatus::Closed)
+        );
+        assert!(client.send_and_forget(request(3, "closed")).is_err());
+        assert!(client.send(request(3, "closed")).await.is_err());
+        assert!(client.receive().next().await.is_none());
+
+        // Pending frames are still flushed before the connection shuts down.
+        let mut frames = server.receive();
+        assert_eq!(frames.next().await.unwrap(), request(1, "last"));
+        assert!(frames.next().await.is_none());
+        assert!(matches!(
+            server.connection_status().next().await,
+            Some(ConnectionStatus::Error(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_error() {
+        let addr = acceptor().await.local_addr();
+        // The acceptor is dropped, so nothing listens on this port anymore.
+        let client = TcpConnection::new(addr);
+        client.connect();
+        let mut statuses = client.connection_status();
+        while let Some(status) = statuses.next().await {
+            if let ConnectionStatus::Error(_) = status {
+                return;
+            }
+        }
+        panic!("connection should have failed");
+    }
+
+    #[tokio::test]
+    async fn acceptor_start() {
+        let acceptor = acceptor().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        acceptor.start(move |conn| {
+            let _ = tx.send(conn);
+        });
+        assert_eq!(
+            acceptor.listening_port(),
+            acceptor.local_addr().port() as usize
+        );
+
+        for stream_id in &[1, 3] {
+            let client = TcpConnection::new(acceptor.local_addr());
+            client.connect();
+            client.send_and_forget(request(*stream_id, "hello")).unwrap();
+            let server = rx.recv().await.unwrap();
+            assert_eq!(
+                server.receive().next().await.unwrap(),
+                request(*stream_id, "hello")
+            );
+        }
+
+        acceptor.stop();
+    }
+}
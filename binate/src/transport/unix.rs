@@ -0,0 +1,306 @@
+use super::accept_error;
+use super::io::IoConnection;
+use crate::connection::{
+    ConnectionAcceptor, ConnectionStatus, DuplexConnection,
+};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::runtime;
+use crate::{Flux, Mono};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+/// A `DuplexConnection` over a Unix domain socket.
+///
+/// Frames are prefixed with their length encoded as a 24-bit unsigned integer, the same as the
+/// TCP transport.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::connection::DuplexConnection;
+/// use binate::transport::UnixConnection;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let conn = UnixConnection::new("/tmp/rsocket.sock");
+/// conn.connect();
+/// # }
+/// ```
+pub struct UnixConnection {
+    path: PathBuf,
+    inner: Arc<IoConnection>,
+}
+
+impl UnixConnection {
+    /// Creates a new `UnixConnection` to the socket at the given path.
+    ///
+    /// The connection is not established until `connect()` is called, but frames can be sent
+    /// right away and are delivered once the connection is established.
+    pub fn new<P: AsRef<Path>>(path: P) -> UnixConnection {
+        UnixConnection {
+            path: path.as_ref().to_owned(),
+            inner: Arc::new(IoConnection::new()),
+        }
+    }
+
+    /// Returns the path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn accepted(stream: UnixStream, path: PathBuf) -> UnixConnection {
+        let conn =
+            UnixConnection { path, inner: Arc::new(IoConnection::new()) };
+        conn.inner.start(stream);
+        conn
+    }
+}
+
+impl DuplexConnection for UnixConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        self.inner.send(frame)
+    }
+
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        self.inner.send_and_forget(frame)
+    }
+
+    fn send_stream(&self, frames: Flux<Frame>) {
+        self.inner.send_stream(frames)
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        self.inner.receive()
+    }
+
+    fn connect(&self) {
+        if self.inner.status() != ConnectionStatus::Unconnected {
+            return;
+        }
+        self.inner.set_status(ConnectionStatus::Connecting);
+
+        let path = self.path.clone();
+        let inner = self.inner.clone();
+        runtime::spawn(async move {
+            match UnixStream::connect(path).await {
+                Ok(stream) => inner.start(stream),
+                Err(e) => {
+                    inner.set_status(ConnectionStatus::Error(e.to_string()))
+                }
+            }
+        });
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.inner.connection_status()
+    }
+}
+
+impl fmt::Debug for UnixConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnixConnection")
+            .field("path", &self.path)
+            .field("status", &self.inner.status())
+            .finish()
+    }
+}
+
+/// A Unix domain socket server that accepts connections and turns them into
+/// [`UnixConnection`]s.
+///
+/// The socket file is removed when the acceptor is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::transport::UnixAcceptor;
+///
+/// # #[tokio::main]
+/// # async fn main() -> binate::Result<()> {
+/// let acceptor = UnixAcceptor::bind("/tmp/rsocket.sock")?;
+/// loop {
+///     let conn = acceptor.accept().await?;
+/// }
+/// # }
+/// ```
+pub struct UnixAcceptor {
+    listener: Arc<UnixListener>,
+    path: PathBuf,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl UnixAcceptor {
+    /// Creates a new `UnixAcceptor` bound to the socket at the given path.
+    ///
+    /// This fails if a file already exists at the given path.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<UnixAcceptor> {
+        let path = path.as_ref().to_owned();
+        let listener = UnixListener::bind(&path)?;
+        Ok(UnixAcceptor {
+            listener: Arc::new(listener),
+            path,
+            task: Mutex::new(None),
+        })
+    }
+
+    /// Returns the path of the socket this acceptor is bound to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts a new incoming connection.
+    pub async fn accept(&self) -> Result<UnixConnection> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(UnixConnection::accepted(stream, self.path.clone()))
+    }
+}
+
+impl ConnectionAcceptor for UnixAcceptor {
+    type Connection = UnixConnection;
+
+    fn start<F>(&self, mut on_accept: F)
+    where
+        F: FnMut(UnixConnection) + Send + 'static,
+    {
+        let mut task = self.task.lock().unwrap();
+        if task.is_some() {
+            return;
+        }
+
+        let listener = self.listener.clone();
+        let path = self.path.clone();
+        *task = Some(runtime::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => on_accept(UnixConnection::accepted(
+                        stream,
+                        path.clone(),
+                    )),
+                    Err(e) => accept_error(e).await,
+                }
+            }
+        }));
+    }
+
+    fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap().as_ref() {
+            task.abort();
+        }
+    }
+
+    /// Always returns 0, as Unix domain sockets are not bound to ports.
+    fn listening_port(&self) -> usize {
+        0
+    }
+}
+
+impl Drop for UnixAcceptor {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl fmt::Debug for UnixAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnixAcceptor").field("path", &self.path).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::payload::Payload;
+    use crate::test_helpers::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+    use tokio_stream::StreamExt;
+
+    fn request(stream_id: u32, data: &'static str) -> Frame {
+        Frame::RequestResponse(RequestResponseFrame::new(
+            stream_id,
+            false,
+            Payload::builder().set_data(data).build(),
+        ))
+    }
+
+    fn acceptor() -> UnixAcceptor {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "binate-{}-{}.sock",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        UnixAcceptor::bind(path).unwrap()
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<UnixConnection>();
+        assert_sync::<UnixConnection>();
+        assert_send::<UnixAcceptor>();
+        assert_sync::<UnixAcceptor>();
+    }
+
+    #[tokio::test]
+    async fn send_and_receive() {
+        let acceptor = acceptor();
+        let client = UnixConnection::new(acceptor.path());
+
+        client.send_and_forget(request(1, "queued")).unwrap();
+        client.connect();
+        let server = acceptor.accept().await.unwrap();
+        client.send(request(3, "sent")).await.unwrap();
+
+        let mut frames = server.receive();
+        assert_eq!(frames.next().await.unwrap(), request(1, "queued"));
+        assert_eq!(frames.next().await.unwrap(), request(3, "sent"));
+
+        server.send(request(2, "reply")).await.unwrap();
+        assert_eq!(
+            client.receive().next().await.unwrap(),
+            request(2, "reply")
+        );
+
+        client.close();
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn acceptor_start() {
+        let acceptor = acceptor();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        acceptor.start(move |conn| {
+            let _ = tx.send(conn);
+        });
+        assert_eq!(acceptor.listening_port(), 0);
+
+        let client = UnixConnection::new(acceptor.path());
+        client.connect();
+        client.send_and_forget(request(1, "hello")).unwrap();
+        let server = rx.recv().await.unwrap();
+        assert_eq!(
+            server.receive().next().await.unwrap(),
+            request(1, "hello")
+        );
+    }
+
+    #[tokio::test]
+    async fn socket_file_removed_on_drop() {
+        let acceptor = acceptor();
+        let path = acceptor.path().to_owned();
+        assert!(path.exists());
+        drop(acceptor);
+        assert!(!path.exists());
+    }
+}
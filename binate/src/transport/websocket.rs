@@ -0,0 +1,429 @@
+use super::accept_error;
+use super::io::IoConnection;
+use crate::connection::{
+    ConnectionAcceptor, ConnectionStatus, DuplexConnection,
+};
+use crate::error::{Error, Kind, Result};
+use crate::frame::{Encode, Frame};
+use crate::runtime;
+use crate::{Flux, Mono};
+
+use futures_util::{future, Sink, SinkExt, StreamExt};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+use tracing::error;
+
+/// A `DuplexConnection` over WebSocket.
+///
+/// Each frame is carried by exactly one binary message, without a length prefix.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::connection::DuplexConnection;
+/// use binate::transport::WebSocketConnection;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let conn = WebSocketConnection::new("ws://127.0.0.1:7878");
+/// conn.connect();
+/// # }
+/// ```
+pub struct WebSocketConnection {
+    url: String,
+    inner: Arc<IoConnection>,
+}
+
+impl WebSocketConnection {
+    /// Creates a new `WebSocketConnection` to the given URL.
+    ///
+    /// The connection is not established until `connect()` is called, but frames can be sent
+    /// right away and are delivered once the connection is established.
+    pub fn new<T: Into<String>>(url: T) -> WebSocketConnection {
+        WebSocketConnection {
+            url: url.into(),
+            inner: Arc::new(IoConnection::new()),
+        }
+    }
+
+    /// Returns the URL of the remote peer.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn accepted<S>(ws: WebSocketStream<S>, url: String) -> WebSocketConnection
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let conn = WebSocketConnection {
+            url,
+            inner: Arc::new(IoConnection::new()),
+        };
+        start(&conn.inner, ws);
+        conn
+    }
+}
+
+impl DuplexConnection for WebSocketConnection {
+    fn send(&self, frame: Frame) -> Mono<Result<()>> {
+        self.inner.send(frame)
+    }
+
+    fn send_and_forget(&self, frame: Frame) -> Result<()> {
+        self.inner.send_and_forget(frame)
+    }
+
+    fn send_stream(&self, frames: Flux<Frame>) {
+        self.inner.send_stream(frames)
+    }
+
+    fn receive(&self) -> Flux<Frame> {
+        self.inner.receive()
+    }
+
+    fn connect(&self) {
+        if self.inner.status() != ConnectionStatus::Unconnected {
+            return;
+        }
+        self.inner.set_status(ConnectionStatus::Connecting);
+
+        let url = self.url.clone();
+        let inner = self.inner.clone();
+        runtime::spawn(async move {
+            match tokio_tungstenite::connect_async(url).await {
+                Ok((ws, _)) => start(&inner, ws),
+                Err(e) => {
+                    inner.set_status(ConnectionStatus::Error(e.to_string()))
+                }
+            }
+        });
+    }
+
+    fn close(&self) {
+        self.inner.close()
+    }
+
+    fn connection_status(&self) -> Flux<ConnectionStatus> {
+        self.inner.connection_status()
+    }
+}
+
+impl fmt::Debug for WebSocketConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketConnection")
+            .field("url", &self.url)
+            .field("status", &self.inner.status())
+            .finish()
+    }
+}
+
+/// A WebSocket server that accepts connections and turns them into [`WebSocketConnection`]s.
+///
+/// # Examples
+///
+/// ```no_run
+/// use binate::transport::WebSocketAcceptor;
+///
+/// # #[tokio::main]
+/// # async fn main() -> binate::Result<()> {
+/// let acceptor =
+///     WebSocketAcceptor::bind("127.0.0.1:7878".parse().unwrap()).await?;
+/// loop {
+///     let conn = acceptor.accept().await?;
+///     println!("accepted connection from {}", conn.url());
+/// }
+/// # }
+/// ```
+pub struct WebSocketAcceptor {
+    listener: Arc<TcpListener>,
+    local_addr: SocketAddr,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebSocketAcceptor {
+    /// Creates a new `WebSocketAcceptor` bound to the given address.
+    ///
+    /// Binding with a port number of 0 will request that the OS assigns a port to this acceptor.
+    /// The port allocated can be queried via [`local_addr`](WebSocketAcceptor::local_addr).
+    pub async fn bind(addr: SocketAddr) -> Result<WebSocketAcceptor> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        Ok(WebSocketAcceptor {
+            listener: Arc::new(listener),
+            local_addr,
+            task: Mutex::new(None),
+        })
+    }
+
+    /// Returns the local address that this acceptor is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Accepts a new incoming connection, completing the WebSocket handshake.
+    pub async fn accept(&self) -> Result<WebSocketConnection> {
+        let (stream, addr) = self.listener.accept().await?;
+        let ws =
+            tokio_tungstenite::accept_async(stream).await.map_err(ws_error)?;
+        Ok(WebSocketConnection::accepted(ws, format!("ws://{}", addr)))
+    }
+}
+
+impl ConnectionAcceptor for WebSocketAcceptor {
+    type Connection = WebSocketConnection;
+
+    fn start<F>(&self, on_accept: F)
+    where
+        F: FnMut(WebSocketConnection) + Send + 'static,
+    {
+        let mut task = self.task.lock().unwrap();
+        if task.is_some() {
+            return;
+        }
+
+        let listener = self.listener.clone();
+        let on_accept = Arc::new(Mutex::new(on_accept));
+        *task = Some(runtime::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        accept_error(e).await;
+                        continue;
+                    }
+                };
+                // Handshakes are done in separate tasks, so that a slow client does not block
+                // others from being accepted.
+                let on_accept = on_accept.clone();
+                runtime::spawn(async move {
+                    match tokio_tungstenite::accept_async(stream).await {
+                        Ok(ws) => {
+                            let url = format!("ws://{}", addr);
+                            let conn = WebSocketConnection::accepted(ws, url);
+                            (on_accept.lock().unwrap())(conn);
+                        }
+                        Err(e) => error!("WebSocket handshake failed: {}", e),
+                    }
+                });
+            }
+        }));
+    }
+
+    fn stop(&self) {
+        if let Some(task) = self.task.lock().unwrap().as_ref() {
+            task.abort();
+        }
+    }
+
+    fn listening_port(&self) -> usize {
+        self.local_addr.port() as usize
+    }
+}
+
+impl Drop for WebSocketAcceptor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl fmt::Debug for WebSocketAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketAcceptor")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+fn start<S>(conn: &IoConnection, ws: WebSocketStream<S>)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (sink, stream) = ws.split();
+    conn.start_framed(Box::pin(frames(stream)), Box::pin(messages(sink)));
+}
+
+/// Decodes the binary messages received into frames, ignoring control messages and extension
+/// frames that have the IGNORE flag set.
+fn frames<St>(stream: St) -> impl Stream<Item = Result<Frame>>
+where
+    St: Stream<Item = tungstenite::Result<Message>>,
+{
+    stream
+        .take_while(|msg| future::ready(!matches!(msg, Ok(Message::Close(_)))))
+        .filter_map(|msg| {
+            future::ready(match msg {
+                Ok(Message::Binary(buf)) => Frame::decode_or_skip(&mut &buf[..])
+                    .map_err(Into::into)
+                    .transpose(),
+                Ok(Message::Text(_)) => Some(Err(Error::new(
+                    Kind::ConnectionError,
+                    Some("unexpected text message"),
+                ))),
+                Ok(_) => None,
+                Err(e) => Some(Err(ws_error(e))),
+            })
+        })
+}
+
+/// Encodes frames into binary messages.
+fn messages<Si>(sink: Si) -> impl Sink<Frame, Error = Error>
+where
+    Si: Sink<Message, Error = tungstenite::Error>,
+{
+    sink.sink_map_err(ws_error).with(|frame: Frame| {
+        future::ok::<_, Error>(Message::Binary(frame.to_bytes().to_vec()))
+    })
+}
+
+fn ws_error(e: tungstenite::Error) -> Error {
+    Error::new(Kind::Io, Some(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::codec::*;
+    use crate::frame::{Flags, FrameType};
+    use crate::payload::Payload;
+    use crate::test_helpers::*;
+    use tokio::sync::mpsc;
+
+    fn request(stream_id: u32, data: &'static str) -> Frame {
+        Frame::RequestResponse(RequestResponseFrame::new(
+            stream_id,
+            false,
+            Payload::builder().set_data(data).build(),
+        ))
+    }
+
+    async fn acceptor() -> WebSocketAcceptor {
+        WebSocketAcceptor::bind("127.0.0.1:0".parse().unwrap()).await.unwrap()
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<WebSocketConnection>();
+        assert_sync::<WebSocketConnection>();
+        assert_send::<WebSocketAcceptor>();
+        assert_sync::<WebSocketAcceptor>();
+    }
+
+    #[tokio::test]
+    async fn send_and_receive() {
+        let acceptor = acceptor().await;
+        let url = format!("ws://{}", acceptor.local_addr());
+        let client = WebSocketConnection::new(url);
+
+        client.send_and_forget(request(1, "queued")).unwrap();
+        client.connect();
+        let server = acceptor.accept().await.unwrap();
+        client.send(request(3, "sent")).await.unwrap();
+
+        let mut frames = server.receive();
+        assert_eq!(frames.next().await.unwrap(), request(1, "queued"));
+        assert_eq!(frames.next().await.unwrap(), request(3, "sent"));
+
+        server.send_stream(Box::pin(tokio_stream::iter(vec![
+            request(2, "a"),
+            request(4, "b"),
+        ])));
+        let mut frames = client.receive();
+        assert_eq!(frames.next().await.unwrap(), request(2, "a"));
+        assert_eq!(frames.next().await.unwrap(), request(4, "b"));
+    }
+
+    #[tokio::test]
+    async fn one_frame_per_message() {
+        let acceptor = acceptor().await;
+        let url = format!("ws://{}", acceptor.local_addr());
+        let (peer, server) = tokio::join!(
+            async { tokio_tungstenite::connect_async(url).await.unwrap().0 },
+            async { acceptor.accept().await.unwrap() }
+        );
+        let (mut sink, mut stream) = peer.split();
+
+        server.send(request(2, "ping")).await.unwrap();
+        let msg = stream.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Binary(request(2, "ping").to_bytes().to_vec()));
+
+        sink.send(msg).await.unwrap();
+        assert_eq!(server.receive().next().await.unwrap(), request(2, "ping"));
+    }
+
+    #[tokio::test]
+    async fn ext_frames() {
+        let acceptor = acceptor().await;
+        let url = format!("ws://{}", acceptor.local_addr());
+        let (peer, server) = tokio::join!(
+            async { tokio_tungstenite::connect_async(url).await.unwrap().0 },
+            async { acceptor.accept().await.unwrap() }
+        );
+        let (mut sink, _stream) = peer.split();
+        let ext_frame = |flags: Flags| {
+            let mut buf = Vec::new();
+            buf.extend_from_slice(&1u32.to_be_bytes());
+            buf.extend_from_slice(&(FrameType::EXT.bits() | flags.bits()).to_be_bytes());
+            buf.extend_from_slice(&0xABCDu32.to_be_bytes());
+            Message::Binary(buf)
+        };
+
+        let mut statuses = server.connection_status();
+        let mut frames = server.receive();
+        sink.send(ext_frame(Flags::IGNORE)).await.unwrap();
+        sink.send(Message::Binary(request(1, "after").to_bytes().to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(frames.next().await.unwrap(), request(1, "after"));
+
+        // An EXT frame that cannot be ignored fails the connection.
+        sink.send(ext_frame(Flags::empty())).await.unwrap();
+        assert!(frames.next().await.is_none());
+        while let Some(status) = statuses.next().await {
+            if let ConnectionStatus::Error(_) = status {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn close() {
+        let acceptor = acceptor().await;
+        let url = format!("ws://{}", acceptor.local_addr());
+        let client = WebSocketConnection::new(url);
+        client.connect();
+        let server = acceptor.accept().await.unwrap();
+
+        client.send(request(1, "last")).await.unwrap();
+        client.close();
+        assert!(client.send_and_forget(request(3, "closed")).is_err());
+
+        let mut frames = server.receive();
+        assert_eq!(frames.next().await.unwrap(), request(1, "last"));
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn acceptor_start() {
+        let acceptor = acceptor().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        acceptor.start(move |conn| {
+            let _ = tx.send(conn);
+        });
+
+        let client =
+            WebSocketConnection::new(format!("ws://{}", acceptor.local_addr()));
+        client.connect();
+        client.send_and_forget(request(1, "hello")).unwrap();
+        let server = rx.recv().await.unwrap();
+        assert_eq!(server.receive().next().await.unwrap(), request(1, "hello"));
+
+        acceptor.stop();
+    }
+}
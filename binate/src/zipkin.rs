@@ -0,0 +1,606 @@
+//! Propagation of Zipkin traces across connections.
+//!
+//! A [`ZipkinRequester`] wraps a requester, so that every request it sends carries the context
+//! of a new span in its [Zipkin tracing metadata](ZipkinMetadata). The new span is a child of
+//! the current Zipkin span if there is one, and otherwise starts a new trace.
+//!
+//! A [`ZipkinLayer`] wraps a responder, so that every stream it handles is a child span of the
+//! span in the tracing metadata of its request. The responses of the stream are produced in a
+//! `tracing` span that records the identifiers of the Zipkin span, which is the current Zipkin
+//! span meanwhile (see [`current_span`]). Requests sent by a `ZipkinRequester` while a stream
+//! is handled thus continue its trace, across as many hops as there are.
+//!
+//! [`ClientBuilder::set_zipkin_tracing`](crate::ClientBuilder::set_zipkin_tracing) and
+//! [`ServerBuilder::set_zipkin_tracing`](crate::ServerBuilder::set_zipkin_tracing) install
+//! both on a connection: the requests of the client, or the requesters given to the acceptor of
+//! the server, are sent by a `ZipkinRequester`, and the responders are wrapped in a
+//! `ZipkinLayer`.
+//!
+//! The current Zipkin span is kept in a thread-local, which is only set while the futures and
+//! streams of a stream are polled. It is not carried over to tasks started with
+//! `tokio::spawn`, so requests sent from such tasks start new traces.
+//!
+//! The tracing metadata is an entry of the composite metadata of the requests, so both ends of
+//! a connection must use `message/x.rsocket.composite-metadata.v0` as metadata MIME type.
+//!
+//! # Examples
+//!
+//! ```
+//! use binate::{Client, Server};
+//!
+//! let server = Server::builder().set_zipkin_tracing();
+//! let client = Client::builder()
+//!     .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0")
+//!     .set_zipkin_tracing();
+//! ```
+//!
+//! The requester and the layer can be installed separately as well:
+//!
+//! ```
+//! use binate::zipkin::{ZipkinLayer, ZipkinRequester};
+//! use binate::{Mono, RSocket, Result, Server, SetupFrame};
+//!
+//! fn acceptor(
+//!     _setup: &SetupFrame,
+//!     requester: Box<dyn RSocket>,
+//! ) -> Mono<Result<Box<dyn RSocket>>> {
+//!     // The requests sent back to the client are traced as well.
+//!     let requester = ZipkinRequester::new(requester);
+//!     Box::pin(async move { Ok(Box::new(requester) as Box<dyn RSocket>) })
+//! }
+//!
+//! let builder = Server::builder()
+//!     .set_acceptor(acceptor)
+//!     .add_layer(ZipkinLayer::new());
+//! ```
+use crate::error::Result;
+use crate::extension::{
+    metadata_entry, CompositeMetadata, Sampling, TraceId, ZipkinMetadata,
+};
+use crate::layer::RSocketLayer;
+use crate::mimetype::WellKnownMimeType;
+use crate::payload::{Metadata, Payload};
+use crate::rsocket::with_first_payload;
+use crate::{Flux, Mono, RSocket};
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, info_span, Span};
+
+const TRACING_ZIPKIN: WellKnownMimeType =
+    WellKnownMimeType::MESSAGE_X_RSOCKET_TRACING_ZIPKIN_V0;
+
+thread_local! {
+    /// The Zipkin span of the stream being handled on this thread, if any.
+    static CURRENT: Cell<Option<ZipkinMetadata>> = const { Cell::new(None) };
+}
+
+/// Returns the context of the current Zipkin span, if any.
+///
+/// There is a current span while a [`ZipkinLayer`] handles a stream, or while the response to
+/// a request of a [`ZipkinRequester`] is awaited.
+pub fn current_span() -> Option<ZipkinMetadata> {
+    CURRENT.with(Cell::get)
+}
+
+/// Returns a new random identifier, which is never 0.
+fn new_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+/// Returns the context of a new child span of the given one, or of a new trace if the given one
+/// carries no identifiers.
+fn child_span(parent: Option<ZipkinMetadata>) -> ZipkinMetadata {
+    let sampling = parent.map_or(Sampling::Unspecified, |p| p.sampling());
+    let parent_ids = parent.and_then(|p| Some((p.trace_id()?, p.span_id()?)));
+    match parent_ids {
+        Some((trace_id, parent_id)) => ZipkinMetadata::new(trace_id, new_id())
+            .set_parent_id(parent_id)
+            .set_sampling(sampling),
+        None => ZipkinMetadata::new(TraceId::Bits64(new_id()), new_id())
+            .set_sampling(sampling),
+    }
+}
+
+/// Formats a span identifier as 16 lower-hex digits.
+struct Hex(u64);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// A Zipkin span, along with the `tracing` span that records it.
+#[derive(Clone)]
+struct Scope {
+    span: Span,
+    context: ZipkinMetadata,
+}
+
+impl Scope {
+    /// Creates the scope of a span on the given side (`requester` or `responder`) of a request.
+    fn new(
+        side: &'static str,
+        interaction: &'static str,
+        context: ZipkinMetadata,
+    ) -> Scope {
+        let span = info_span!(
+            "zipkin",
+            side,
+            interaction,
+            trace_id = %context.trace_id().unwrap(),
+            span_id = %Hex(context.span_id().unwrap()),
+            parent_id = tracing::field::Empty,
+        );
+        if let Some(parent_id) = context.parent_id() {
+            span.record("parent_id", tracing::field::display(Hex(parent_id)));
+        }
+        Scope { span, context }
+    }
+
+    /// Runs the given function in this scope.
+    fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _entered = self.span.enter();
+        let previous =
+            CURRENT.with(|current| current.replace(Some(self.context)));
+        let _restore = Restore(previous);
+        f()
+    }
+
+    /// Wraps the given future or stream, so that it is polled in this scope.
+    fn wrap<T>(self, inner: T) -> Traced<T> {
+        Traced { inner, scope: self }
+    }
+}
+
+/// Restores the previous current span when dropped.
+struct Restore(Option<ZipkinMetadata>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+    }
+}
+
+/// A future or stream that is polled in a scope.
+struct Traced<T> {
+    inner: T,
+    scope: Scope,
+}
+
+impl<T> Future for Traced<T>
+where
+    T: Future + Unpin,
+{
+    type Output = T::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        this.scope.run(|| Pin::new(inner).poll(cx))
+    }
+}
+
+impl<T> Stream for Traced<T>
+where
+    T: Stream + Unpin,
+{
+    type Item = T::Item;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        this.scope.run(|| Pin::new(inner).poll_next(cx))
+    }
+}
+
+/// A layer that continues the Zipkin traces of the requests that a responder receives.
+///
+/// Each request-response, request-stream, request-channel and fire-and-forget request is
+/// handled in a child span of the span in its tracing metadata, or in a new trace if it has
+/// none. Malformed tracing metadata is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct ZipkinLayer {
+    _priv: (),
+}
+
+impl ZipkinLayer {
+    /// Creates a new `ZipkinLayer`.
+    pub fn new() -> ZipkinLayer {
+        ZipkinLayer { _priv: () }
+    }
+}
+
+impl RSocketLayer for ZipkinLayer {
+    fn layer(&self, responder: Box<dyn RSocket>) -> Box<dyn RSocket> {
+        Box::new(ZipkinResponder { inner: Arc::from(responder) })
+    }
+}
+
+struct ZipkinResponder {
+    inner: Arc<dyn RSocket>,
+}
+
+/// Returns the scope that handles the given request.
+fn handle(interaction: &'static str, request: &Payload) -> Scope {
+    let entry = metadata_entry(
+        WellKnownMimeType::MESSAGE_X_RSOCKET_COMPOSITE_METADATA_V0,
+        request.metadata(),
+        TRACING_ZIPKIN,
+    );
+    let parent = match entry.and_then(|entry| {
+        entry.as_ref().map(ZipkinMetadata::decode).transpose()
+    }) {
+        Ok(parent) => parent,
+        Err(err) => {
+            debug!(error = %err, "ignored malformed tracing metadata");
+            None
+        }
+    };
+    Scope::new("responder", interaction, child_span(parent))
+}
+
+impl RSocket for ZipkinResponder {
+    fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+        let scope = handle("request_response", &payload);
+        let response = scope.run(|| self.inner.request_response(payload));
+        Box::pin(scope.wrap(response))
+    }
+
+    fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+        let scope = handle("request_stream", &payload);
+        let payloads = scope.run(|| self.inner.request_stream(payload));
+        Box::pin(scope.wrap(payloads))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        // The tracing metadata is in the first payload.
+        let inner = self.inner.clone();
+        with_first_payload(payloads, move |first, payloads| {
+            let scope = handle("request_channel", first);
+            let payloads = scope.run(|| inner.request_channel(payloads));
+            Box::pin(scope.wrap(payloads))
+        })
+    }
+
+    fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+        let scope = handle("fire_and_forget", &payload);
+        scope.run(|| self.inner.fire_and_forget(payload))
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        self.inner.metadata_push(metadata)
+    }
+}
+
+/// A requester that propagates the current Zipkin span in the tracing metadata of its requests.
+///
+/// Each request-response, request-stream, request-channel and fire-and-forget request is sent
+/// in a child span of the current span, or in a new trace if there is no current span, and
+/// carries the context of that span in its composite metadata, in place of any tracing metadata
+/// that it already carries (e.g. when it is forwarded). Requests whose metadata is not composite
+/// are sent as they are.
+pub struct ZipkinRequester {
+    inner: Box<dyn RSocket>,
+}
+
+impl ZipkinRequester {
+    /// Creates a new `ZipkinRequester` that sends its requests with the given requester.
+    pub fn new(requester: Box<dyn RSocket>) -> ZipkinRequester {
+        ZipkinRequester { inner: requester }
+    }
+
+    /// Returns the scope of a new request.
+    fn request(interaction: &'static str) -> Scope {
+        Scope::new("requester", interaction, child_span(current_span()))
+    }
+}
+
+/// Adds the given span to the composite metadata of the given payload, in place of any span
+/// that it already carries.
+fn inject(payload: &mut Payload, context: &ZipkinMetadata) {
+    let metadata = match &payload.metadata {
+        Some(metadata) => match CompositeMetadata::decode(metadata) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        },
+        None => CompositeMetadata::new(),
+    };
+    let mut metadata: CompositeMetadata = metadata
+        .into_iter()
+        .filter(|entry| *entry.mime_type() != TRACING_ZIPKIN.into())
+        .collect();
+    metadata.push(TRACING_ZIPKIN, context.to_bytes());
+    payload.metadata = Some(metadata.to_bytes());
+}
+
+impl RSocket for ZipkinRequester {
+    fn request_response(&self, mut payload: Payload) -> Mono<Result<Payload>> {
+        let scope = ZipkinRequester::request("request_response");
+        inject(&mut payload, &scope.context);
+        let response = scope.run(|| self.inner.request_response(payload));
+        Box::pin(scope.wrap(response))
+    }
+
+    fn request_stream(&self, mut payload: Payload) -> Flux<Result<Payload>> {
+        let scope = ZipkinRequester::request("request_stream");
+        inject(&mut payload, &scope.context);
+        let payloads = scope.run(|| self.inner.request_stream(payload));
+        Box::pin(scope.wrap(payloads))
+    }
+
+    fn request_channel(
+        &self,
+        payloads: Flux<Result<Payload>>,
+    ) -> Flux<Result<Payload>> {
+        let scope = ZipkinRequester::request("request_channel");
+        let mut context = Some(scope.context);
+        let payloads = payloads.map(move |item| {
+            item.map(|mut payload| {
+                if let Some(context) = context.take() {
+                    inject(&mut payload, &context);
+                }
+                payload
+            })
+        });
+        let payloads =
+            scope.run(|| self.inner.request_channel(Box::pin(payloads)));
+        Box::pin(scope.wrap(payloads))
+    }
+
+    fn fire_and_forget(&self, mut payload: Payload) -> Result<()> {
+        let scope = ZipkinRequester::request("fire_and_forget");
+        inject(&mut payload, &scope.context);
+        scope.run(|| self.inner.fire_and_forget(payload))
+    }
+
+    fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+        self.inner.metadata_push(metadata)
+    }
+}
+
+impl fmt::Debug for ZipkinRequester {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZipkinRequester").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use crate::transport::LocalConnection;
+    use crate::{Client, Server, SetupFrame};
+    use std::sync::Mutex;
+
+    /// The tracing metadata of a request, and the current span when it was handled.
+    type Recorded = (Option<ZipkinMetadata>, Option<ZipkinMetadata>);
+
+    /// Records the tracing metadata of the requests, and the current span when they are
+    /// handled.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        requests: Arc<Mutex<Vec<Recorded>>>,
+    }
+
+    impl Recorder {
+        fn record(&self, payload: &Payload) {
+            let metadata = payload.metadata().map(|metadata| {
+                let metadata = CompositeMetadata::decode(metadata).unwrap();
+                ZipkinMetadata::decode(metadata.get(TRACING_ZIPKIN).unwrap())
+                    .unwrap()
+            });
+            self.requests.lock().unwrap().push((metadata, current_span()));
+        }
+
+        fn take(
+            &self,
+        ) -> Vec<(Option<ZipkinMetadata>, Option<ZipkinMetadata>)> {
+            std::mem::take(&mut *self.requests.lock().unwrap())
+        }
+    }
+
+    impl RSocket for Recorder {
+        fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+            self.record(&payload);
+            Box::pin(async move { Ok(payload) })
+        }
+
+        fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+            self.record(&payload);
+            Box::pin(tokio_stream::once(Ok(payload)))
+        }
+
+        fn request_channel(
+            &self,
+            payloads: Flux<Result<Payload>>,
+        ) -> Flux<Result<Payload>> {
+            let recorder = self.clone();
+            Box::pin(payloads.map(move |payload| {
+                recorder.record(payload.as_ref().unwrap());
+                payload
+            }))
+        }
+
+        fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+            self.record(&payload);
+            Ok(())
+        }
+
+        fn metadata_push(&self, _metadata: Metadata) -> Mono<Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Forwards the request-response requests, and records the current span meanwhile.
+    struct Forwarder {
+        requester: ZipkinRequester,
+        handled: Arc<Mutex<Option<ZipkinMetadata>>>,
+    }
+
+    impl RSocket for Forwarder {
+        fn request_response(&self, payload: Payload) -> Mono<Result<Payload>> {
+            *self.handled.lock().unwrap() = current_span();
+            self.requester.request_response(payload)
+        }
+
+        fn request_stream(&self, payload: Payload) -> Flux<Result<Payload>> {
+            self.requester.request_stream(payload)
+        }
+
+        fn request_channel(
+            &self,
+            payloads: Flux<Result<Payload>>,
+        ) -> Flux<Result<Payload>> {
+            self.requester.request_channel(payloads)
+        }
+
+        fn fire_and_forget(&self, payload: Payload) -> Result<()> {
+            self.requester.fire_and_forget(payload)
+        }
+
+        fn metadata_push(&self, metadata: Metadata) -> Mono<Result<()>> {
+            self.requester.metadata_push(metadata)
+        }
+    }
+
+    #[test]
+    fn assert_send_sync() {
+        assert_send::<ZipkinRequester>();
+        assert_sync::<ZipkinRequester>();
+    }
+
+    #[test]
+    fn child_spans() {
+        let root = child_span(None);
+        assert!(root.parent_id().is_none());
+        assert_eq!(root.sampling(), Sampling::Unspecified);
+
+        let parent = root.set_sampling(Sampling::Sampled);
+        let child = child_span(Some(parent));
+        assert_eq!(child.trace_id(), parent.trace_id());
+        assert_eq!(child.parent_id(), parent.span_id());
+        assert_ne!(child.span_id(), parent.span_id());
+        assert_eq!(child.sampling(), Sampling::Sampled);
+
+        let child =
+            child_span(Some(ZipkinMetadata::sampling_only(Sampling::Debug)));
+        assert!(child.parent_id().is_none());
+        assert_eq!(child.sampling(), Sampling::Debug);
+    }
+
+    #[tokio::test]
+    async fn propagation() {
+        let recorder = Recorder::default();
+        let requester = ZipkinRequester::new(Box::new(recorder.clone()));
+        let payload = Payload::builder().set_data("ping").build();
+        requester.request_response(payload.clone()).await.unwrap();
+
+        // The requester starts a new trace, whose span is current while awaiting the response.
+        let (sent, current) = recorder.take().pop().unwrap();
+        let sent = sent.unwrap();
+        assert!(sent.parent_id().is_none());
+        assert_eq!(current, Some(sent));
+        assert!(current_span().is_none());
+
+        // The responder handles the request in a child span, and the requests that it sends
+        // meanwhile continue the trace.
+        let downstream = Recorder::default();
+        let forwarder = Forwarder {
+            requester: ZipkinRequester::new(Box::new(downstream.clone())),
+            handled: Arc::default(),
+        };
+        let handled = forwarder.handled.clone();
+        let responder = ZipkinLayer::new().layer(Box::new(forwarder));
+        let requester = ZipkinRequester::new(responder);
+        requester.request_response(payload).await.unwrap();
+
+        let handled = handled.lock().unwrap().unwrap();
+        let (sent, _) = downstream.take().pop().unwrap();
+        let sent = sent.unwrap();
+        assert!(handled.parent_id().is_some());
+        assert_eq!(sent.trace_id(), handled.trace_id());
+        assert_eq!(sent.parent_id(), handled.span_id());
+    }
+
+    #[tokio::test]
+    async fn channel() {
+        let recorder = Recorder::default();
+        let responder = ZipkinLayer::new().layer(Box::new(recorder.clone()));
+        let requester = ZipkinRequester::new(responder);
+        let payloads = vec![
+            Ok(Payload::builder().set_data("1").build()),
+            Ok(Payload::builder().set_data("2").build()),
+        ];
+        let payloads =
+            requester.request_channel(Box::pin(tokio_stream::iter(payloads)));
+        assert_eq!(payloads.collect::<Vec<_>>().await.len(), 2);
+
+        // Only the first payload carries the tracing metadata, and both are handled in the
+        // span of the channel.
+        let requests = recorder.take();
+        let sent = requests[0].0.unwrap();
+        let handled = requests[0].1.unwrap();
+        assert_eq!(handled.parent_id(), sent.span_id());
+        assert!(requests[1].0.is_none());
+        assert_eq!(requests[1].1, Some(handled));
+    }
+
+    #[tokio::test]
+    async fn builders() {
+        let (client_conn, server_conn) = LocalConnection::pair_with_codec();
+        let recorder = Recorder::default();
+        let responder = recorder.clone();
+        let builder = Server::builder()
+            .set_acceptor(move |_: &SetupFrame, _: Box<dyn RSocket>| {
+                let responder =
+                    Box::new(responder.clone()) as Box<dyn RSocket>;
+                Box::pin(async move { Ok(responder) })
+                    as Mono<Result<Box<dyn RSocket>>>
+            })
+            .set_zipkin_tracing();
+        tokio::spawn(
+            async move { builder.serve_connection(server_conn).await },
+        );
+
+        let client = Client::builder()
+            .set_metadata_mimetype("message/x.rsocket.composite-metadata.v0")
+            .set_zipkin_tracing()
+            .connect(client_conn)
+            .await
+            .unwrap();
+        let payload = Payload::builder().set_data("ping").build();
+        client.request_response(payload).await.unwrap();
+
+        // The client starts a new trace, which the server continues.
+        let (sent, handled) = recorder.take().pop().unwrap();
+        let sent = sent.unwrap();
+        let handled = handled.unwrap();
+        assert!(sent.parent_id().is_none());
+        assert_eq!(handled.trace_id(), sent.trace_id());
+        assert_eq!(handled.parent_id(), sent.span_id());
+    }
+}